@@ -0,0 +1,138 @@
+use bendy_tracer::color::LinearRgb;
+use bendy_tracer::scene::{Data, MassivePoint, Material, Object, Scene, Sphere};
+use bendy_tracer::tracer::{Bvh, Clip, Geodesic, Ray, SegmentCursor, Spacetime, StepControl};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use glam::Vec3A;
+use rand::prelude::*;
+
+const SPHERES: usize = 2000;
+const PATHS: usize = 256;
+const MAX_STEPS: usize = 1024;
+
+/// A black hole surrounded by a cloud of small spheres.
+fn lensing_scene(rng: &mut impl Rng) -> Scene {
+    let mut scene = Scene::default();
+    let material = scene.add_data(Data::new(Material::diffuse(LinearRgb::splat(0.5), 1.0)));
+
+    scene.add_object(Object::new(MassivePoint::new(1.0)));
+    for _ in 0..SPHERES {
+        let direction = Vec3A::new(
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+        )
+        .normalize_or_zero();
+        let position = direction * rng.gen_range(10.0..60.0);
+        let sphere = Sphere::new(material, rng.gen_range(0.05..0.3));
+        scene.add_object(Object::new(sphere).with_translation(position));
+    }
+
+    scene
+}
+
+/// Bent paths from an observer outside the cloud, as lists of points.
+fn paths(spacetime: &Spacetime, rng: &mut impl Rng) -> Vec<Vec<Vec3A>> {
+    let control = StepControl {
+        tolerance: 1e-4,
+        min_step: 1e-4,
+        max_step: 1.0,
+    };
+
+    (0..PATHS)
+        .filter_map(|_| {
+            let target = Vec3A::new(rng.gen_range(-8.0..8.0), rng.gen_range(-8.0..8.0), 0.0);
+            let origin = Vec3A::new(0.0, 0.0, 80.0);
+            let mut geodesic = Geodesic::new(spacetime, &Ray::new(origin, target - origin))?;
+
+            let mut points = vec![geodesic.position];
+            let mut step = 0.05;
+            for _ in 0..MAX_STEPS {
+                step = geodesic.step_adaptive(spacetime, step, &control).next;
+                if spacetime.is_inside_horizon(geodesic.position) {
+                    break;
+                }
+                points.push(geodesic.position);
+            }
+            Some(points)
+        })
+        .collect()
+}
+
+fn segment_traversal(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let scene = lensing_scene(&mut rng);
+    let spacetime = Spacetime::from_scene(&scene);
+    let bvh = Bvh::from_scene(&scene);
+    let paths = paths(&spacetime, &mut rng);
+
+    let mut group = c.benchmark_group("segments");
+
+    group.bench_function("brute_force", |b| {
+        b.iter(|| {
+            for path in &paths {
+                for segment in path.windows(2) {
+                    let ray = Ray::new(segment[0], segment[1] - segment[0]);
+                    let mut clip = Clip {
+                        min: 0.0,
+                        max: segment[0].distance(segment[1]),
+                    };
+                    let mut result = None;
+                    for object in scene.iter() {
+                        if let Some(manifold) = object.hit(&ray, &clip, &scene) {
+                            clip.max = manifold.t;
+                            result = Some(manifold);
+                        }
+                    }
+                    if black_box(result).is_some() {
+                        break;
+                    }
+                }
+            }
+        })
+    });
+
+    group.bench_function("bvh", |b| {
+        b.iter(|| {
+            for path in &paths {
+                for segment in path.windows(2) {
+                    let ray = Ray::new(segment[0], segment[1] - segment[0]);
+                    let clip = Clip {
+                        min: 0.0,
+                        max: segment[0].distance(segment[1]),
+                    };
+                    let result = bvh.hit(&ray, &clip, |object_ref, clip| {
+                        scene.get_object(object_ref).hit(&ray, clip, &scene)
+                    });
+                    if black_box(result).is_some() {
+                        break;
+                    }
+                }
+            }
+        })
+    });
+
+    group.bench_function("bvh_segments", |b| {
+        let mut cursor = SegmentCursor::new();
+        b.iter(|| {
+            for path in &paths {
+                cursor.reset();
+                for segment in path.windows(2) {
+                    let result = bvh.hit_segment(
+                        &mut cursor,
+                        segment[0],
+                        segment[1],
+                        |object_ref, ray, clip| scene.get_object(object_ref).hit(ray, clip, &scene),
+                    );
+                    if black_box(result).is_some() {
+                        break;
+                    }
+                }
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, segment_traversal);
+criterion_main!(benches);
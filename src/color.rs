@@ -1,6 +1,8 @@
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
 
-use glam::Vec3;
+use std::sync::OnceLock;
+
+use glam::{Mat3, Vec3};
 use serde::{Deserialize, Serialize};
 
 fn srgb_to_linear(x: f32) -> f32 {
@@ -19,6 +21,163 @@ fn linear_to_srgb(x: f32) -> f32 {
     }
 }
 
+pub const WAVELENGTH_MIN: f32 = 380.0;
+pub const WAVELENGTH_MAX: f32 = 780.0;
+
+const WAVELENGTH_STEP: f32 = 5.0;
+
+// luminance of a 6504K blackbody as integrated by `blackbody_xyz`
+const D65_LUMINANCE: f32 = 37.876_675;
+
+fn gaussian(x: f32, mean: f32, sigma_lo: f32, sigma_hi: f32) -> f32 {
+    let sigma = if x < mean { sigma_lo } else { sigma_hi };
+    let t = (x - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// Analytic fit of the CIE 1931 2° color matching functions by Wyman, Sloan
+/// and Shirley; `wavelength` is in nanometres.
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+    let l = wavelength;
+    let x = 1.056 * gaussian(l, 599.8, 37.9, 31.0) + 0.362 * gaussian(l, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(l, 501.1, 20.4, 26.2);
+    let y = 0.821 * gaussian(l, 568.8, 46.9, 40.5) + 0.286 * gaussian(l, 530.9, 16.3, 31.1);
+    let z = 1.217 * gaussian(l, 437.0, 11.8, 36.0) + 0.681 * gaussian(l, 459.0, 26.0, 13.8);
+    Vec3::new(x, y, z)
+}
+
+pub fn xyz_to_linear(xyz: Vec3) -> LinearRgb {
+    LinearRgb {
+        r: 3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        g: -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        b: 0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    }
+}
+
+/// Spectral radiance of a blackbody in arbitrary units; `wavelength` is in
+/// nanometres and `temperature` in kelvin.
+pub fn planck(wavelength: f32, temperature: f32) -> f32 {
+    // second radiation constant in µm·K
+    const C2: f32 = 14_387.77;
+
+    let l = wavelength * 1e-3;
+    let l5 = l * l * l * l * l;
+    (l5 * ((C2 / (l * temperature)).exp() - 1.0)).recip()
+}
+
+fn blackbody_xyz(temperature: f32) -> Vec3 {
+    let steps = ((WAVELENGTH_MAX - WAVELENGTH_MIN) / WAVELENGTH_STEP) as usize;
+    (0..=steps)
+        .map(|i| WAVELENGTH_MIN + i as f32 * WAVELENGTH_STEP)
+        .map(|l| cie_xyz(l) * planck(l, temperature) * WAVELENGTH_STEP)
+        .fold(Vec3::ZERO, |a, b| a + b)
+}
+
+/// Linear radiance of a blackbody at `temperature` kelvin, scaled so that a
+/// 6504K blackbody has a luminance of one.
+pub fn blackbody(temperature: f32) -> LinearRgb {
+    if temperature <= 0.0 {
+        return LinearRgb::BLACK;
+    }
+
+    let rgb = xyz_to_linear(blackbody_xyz(temperature) / D65_LUMINANCE);
+    LinearRgb {
+        r: rgb.r.max(0.0),
+        g: rgb.g.max(0.0),
+        b: rgb.b.max(0.0),
+    }
+}
+
+// reciprocal of the average clipped linear color of the visible
+// wavelengths, by channel, as checked by `white_spectrum`
+const RESPONSE_SCALE: [f32; 3] = [2.270_371, 3.466_611, 3.659_781];
+
+/// Linear color of light of a single `wavelength` in nanometres, clipped to
+/// the sRGB gamut and scaled so that wavelengths spread evenly over the
+/// visible range average out to white.
+pub fn wavelength_response(wavelength: f32) -> LinearRgb {
+    let rgb = xyz_to_linear(cie_xyz(wavelength));
+    LinearRgb {
+        r: rgb.r.max(0.0) * RESPONSE_SCALE[0],
+        g: rgb.g.max(0.0) * RESPONSE_SCALE[1],
+        b: rgb.b.max(0.0) * RESPONSE_SCALE[2],
+    }
+}
+
+fn visible_wavelengths() -> impl Iterator<Item = f32> {
+    let steps = ((WAVELENGTH_MAX - WAVELENGTH_MIN) / WAVELENGTH_STEP) as usize;
+    (0..=steps).map(|i| WAVELENGTH_MIN + i as f32 * WAVELENGTH_STEP)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Smooth blue, green and red bands that add up to one at every
+/// wavelength, and go on past the visible range.
+fn bands(wavelength: f32) -> Vec3 {
+    let blue = 1.0 - smoothstep(460.0, 520.0, wavelength);
+    let red = smoothstep(560.0, 620.0, wavelength);
+    Vec3::new(red, 1.0 - red - blue, blue)
+}
+
+/// Wavelength responses over the visible range, and the matrix that turns
+/// a color into the weights of the bands that make up its spectrum.
+struct Upsampling {
+    responses: Vec<Vec3>,
+    weights: Mat3,
+}
+
+fn upsampling() -> &'static Upsampling {
+    static UPSAMPLING: OnceLock<Upsampling> = OnceLock::new();
+    UPSAMPLING.get_or_init(|| {
+        let responses = visible_wavelengths()
+            .map(|l| Vec3::from(<[f32; 3]>::from(wavelength_response(l))))
+            .collect::<Vec<_>>();
+        // the color of each band, as a column
+        let colors = visible_wavelengths()
+            .zip(&responses)
+            .fold(Mat3::ZERO, |colors, (l, &r)| {
+                let b = bands(l);
+                colors + Mat3::from_cols(r * b.x, r * b.y, r * b.z)
+            })
+            * (responses.len() as f32).recip();
+        Upsampling {
+            responses,
+            weights: colors.inverse(),
+        }
+    })
+}
+
+/// Linear color of light emitted as `color`, seen with its frequency
+/// scaled by `shift`.
+///
+/// The light is given a spectrum of its color made of smooth blue, green
+/// and red bands, which is flat for white, and its wavelengths are divided
+/// by `shift`. The spectrum keeps its height at each wavelength, so only
+/// colored light changes color.
+pub fn shifted(color: LinearRgb, shift: f32) -> LinearRgb {
+    if shift == 1.0 {
+        return color;
+    }
+
+    let upsampling = upsampling();
+    let weights = upsampling.weights * Vec3::from(<[f32; 3]>::from(color));
+    let rgb = visible_wavelengths()
+        .zip(&upsampling.responses)
+        .fold(Vec3::ZERO, |rgb, (l, &response)| {
+            rgb + response * bands(l * shift).dot(weights)
+        })
+        / upsampling.responses.len() as f32;
+
+    LinearRgb {
+        r: rgb.x.max(0.0),
+        g: rgb.y.max(0.0),
+        b: rgb.z.max(0.0),
+    }
+}
+
 fn f32_to_u8(x: f32) -> u8 {
     (x * u8::MAX as f32) as u8
 }
@@ -286,3 +445,61 @@ impl_rgb_op!(Div, div);
 impl_scalar_op!(Div<f32>, div);
 impl_rgb_op_assign!(DivAssign, div_assign, div);
 impl_scalar_op_assign!(DivAssign<f32>, div_assign, div);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_spectrum() {
+        let steps = 4000;
+        let span = WAVELENGTH_MAX - WAVELENGTH_MIN;
+        let mean = (0..steps)
+            .map(|i| WAVELENGTH_MIN + (i as f32 + 0.5) * span / steps as f32)
+            .map(wavelength_response)
+            .fold(LinearRgb::BLACK, |a, b| a + b)
+            / steps as f32;
+        for channel in <[f32; 3]>::from(mean) {
+            assert!((channel - 1.0).abs() < 1e-3, "{mean:?}");
+        }
+
+        // the ends of the spectrum are red and blue
+        let red = wavelength_response(650.0);
+        let blue = wavelength_response(450.0);
+        assert!(red.r > red.g && red.r > red.b);
+        assert!(blue.b > blue.r && blue.b > blue.g);
+    }
+
+    #[test]
+    fn shifted_colors() {
+        // colors come back from their spectra as they were
+        for color in [
+            LinearRgb::WHITE,
+            LinearRgb::new(1.0, 0.0, 0.0),
+            LinearRgb::new(0.1, 0.5, 0.3),
+        ] {
+            let same = shifted(color, 1.0 + 1e-6);
+            for (a, b) in <[f32; 3]>::from(same)
+                .into_iter()
+                .zip(<[f32; 3]>::from(color))
+            {
+                assert!((a - b).abs() < 1e-3, "{same:?} != {color:?}");
+            }
+        }
+
+        // white light has a flat spectrum, which looks the same shifted
+        for shift in [0.8, 1.2] {
+            let white = shifted(LinearRgb::WHITE, shift);
+            for channel in <[f32; 3]>::from(white) {
+                assert!((channel - 1.0).abs() < 1e-3, "{white:?}");
+            }
+        }
+
+        // green light turns orange as it is redshifted, and blue as it is
+        // blueshifted
+        let orange = shifted(LinearRgb::new(0.0, 1.0, 0.0), 0.9);
+        assert!(orange.r > orange.g && orange.g > orange.b, "{orange:?}");
+        let blue = shifted(LinearRgb::new(0.0, 1.0, 0.0), 1.1);
+        assert!(blue.b > blue.g && blue.g > blue.r, "{blue:?}");
+    }
+}
@@ -5,10 +5,10 @@ use std::io::{self, BufReader, BufWriter, Write as _};
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use anyhow::{ensure, Error};
+use anyhow::{ensure, Context, Error};
 use bendy_tracer::color::LinearRgb;
 use bendy_tracer::scene::{
-    Camera, Cuboid, Data, Material, Object, ObjectFlags, Rect, Scene, Update, UpdateQueue,
+    import, Camera, Cuboid, Data, Material, Object, ObjectFlags, Rect, Scene, Update, UpdateQueue,
 };
 use bendy_tracer::tracer::{Buffer, ColorSpace, Config, RenderConfig, Status, Subsample, Tracer};
 use clap::{Parser, ValueEnum};
@@ -46,6 +46,24 @@ impl Output {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+enum Integrator {
+    Straight,
+    #[default]
+    Geodesic,
+    ThinLens,
+}
+
+impl Integrator {
+    fn into_integrator(self) -> bendy_tracer::tracer::Integrator {
+        match self {
+            Self::Straight => bendy_tracer::tracer::Integrator::Straight,
+            Self::Geodesic => bendy_tracer::tracer::Integrator::Geodesic,
+            Self::ThinLens => bendy_tracer::tracer::Integrator::ThinLens,
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 struct Cli {
@@ -58,6 +76,18 @@ struct Cli {
     #[clap(long, value_parser)]
     output: Output,
 
+    #[clap(long, value_parser, default_value = "geodesic")]
+    integrator: Integrator,
+
+    /// Largest error in position and momentum allowed per step of the
+    /// geodesic integrator.
+    #[clap(long, value_parser, default_value_t = Config::default().geodesic_tolerance)]
+    geodesic_tolerance: f32,
+
+    /// Number of wavelengths a path carries through dispersive glass.
+    #[clap(long, value_parser, default_value_t = Config::default().wavelengths)]
+    wavelengths: usize,
+
     #[clap(long, value_parser, default_value_t = 64)]
     samples: usize,
 
@@ -69,6 +99,26 @@ struct Cli {
 
     #[clap(long, value_parser, default_value_os_t = PathBuf::from("scene.json"))]
     scene: PathBuf,
+
+    /// Wavefront OBJ models to add to the scene.
+    #[clap(long, value_parser)]
+    obj: Vec<PathBuf>,
+
+    /// glTF 2.0 scenes to add to the scene.
+    #[clap(long, value_parser)]
+    gltf: Vec<PathBuf>,
+
+    /// PLY meshes to add to the scene.
+    #[clap(long, value_parser)]
+    ply: Vec<PathBuf>,
+
+    /// Grayscale images to add to the scene as terrain.
+    #[clap(long, value_parser)]
+    heightfield: Vec<PathBuf>,
+
+    /// Tag of the camera to render from.
+    #[clap(long, value_parser, default_value = "camera")]
+    camera: String,
 }
 
 fn main() -> Result<(), Error> {
@@ -213,7 +263,39 @@ fn main() -> Result<(), Error> {
         scene
     };
 
-    let mut camera = scene.find_by_tag("camera").unwrap();
+    for path in &args.obj {
+        let objects = import::load_obj(&mut scene, path)?;
+        writeln!(
+            io::stderr(),
+            "loaded {} meshes from {}",
+            objects.len(),
+            path.display()
+        )?;
+    }
+
+    for path in &args.gltf {
+        let objects = import::load_gltf(&mut scene, path)?;
+        writeln!(
+            io::stderr(),
+            "loaded {} nodes from {}",
+            objects.len(),
+            path.display()
+        )?;
+    }
+
+    for path in &args.ply {
+        import::load_ply(&mut scene, path)?;
+        writeln!(io::stderr(), "loaded mesh from {}", path.display())?;
+    }
+
+    for path in &args.heightfield {
+        import::load_heightfield(&mut scene, path)?;
+        writeln!(io::stderr(), "loaded height map from {}", path.display())?;
+    }
+
+    let mut camera = scene
+        .find_by_tag(&args.camera)
+        .with_context(|| format!("no camera tagged {:?}", args.camera))?;
 
     let mut update_queue = UpdateQueue::new();
     update_queue.push(Update::object(camera, move |object, _, _| {
@@ -224,6 +306,9 @@ fn main() -> Result<(), Error> {
 
     let tracer = Tracer::with_config(Config {
         output: args.output.into_output(),
+        integrator: args.integrator.into_integrator(),
+        geodesic_tolerance: args.geodesic_tolerance,
+        wavelengths: args.wavelengths,
         chunks_x: 8,
         chunks_y: 4,
         ..Default::default()
@@ -324,7 +409,9 @@ fn main() -> Result<(), Error> {
             };
             buffer.clear();
 
-            camera = scene.find_by_tag("camera").unwrap();
+            camera = scene
+                .find_by_tag(&args.camera)
+                .with_context(|| format!("no camera tagged {:?}", args.camera))?;
 
             update_queue.push(Update::object(camera, move |object, _, _| {
                 let aspect_ratio = window_width as f32 / window_height as f32;
@@ -378,6 +465,15 @@ fn main() -> Result<(), Error> {
                 write!(&mut title, "; avg t per sample: {seconds}s {millis}ms")?;
             }
         }
+        let stats = tracer.stats();
+        if stats.geodesics != 0 {
+            write!(
+                &mut title,
+                "; steps per geodesic: {:.1}; rejected steps: {}",
+                stats.steps_per_geodesic(),
+                stats.rejected_steps,
+            )?;
+        }
         if let (Some(start), Some(end)) = (start, end) {
             let total = end - start;
             let seconds = total.as_secs();
@@ -1,4 +1,8 @@
-use glam::{Vec3, Vec3A};
+use std::f32::consts::{PI, TAU};
+
+use glam::{Vec2, Vec3, Vec3A};
+
+use crate::color::LinearRgb;
 
 pub mod distr;
 
@@ -24,6 +28,29 @@ impl Interpolate for Vec3A {
     }
 }
 
+impl Interpolate for LinearRgb {
+    fn lerp(self, other: Self, factor: f32) -> Self {
+        self + (other - self) * factor
+    }
+}
+
+/// Texture coordinates of `direction` on a sphere, with the longitude
+/// around the y axis along u, which is 0 towards +z and 0.5 towards -z,
+/// and the latitude along v, from +y at the top.
+pub fn spherical_uv(direction: Vec3A) -> Vec2 {
+    let direction = direction.normalize_or_zero();
+    Vec2::new(
+        0.5 + direction.x.atan2(-direction.z) / TAU,
+        direction.y.clamp(-1.0, 1.0).acos() / PI,
+    )
+}
+
+/// Direction in which the u of `spherical_uv` grows at `direction`, which
+/// isn't normalized and vanishes at the poles.
+pub fn spherical_tangent(direction: Vec3A) -> Vec3A {
+    Vec3A::new(-direction.z, 0.0, direction.x)
+}
+
 pub trait Vec3Ext {
     fn project(self, normal: Self) -> Self;
     fn reflect(self, normal: Self) -> Self;
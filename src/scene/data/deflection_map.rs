@@ -0,0 +1,215 @@
+use std::f32;
+use std::sync::Arc;
+
+use anyhow::{ensure, Error, Result};
+use glam::{IVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+/// Deflection angles of a thin lens, sampled on a regular grid over its
+/// plane.
+///
+/// Each sample is the angle in radians by which rays crossing the plane at
+/// that point are bent, with `x` along the plane's local x axis and `y`
+/// along its local z axis. Clones share the samples, so screens can hold
+/// on to the map cheaply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "DeflectionBuffer")]
+pub struct DeflectionMap {
+    width: usize,
+    height: usize,
+    #[serde(skip)]
+    size: Vec2,
+    buffer: Arc<[Vec2]>,
+}
+
+#[derive(Deserialize)]
+struct DeflectionBuffer {
+    width: usize,
+    height: usize,
+    buffer: Vec<Vec2>,
+}
+
+impl TryFrom<DeflectionBuffer> for DeflectionMap {
+    type Error = Error;
+
+    fn try_from(buffer: DeflectionBuffer) -> Result<Self> {
+        Self::try_new(buffer.width, buffer.height, buffer.buffer)
+    }
+}
+
+impl DeflectionMap {
+    pub fn new(width: usize, height: usize, buffer: Vec<Vec2>) -> Self {
+        Self::try_new(width, height, buffer).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Creates a map of `width` by `height` samples, stored row by row, or
+    /// fails if `buffer` has the wrong size.
+    pub fn try_new(width: usize, height: usize, buffer: Vec<Vec2>) -> Result<Self> {
+        ensure!(
+            buffer.len() == width * height,
+            "deflection map has the wrong size"
+        );
+
+        let size = Vec2::new(width as f32 - 1.0, height as f32 - 1.0);
+        Ok(Self {
+            width,
+            height,
+            size,
+            buffer: buffer.into(),
+        })
+    }
+
+    pub fn with_value(width: usize, height: usize, value: Vec2) -> Self {
+        let size = width * height;
+        let buffer = vec![value; size];
+        Self::new(width, height, buffer)
+    }
+
+    pub fn with_func<F>(width: usize, height: usize, mut f: F) -> Self
+    where
+        F: FnMut(usize, usize) -> Vec2,
+    {
+        let size = width * height;
+        let buffer = (0..size).map(|i| f(i % width, i / width)).collect();
+        Self::new(width, height, buffer)
+    }
+
+    /// Computes the deflection of a surface density grid covering `extent`,
+    /// in geometrized units (`G = c = 1`), by summing `4 Σ dA (ξ - ξ') / |ξ - ξ'|²`
+    /// over all cells. The densities are taken at the same points the map is
+    /// sampled at, with the outer ones on the edges of `extent`.
+    ///
+    /// This takes quadratic time in the number of cells, so it is meant for
+    /// maps of moderate size.
+    pub fn from_surface_density(
+        width: usize,
+        height: usize,
+        extent: Vec2,
+        surface_density: &[f32],
+    ) -> Self {
+        assert_eq!(
+            surface_density.len(),
+            width * height,
+            "surface density has the wrong size"
+        );
+
+        let cell = extent / (Vec2::new(width as f32, height as f32) - 1.0).max(Vec2::ONE);
+        let area = cell.x * cell.y;
+        let position = |x: usize, y: usize| Vec2::new(x as f32, y as f32) * cell - 0.5 * extent;
+        // the cell itself is treated as a disk of the same area, whose
+        // deflection vanishes at its center
+        let softening = area / f32::consts::PI;
+
+        Self::with_func(width, height, |x, y| {
+            let here = position(x, y);
+            surface_density
+                .iter()
+                .enumerate()
+                .filter(|(_, density)| **density != 0.0)
+                .map(|(i, density)| {
+                    let offset = position(i % width, i / width) - here;
+                    let distance_squared = offset.length_squared().max(softening);
+                    offset * (4.0 * density * area / distance_squared)
+                })
+                .fold(Vec2::ZERO, |total, deflection| total + deflection)
+        })
+    }
+
+    /// Returns the sample at `coord`, clamped to the edges of the map.
+    pub fn index(&self, coord: IVec2) -> Vec2 {
+        if self.width == 0 || self.height == 0 {
+            return Vec2::ZERO;
+        }
+
+        let x = (coord.x.max(0) as usize).min(self.width - 1);
+        let y = (coord.y.max(0) as usize).min(self.height - 1);
+        self.buffer[y * self.width + x]
+    }
+
+    fn sample_xy(&self, x: f32, y: f32) -> Vec2 {
+        self.index(IVec2::new(x as _, y as _))
+    }
+
+    /// Samples the map bilinearly at `coord` in `[0, 1]²`.
+    pub fn sample(&self, coord: Vec2) -> Vec2 {
+        let coord = coord.clamp(Vec2::ZERO, Vec2::ONE);
+        let icoord = coord * self.size;
+
+        let x0 = self.sample_xy(icoord.x.floor(), icoord.y.floor());
+        let x1 = self.sample_xy(icoord.x.ceil(), icoord.y.floor());
+        let y0 = x0.lerp(x1, icoord.x.fract());
+        let x0 = self.sample_xy(icoord.x.floor(), icoord.y.ceil());
+        let x1 = self.sample_xy(icoord.x.ceil(), icoord.y.ceil());
+        let y1 = x0.lerp(x1, icoord.x.fract());
+
+        y0.lerp(y1, icoord.y.fract())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_disk() {
+        let (mass, radius, size) = (2.0, 10.0, 64);
+        let extent = Vec2::splat(40.0);
+        let cell = extent / (size - 1) as f32;
+        let density = mass / (f32::consts::PI * radius * radius);
+        let surface_density: Vec<_> = (0..size * size)
+            .map(|i| {
+                let position =
+                    Vec2::new((i % size) as f32, (i / size) as f32) * cell - 0.5 * extent;
+                if position.length() < radius {
+                    density
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let map = DeflectionMap::from_surface_density(size, size, extent, &surface_density);
+
+        // inside the disk the deflection grows linearly, outside it falls off
+        // like that of a point mass
+        for rho in [5.0, 15.0] {
+            let coord = Vec2::new(rho, 0.0) / extent + 0.5;
+            let expected = 4.0 * mass * (rho / radius).min(1.0).powi(2) / rho;
+            let deflection = map.sample(coord);
+            assert!(
+                (deflection.x + expected).abs() < 0.03 * expected,
+                "{rho}: {deflection} != {}",
+                -expected
+            );
+            assert!(deflection.y.abs() < 0.03 * expected);
+        }
+    }
+
+    #[test]
+    fn point_mass() {
+        let (mass, size) = (0.5, 33);
+        let extent = Vec2::splat(32.0);
+        let mut surface_density = vec![0.0; size * size];
+        // one unit cell, 8 to the right of the center
+        surface_density[16 * size + 24] = mass;
+        let map = DeflectionMap::from_surface_density(size, size, extent, &surface_density);
+
+        // read back at the grid point 8 to the left of the center
+        let deflection = map.sample(Vec2::new(8.0 / 32.0, 0.5));
+        let expected = 4.0 * mass / 16.0;
+        assert!(
+            (deflection.x - expected).abs() < 1e-4 * expected,
+            "{deflection} != {expected}"
+        );
+        assert!(deflection.y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn deserialize() {
+        let json = r#"{"width":2,"height":1,"buffer":[[1,0],[3,0]]}"#;
+        let map: DeflectionMap = serde_json::from_str(json).unwrap();
+        assert_eq!(map.sample(Vec2::new(0.5, 0.0)), Vec2::new(2.0, 0.0));
+
+        let json = r#"{"width":4,"height":4,"size":[3,3],"buffer":[[0,0],[0,0]]}"#;
+        assert!(serde_json::from_str::<DeflectionMap>(json).is_err());
+    }
+}
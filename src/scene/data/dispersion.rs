@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+/// Wavelength in nanometres at which glasses are usually given their index
+/// of refraction, the yellow helium d line.
+pub const REFERENCE_WAVELENGTH: f32 = 587.6;
+
+/// Index of refraction of a dielectric, either constant or changing with
+/// the wavelength of light.
+///
+/// Scene files give a constant as it is, and dispersion as a `Dispersion`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Ior {
+    Constant(f32),
+    Dispersive(Dispersion),
+}
+
+impl Ior {
+    /// The index of refraction at `wavelength` in nanometres, or at
+    /// `REFERENCE_WAVELENGTH` without one.
+    pub fn at(&self, wavelength: Option<f32>) -> f32 {
+        match *self {
+            Self::Constant(ior) => ior,
+            Self::Dispersive(dispersion) => {
+                dispersion.ior(wavelength.unwrap_or(REFERENCE_WAVELENGTH))
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        matches!(self, Self::Dispersive(_))
+    }
+}
+
+impl From<f32> for Ior {
+    fn from(ior: f32) -> Self {
+        Self::Constant(ior)
+    }
+}
+
+impl From<Dispersion> for Ior {
+    fn from(dispersion: Dispersion) -> Self {
+        Self::Dispersive(dispersion)
+    }
+}
+
+/// How the index of refraction of a glass changes with the wavelength `λ`
+/// of light, in micrometres.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Dispersion {
+    /// Cauchy's equation `n = a + b / λ²`, which fits most glasses well
+    /// enough over the visible range.
+    Cauchy { a: f32, b: f32 },
+    /// The Sellmeier equation `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`, as glass
+    /// makers publish it.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, the common crown glass of lenses.
+    pub const BK7: Self = Self::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+
+    /// Fused silica, after Malitson.
+    pub const FUSED_SILICA: Self = Self::Sellmeier {
+        b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+        c: [0.004_679_148, 0.013_512_063, 97.934],
+    };
+
+    /// Schott SF11, a dense flint glass that splits colors strongly.
+    pub const DENSE_FLINT: Self = Self::Sellmeier {
+        b: [1.737_597, 0.313_747_35, 1.898_781],
+        c: [0.013_188_707, 0.062_306_814, 155.236_3],
+    };
+
+    pub fn cauchy(a: f32, b: f32) -> Self {
+        Self::Cauchy { a, b }
+    }
+
+    /// The index of refraction at `wavelength` in nanometres.
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let l_sqr = (wavelength * 1e-3).powi(2);
+        match *self {
+            Self::Cauchy { a, b } => a + b / l_sqr,
+            Self::Sellmeier { b, c } => {
+                let n_sqr = 1.0 + (0..3).map(|i| b[i] * l_sqr / (l_sqr - c[i])).sum::<f32>();
+                n_sqr.sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glasses() {
+        // the published indices at the d line
+        for (dispersion, expected) in [
+            (Dispersion::BK7, 1.5168),
+            (Dispersion::FUSED_SILICA, 1.4585),
+            (Dispersion::DENSE_FLINT, 1.7847),
+        ] {
+            let ior = Ior::from(dispersion);
+            assert!((ior.at(None) - expected).abs() < 1e-3, "{dispersion:?}");
+            // blue light bends more than red
+            assert!(ior.at(Some(450.0)) > ior.at(Some(650.0)));
+        }
+
+        let cauchy = Dispersion::cauchy(1.5, 0.004);
+        assert!((cauchy.ior(500.0) - 1.516).abs() < 1e-5);
+
+        let ior: Ior = serde_json::from_str("1.33").unwrap();
+        assert_eq!(ior, Ior::Constant(1.33));
+        let ior: Ior = serde_json::from_str(r#"{"Cauchy": {"a": 1.5, "b": 0.004}}"#).unwrap();
+        assert_eq!(ior, Ior::Dispersive(cauchy));
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::{ensure, Error, Result};
+use glam::{Vec2, Vec3A};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use super::mesh::intersect_triangle;
+use crate::tracer::{Aabb, Clip, Ray};
+
+/// Heights sampled on a regular grid, spanning `[-1, 1]` along the local x
+/// and z axes with the first row at `z = -1`.
+///
+/// Each cell between four samples is split into two triangles. The cells
+/// are hit-tested through a pyramid of the smallest and largest heights
+/// over ever larger blocks of them, which isn't serialized but rebuilt
+/// whenever a map is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "HeightBuffer")]
+pub struct HeightMap {
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+    #[serde(skip)]
+    levels: Vec<Level>,
+}
+
+#[derive(Deserialize)]
+struct HeightBuffer {
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+}
+
+impl TryFrom<HeightBuffer> for HeightMap {
+    type Error = Error;
+
+    fn try_from(buffer: HeightBuffer) -> Result<Self> {
+        Self::try_new(buffer.width, buffer.depth, buffer.heights)
+    }
+}
+
+/// Range of heights in each block of `2^level` by `2^level` cells.
+#[derive(Debug, Clone)]
+struct Level {
+    width: usize,
+    depth: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+impl Level {
+    fn range(&self, x: usize, z: usize) -> (f32, f32) {
+        self.ranges[z * self.width + x]
+    }
+
+    /// The level above this one, where each block spans two by two of
+    /// these.
+    fn merged(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let depth = self.depth.div_ceil(2);
+        let ranges = (0..width * depth)
+            .map(|i| {
+                let (x, z) = (2 * (i % width), 2 * (i / width));
+                [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)]
+                    .into_iter()
+                    .filter(|&(x, z)| x < self.width && z < self.depth)
+                    .map(|(x, z)| self.range(x, z))
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), range| {
+                        (min.min(range.0), max.max(range.1))
+                    })
+            })
+            .collect();
+        Self {
+            width,
+            depth,
+            ranges,
+        }
+    }
+}
+
+impl HeightMap {
+    /// Creates a map of `width` by `depth` samples, stored row by row.
+    ///
+    /// Panics if there are too few samples, see `try_new`.
+    pub fn new(width: usize, depth: usize, heights: Vec<f32>) -> Self {
+        Self::try_new(width, depth, heights).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Creates a map of `width` by `depth` samples, stored row by row, or
+    /// fails if there are less than two by two of them or `heights` has the
+    /// wrong size.
+    pub fn try_new(width: usize, depth: usize, heights: Vec<f32>) -> Result<Self> {
+        ensure!(
+            width >= 2 && depth >= 2,
+            "height map needs at least two by two samples"
+        );
+        ensure!(
+            heights.len() == width * depth,
+            "height map has the wrong size"
+        );
+
+        let cells = Level {
+            width: width - 1,
+            depth: depth - 1,
+            ranges: (0..(width - 1) * (depth - 1))
+                .map(|i| {
+                    let (x, z) = (i % (width - 1), i / (width - 1));
+                    [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)]
+                        .into_iter()
+                        .map(|(x, z)| heights[z * width + x])
+                        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), height| {
+                            (min.min(height), max.max(height))
+                        })
+                })
+                .collect(),
+        };
+        let mut levels = vec![cells];
+        while let Some(level) = levels.last().filter(|level| level.ranges.len() > 1) {
+            levels.push(level.merged());
+        }
+
+        Ok(Self {
+            width,
+            depth,
+            heights,
+            levels,
+        })
+    }
+
+    pub fn with_func<F>(width: usize, depth: usize, mut f: F) -> Self
+    where
+        F: FnMut(usize, usize) -> f32,
+    {
+        let heights = (0..width * depth)
+            .map(|i| f(i % width, i / width))
+            .collect();
+        Self::new(width, depth, heights)
+    }
+
+    /// Reads the heights from the brightness of an image, from 0 for black
+    /// to 1 for white. 16 bit images keep their full precision.
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let image = image.to_luma16();
+        let heights = image
+            .pixels()
+            .map(|pixel| f32::from(pixel.0[0]) / f32::from(u16::MAX))
+            .collect();
+        Self::new(image.width() as usize, image.height() as usize, heights)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn bounding_box(&self) -> (Vec3A, Vec3A) {
+        let top = self.levels.last().expect("height map has no levels");
+        let (min, max) = top.range(0, 0);
+        (Vec3A::new(-1.0, min, -1.0), Vec3A::new(1.0, max, 1.0))
+    }
+
+    fn sample(&self, x: usize, z: usize) -> f32 {
+        self.heights[z * self.width + x]
+    }
+
+    /// Local position of the sample at `(x, z)`.
+    fn vertex(&self, x: usize, z: usize) -> Vec3A {
+        Vec3A::new(
+            2.0 * x as f32 / (self.width - 1) as f32 - 1.0,
+            self.sample(x, z),
+            2.0 * z as f32 / (self.depth - 1) as f32 - 1.0,
+        )
+    }
+
+    /// Normal at the sample at `(x, z)`, from the central differences of
+    /// the heights around it.
+    fn vertex_normal(&self, x: usize, z: usize) -> Vec3A {
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (back, front) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+        let slope_x = (self.sample(right, z) - self.sample(left, z))
+            / (self.vertex(right, z).x - self.vertex(left, z).x);
+        let slope_z = (self.sample(x, front) - self.sample(x, back))
+            / (self.vertex(x, front).z - self.vertex(x, back).z);
+        Vec3A::new(-slope_x, 1.0, -slope_z).normalize()
+    }
+
+    /// Normal at the local `position`, interpolated bilinearly between the
+    /// normals at the samples around it.
+    pub fn normal(&self, position: Vec3A) -> Vec3A {
+        let size = Vec2::new((self.width - 1) as f32, (self.depth - 1) as f32);
+        let grid = ((Vec2::new(position.x, position.z) + 1.0) * 0.5 * size).clamp(Vec2::ZERO, size);
+        // the far edges belong to the last cells
+        let (x, z) = (
+            (grid.x as usize).min(self.width - 2),
+            (grid.y as usize).min(self.depth - 2),
+        );
+        let Vec2 { x: u, y: v } = grid - Vec2::new(x as f32, z as f32);
+
+        let back = self
+            .vertex_normal(x, z)
+            .lerp(self.vertex_normal(x + 1, z), u);
+        let front = self
+            .vertex_normal(x, z + 1)
+            .lerp(self.vertex_normal(x + 1, z + 1), u);
+        back.lerp(front, v).normalize()
+    }
+
+    /// Bounds of the block at `(x, z)` on `level`.
+    fn block_bounds(&self, level: usize, x: usize, z: usize) -> Aabb {
+        let (min, max) = self.levels[level].range(x, z);
+        let cells = 1 << level;
+        let (x0, z0) = (x * cells, z * cells);
+        let (x1, z1) = (
+            ((x + 1) * cells).min(self.width - 1),
+            ((z + 1) * cells).min(self.depth - 1),
+        );
+        let (back, front) = (self.vertex(x0, z0), self.vertex(x1, z1));
+        Aabb::new(
+            Vec3A::new(back.x, min, back.z),
+            Vec3A::new(front.x, max, front.z),
+        )
+    }
+
+    /// Closest hit of `ray` within `clip`, with the normal of the triangle
+    /// that was hit. The ray's direction doesn't need to be normalized, `t`
+    /// is measured in its length.
+    pub fn hit(&self, ray: &Ray, clip: &Clip) -> Option<(f32, Vec3A)> {
+        // a little slack keeps rays along the edges of blocks from slipping
+        // between them
+        const SLACK: f32 = 1e-5;
+
+        let inverse = ray.direction.recip();
+        let mut closest: Option<(f32, Vec3A)> = None;
+        let mut stack = vec![(self.levels.len() - 1, 0, 0)];
+
+        while let Some((level, x, z)) = stack.pop() {
+            let clip = Clip {
+                min: clip.min,
+                max: closest.map_or(clip.max, |(t, _)| t),
+            };
+            let bounds = self.block_bounds(level, x, z).grow(SLACK);
+            if bounds.intersect(ray.origin, inverse, &clip).is_none() {
+                continue;
+            }
+
+            if level == 0 {
+                let [a, b, c, d] = [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)]
+                    .map(|(x, z)| self.vertex(x, z));
+                for [a, b, c] in [[a, c, b], [b, c, d]] {
+                    if let Some((t, _)) = intersect_triangle(ray, &clip, a, b, c) {
+                        if closest.is_none_or(|(closest, _)| t < closest) {
+                            closest = Some((t, (b - a).cross(c - a).normalize()));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // the nearest block goes on the stack last, to be tested first
+            let below = &self.levels[level - 1];
+            let mut blocks: Vec<_> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                .into_iter()
+                .map(|(dx, dz)| (2 * x + dx, 2 * z + dz))
+                .filter(|&(x, z)| x < below.width && z < below.depth)
+                .filter_map(|(x, z)| {
+                    let bounds = self.block_bounds(level - 1, x, z).grow(SLACK);
+                    let t = bounds.intersect(ray.origin, inverse, &clip)?;
+                    Some((t, x, z))
+                })
+                .collect();
+            blocks.sort_by(|(a, _, _), (b, _, _)| b.total_cmp(a));
+            stack.extend(blocks.into_iter().map(|(_, x, z)| (level - 1, x, z)));
+        }
+
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn pyramid() {
+        // an odd size leaves partial blocks along the far edges
+        let map = HeightMap::with_func(37, 23, |x, z| {
+            let (x, z) = (x as f32 * 0.4, z as f32 * 0.3);
+            0.2 * x.sin() * z.cos() + 0.05 * (3.0 * x + z).sin()
+        });
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..2000 {
+            let origin = Vec3A::new(
+                rng.gen_range(-1.5..1.5),
+                rng.gen_range(0.3..1.0),
+                rng.gen_range(-1.5..1.5),
+            );
+            let target = Vec3A::new(rng.gen_range(-1.0..1.0), 0.0, rng.gen_range(-1.0..1.0));
+            let ray = Ray::new(origin, target - origin);
+
+            let expected = (0..map.depth - 1)
+                .flat_map(|z| (0..map.width - 1).map(move |x| (x, z)))
+                .flat_map(|(x, z)| {
+                    let [a, b, c, d] = [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)]
+                        .map(|(x, z)| map.vertex(x, z));
+                    [[a, c, b], [b, c, d]]
+                })
+                .filter_map(|[a, b, c]| intersect_triangle(&ray, &clip, a, b, c))
+                .map(|(t, _)| t)
+                .reduce(f32::min);
+            let hit = map.hit(&ray, &clip).map(|(t, _)| t);
+
+            match (hit, expected) {
+                (Some(hit), Some(expected)) => assert!((hit - expected).abs() < 1e-4),
+                (None, None) => {}
+                _ => panic!("{ray:?}: {hit:?} != {expected:?}"),
+            }
+        }
+
+        // the normals face up and lean away from the slopes
+        let normal = map.normal(Vec3A::new(0.0, 0.0, 0.0));
+        assert!(normal.y > 0.0 && normal.is_normalized());
+
+        // and blend all the way into the samples on the far edges
+        let (x, z) = (map.width - 1, map.depth - 1);
+        let corner = map.normal(Vec3A::new(1.0, 0.0, 1.0));
+        assert!(corner.abs_diff_eq(map.vertex_normal(x, z), 1e-5));
+        let edge = map.normal(map.vertex(x, z - 1) - Vec3A::X * 0.5 / x as f32);
+        let expected = map
+            .vertex_normal(x - 1, z - 1)
+            .lerp(map.vertex_normal(x, z - 1), 0.75)
+            .normalize();
+        assert!(edge.abs_diff_eq(expected, 1e-4));
+
+        let json = r#"{"width": 3, "depth": 2, "heights": [0, 0]}"#;
+        assert!(serde_json::from_str::<HeightMap>(json).is_err());
+        let (_, face_normal) = map
+            .hit(&Ray::new(Vec3A::new(0.1, 1.0, 0.2), Vec3A::NEG_Y), &clip)
+            .unwrap();
+        assert!(face_normal.y > 0.0);
+
+        // a fine map seen through a large transform gets rays with a short
+        // direction in local space, which still hit every cell
+        let size = 2048;
+        let map = HeightMap::with_func(size, size, |x, z| {
+            0.01 * (x as f32 * 0.1).sin() * (z as f32 * 0.1).cos()
+        });
+        let scale = 1e6;
+        for _ in 0..100 {
+            let target = Vec3A::new(rng.gen_range(-1.0..1.0), 0.0, rng.gen_range(-1.0..1.0));
+            let origin =
+                target + Vec3A::new(rng.gen_range(-0.5..0.5), 1.0, rng.gen_range(-0.5..0.5));
+            let ray = Ray {
+                origin,
+                direction: (target - origin).normalize() / scale,
+            };
+            assert!(
+                map.hit(&ray, &Clip { min: 0.0, max: 1e8 }).is_some(),
+                "{ray:?}"
+            );
+        }
+    }
+}
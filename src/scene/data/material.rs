@@ -6,8 +6,10 @@ use rand::prelude::*;
 use rand_distr::Uniform;
 use serde::{Deserialize, Serialize};
 
-use crate::color::LinearRgb;
-use crate::math::distr::{Cosine, UnitHemisphere};
+use super::microfacet::{dielectric_fresnel, Ggx};
+use super::{principled, Dispersion, Ior, NormalMap, Param, Principled};
+use crate::color::{self, LinearRgb};
+use crate::math::distr::Cosine;
 use crate::math::{Interpolate, Vec3Ext};
 use crate::scene::{ObjectFlags, ObjectRef};
 use crate::tracer::{Clip, ColorData, Manifold, Ray};
@@ -22,59 +24,186 @@ pub struct ShaderData {
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Material {
     Flat {
-        albedo: LinearRgb,
+        albedo: Param<LinearRgb>,
     },
     Diffuse {
-        albedo: LinearRgb,
-        roughness: f32,
+        albedo: Param<LinearRgb>,
+        roughness: Param<f32>,
+        #[serde(default)]
+        normal_map: Option<NormalMap>,
     },
     Metallic {
-        albedo: LinearRgb,
-        roughness: f32,
+        albedo: Param<LinearRgb>,
+        roughness: Param<f32>,
+        #[serde(default)]
+        normal_map: Option<NormalMap>,
     },
     Glass {
-        albedo: LinearRgb,
-        roughness: f32,
-        ior: f32,
+        albedo: Param<LinearRgb>,
+        roughness: Param<f32>,
+        ior: Ior,
+        #[serde(default)]
+        normal_map: Option<NormalMap>,
     },
     Emissive {
-        albedo: LinearRgb,
+        albedo: Param<LinearRgb>,
         intensity: f32,
     },
+    /// Emits blackbody radiation at `temperature` kelvin, scaled by the
+    /// temperature profile of the object it is applied to.
+    ///
+    /// A blackbody seen through a frequency shift `g` looks like a blackbody
+    /// at `g` times its temperature, so its color shifts physically; the
+    /// brightness change that comes with it is left to the tracer's beaming.
+    Blackbody {
+        temperature: f32,
+        intensity: f32,
+    },
+    /// Any blend of the scattering of the other materials, see
+    /// `Principled`. Scene files can give another material here to have it
+    /// converted.
+    Principled(#[serde(deserialize_with = "principled::deserialize")] Principled),
 }
 
 impl Material {
     pub const fn flat(albedo: LinearRgb) -> Self {
-        Self::Flat { albedo }
+        Self::Flat {
+            albedo: Param::Constant(albedo),
+        }
     }
 
     pub const fn diffuse(albedo: LinearRgb, roughness: f32) -> Self {
-        Self::Diffuse { albedo, roughness }
+        Self::Diffuse {
+            albedo: Param::Constant(albedo),
+            roughness: Param::Constant(roughness),
+            normal_map: None,
+        }
     }
 
     pub const fn metallic(albedo: LinearRgb, roughness: f32) -> Self {
-        Self::Metallic { albedo, roughness }
+        Self::Metallic {
+            albedo: Param::Constant(albedo),
+            roughness: Param::Constant(roughness),
+            normal_map: None,
+        }
     }
 
     pub const fn glass(albedo: LinearRgb, roughness: f32, ior: f32) -> Self {
         Self::Glass {
-            albedo,
-            roughness,
-            ior,
+            albedo: Param::Constant(albedo),
+            roughness: Param::Constant(roughness),
+            ior: Ior::Constant(ior),
+            normal_map: None,
+        }
+    }
+
+    /// Glass that splits light into its colors, see `Dispersion`.
+    pub const fn dispersive_glass(
+        albedo: LinearRgb,
+        roughness: f32,
+        dispersion: Dispersion,
+    ) -> Self {
+        Self::Glass {
+            albedo: Param::Constant(albedo),
+            roughness: Param::Constant(roughness),
+            ior: Ior::Dispersive(dispersion),
+            normal_map: None,
         }
     }
 
     pub const fn emissive(albedo: LinearRgb, intensity: f32) -> Self {
-        Self::Emissive { albedo, intensity }
+        Self::Emissive {
+            albedo: Param::Constant(albedo),
+            intensity,
+        }
+    }
+
+    pub const fn blackbody(temperature: f32, intensity: f32) -> Self {
+        Self::Blackbody {
+            temperature,
+            intensity,
+        }
+    }
+
+    pub const fn principled(principled: Principled) -> Self {
+        Self::Principled(principled)
+    }
+
+    /// Adds `normal_map` to a material that scatters light, other materials
+    /// are left as they are.
+    pub fn with_normal_map(mut self, normal_map: NormalMap) -> Self {
+        match &mut self {
+            Material::Diffuse {
+                normal_map: map, ..
+            }
+            | Material::Metallic {
+                normal_map: map, ..
+            }
+            | Material::Glass {
+                normal_map: map, ..
+            }
+            | Material::Principled(Principled {
+                normal_map: map, ..
+            }) => *map = Some(normal_map),
+            Material::Flat { .. } | Material::Emissive { .. } | Material::Blackbody { .. } => {}
+        }
+        self
     }
 
-    pub fn emitted<R: Rng + ?Sized>(&self, _rng: &mut R, _manifold: &Manifold) -> LinearRgb {
+    /// Whether the material bends light differently for each wavelength,
+    /// so that the tracer has to follow them one by one.
+    pub fn is_dispersive(&self) -> bool {
+        matches!(self, Material::Glass { ior, .. } if ior.is_dispersive())
+    }
+
+    pub fn normal_map(&self) -> Option<&NormalMap> {
+        match self {
+            Material::Diffuse { normal_map, .. }
+            | Material::Metallic { normal_map, .. }
+            | Material::Glass { normal_map, .. }
+            | Material::Principled(Principled { normal_map, .. }) => normal_map.as_ref(),
+            Material::Flat { .. } | Material::Emissive { .. } | Material::Blackbody { .. } => None,
+        }
+    }
+
+    /// `manifold` with the normal replaced by the shading normal of the
+    /// material's normal map, if it has one.
+    fn shading<'a>(&self, manifold: &Manifold<'a>) -> Manifold<'a> {
+        match self.normal_map() {
+            Some(normal_map) => Manifold {
+                normal: normal_map.shading_normal(manifold),
+                ..*manifold
+            },
+            None => *manifold,
+        }
+    }
+
+    pub fn emitted<R: Rng + ?Sized>(&self, _rng: &mut R, manifold: &Manifold) -> LinearRgb {
         match *self {
             Material::Diffuse { .. } | Material::Metallic { .. } | Material::Glass { .. } => {
                 LinearRgb::BLACK
             }
-            Material::Flat { albedo } => albedo,
-            Material::Emissive { albedo, intensity } => albedo * intensity,
+            Material::Flat { albedo } => color::shifted(albedo.at(manifold), manifold.shift),
+            Material::Emissive { albedo, intensity } => {
+                color::shifted(albedo.at(manifold), manifold.shift) * intensity
+            }
+            Material::Principled(principled) => {
+                let emission = principled.emission.at(manifold);
+                color::shifted(emission, manifold.shift) * principled.emission_strength
+            }
+            Material::Blackbody {
+                temperature,
+                intensity,
+            } => {
+                let scale = manifold.object_ref.map_or(1.0, |object_ref| {
+                    manifold
+                        .scene
+                        .get_object(object_ref)
+                        .temperature_scale(manifold.position)
+                });
+                let shift = manifold.shift;
+                color::blackbody(temperature * scale * shift) * intensity / shift.powi(4)
+            }
         }
     }
 
@@ -83,6 +212,32 @@ impl Material {
         rng: &mut R,
         manifold: &Manifold,
         clip: &Clip,
+    ) -> ShaderData {
+        let shading = self.shading(manifold);
+        let data = self.shade_impl(rng, &shading, clip);
+
+        // a tilted normal can send rays to the other side of the surface
+        // than it seems to, which would leak light through it
+        match data.scatter {
+            Some(ray)
+                if ray.direction.dot(manifold.normal).is_sign_positive()
+                    != ray.direction.dot(shading.normal).is_sign_positive() =>
+            {
+                ShaderData {
+                    scatter: None,
+                    pdf: 1.0,
+                    ..data
+                }
+            }
+            _ => data,
+        }
+    }
+
+    fn shade_impl<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        manifold: &Manifold,
+        clip: &Clip,
     ) -> ShaderData {
         match *self {
             Material::Flat { .. } => ShaderData {
@@ -96,6 +251,7 @@ impl Material {
                 pdf: 1.0,
             },
             Material::Diffuse { albedo, .. } => {
+                let albedo = albedo.at(manifold);
                 let color_data = ColorData {
                     color: albedo,
                     albedo,
@@ -103,23 +259,13 @@ impl Material {
                     depth: manifold.t,
                 };
 
-                let count = manifold
-                    .scene
-                    .iter()
-                    .filter(|object| object.has_flags(ObjectFlags::LIGHT))
-                    .count();
-
-                let index = rng.sample::<usize, _>(Uniform::new(0, count));
-
-                let (light, _) = manifold
-                    .scene
-                    .pairs()
-                    .filter(|(_, object)| object.has_flags(ObjectFlags::LIGHT))
-                    .nth(index)
-                    .unwrap();
-
                 // TODO: optimize this allocation
-                let pdf = Pdf::Mix(Box::new(Pdf::Diffuse), Box::new(Pdf::Light(light)), 0.5);
+                let pdf = match random_light(rng, manifold) {
+                    Some(light) => {
+                        Pdf::Mix(Box::new(Pdf::Diffuse), Box::new(Pdf::Light(light)), 0.5)
+                    }
+                    None => Pdf::Diffuse,
+                };
                 let ray = pdf.scatter(rng, manifold);
 
                 if let Some(pdf) = pdf.pdf(&ray, manifold, clip) {
@@ -136,7 +282,10 @@ impl Material {
                     }
                 }
             }
-            Material::Metallic { albedo, roughness } => {
+            Material::Metallic {
+                albedo, roughness, ..
+            } => {
+                let (albedo, roughness) = (albedo.at(manifold), roughness.at(manifold));
                 let color_data = ColorData {
                     color: albedo,
                     albedo,
@@ -165,7 +314,9 @@ impl Material {
                 albedo,
                 roughness,
                 ior,
+                ..
             } => {
+                let (albedo, roughness) = (albedo.at(manifold), roughness.at(manifold));
                 let color_data = ColorData {
                     color: albedo,
                     albedo,
@@ -173,7 +324,7 @@ impl Material {
                     depth: manifold.t,
                 };
 
-                let pdf = Pdf::Glass(roughness, ior);
+                let pdf = Pdf::Glass(roughness, ior.at(manifold.wavelength));
                 let ray = pdf.scatter(rng, manifold);
 
                 if let Some(pdf) = pdf.pdf(&ray, manifold, clip) {
@@ -190,7 +341,62 @@ impl Material {
                     }
                 }
             }
-            Material::Emissive { .. } => ShaderData {
+            Material::Principled(principled) => {
+                let principled = principled.at(manifold.scene, manifold.uv);
+                let color_data = ColorData {
+                    color: LinearRgb::BLACK,
+                    albedo: principled.base_color,
+                    normal: manifold.normal,
+                    depth: manifold.t,
+                };
+                if !principled.scatters(manifold.face) {
+                    return ShaderData {
+                        scatter: None,
+                        albedo: Some(color_data),
+                        pdf: 1.0,
+                    };
+                }
+
+                // light sampling takes half the share of the diffuse lobe
+                let light = random_light(rng, manifold);
+                let share =
+                    light.map_or(0.0, |_| 0.5 * principled.diffuse_probability(manifold.face));
+
+                let normal = manifold.normal;
+                let outgoing = -manifold.ray.direction.normalize();
+                let ray = match light {
+                    Some(light) if rng.gen_bool(share as _) => {
+                        Pdf::Light(light).scatter(rng, manifold)
+                    }
+                    _ => Ray::new(
+                        manifold.position,
+                        principled.sample(rng, normal, manifold.face, outgoing),
+                    ),
+                };
+
+                let (color, pdf) =
+                    principled.evaluate(normal, manifold.face, outgoing, ray.direction);
+                let light_pdf = light.map_or(0.0, |light| light_pdf(light, &ray, manifold, clip));
+                let pdf = pdf.lerp(light_pdf, share);
+
+                if pdf.abs_diff_eq(&0.0, 1e-5) {
+                    ShaderData {
+                        scatter: None,
+                        albedo: Some(color_data),
+                        pdf: 1.0,
+                    }
+                } else {
+                    ShaderData {
+                        scatter: Some(ray),
+                        albedo: Some(ColorData {
+                            color,
+                            ..color_data
+                        }),
+                        pdf,
+                    }
+                }
+            }
+            Material::Emissive { .. } | Material::Blackbody { .. } => ShaderData {
                 scatter: None,
                 albedo: None,
                 pdf: 1.0,
@@ -198,13 +404,68 @@ impl Material {
         }
     }
 
+    /// How much light arriving along `ray` is scattered back along the ray
+    /// of the manifold, as the scattering function times the cosine of the
+    /// angle to the normal, without the albedo.
+    ///
+    /// Smooth metal and glass scatter along a single direction, for which
+    /// this is 1. So is it for principled materials, whose lobes are tinted
+    /// differently and already make up the color `shade` returns.
     pub fn pdf(&self, manifold: &Manifold, ray: &Ray) -> f32 {
+        let manifold = &self.shading(manifold);
         match *self {
             Material::Flat { .. } => 1.0,
             Material::Diffuse { .. } => diffuse_pdf(ray, manifold),
-            Material::Metallic { roughness, .. } => metallic_pdf(ray, manifold, roughness),
-            Material::Glass { roughness, ior, .. } => glass_pdf(ray, manifold, roughness, ior),
-            Material::Emissive { .. } => 1.0,
+            Material::Metallic { roughness, .. } => {
+                metallic_scattering(ray, manifold, roughness.at(manifold))
+            }
+            Material::Glass { roughness, ior, .. } => glass_scattering(
+                ray,
+                manifold,
+                roughness.at(manifold),
+                ior.at(manifold.wavelength),
+            ),
+            Material::Emissive { .. } | Material::Blackbody { .. } => 1.0,
+            Material::Principled(_) => 1.0,
+        }
+    }
+
+    /// The scattering function and the density with which `shade` picks
+    /// `ray`, at the wavelength of `manifold`.
+    ///
+    /// Unlike `pdf`, smooth glass gives the chance of reflecting or
+    /// refracting here, and nothing for a direction that only light of
+    /// other wavelengths is refracted into.
+    pub fn spectral_pdf(&self, manifold: &Manifold, ray: &Ray) -> (f32, f32) {
+        let manifold = &self.shading(manifold);
+        match *self {
+            Material::Glass { roughness, ior, .. } => {
+                let (roughness, ior) = (roughness.at(manifold), ior.at(manifold.wavelength));
+                if Ggx::new(roughness, manifold.normal).is_some() {
+                    return (
+                        glass_scattering(ray, manifold, roughness, ior),
+                        glass_pdf(ray, manifold, roughness, ior),
+                    );
+                }
+
+                let ior = relative_ior(manifold, ior);
+                let incoming = manifold.ray.direction.normalize();
+                let fresnel = dielectric_fresnel(-incoming, manifold.normal, ior).clamp(0.0, 1.0);
+                let refracted = ray.direction.dot(manifold.normal) < 0.0;
+                let chance = if !refracted {
+                    fresnel
+                } else if incoming
+                    .refract(manifold.normal, ior)
+                    .normalize_or_zero()
+                    .abs_diff_eq(ray.direction.normalize(), 1e-4)
+                {
+                    1.0 - fresnel
+                } else {
+                    0.0
+                };
+                (chance, chance)
+            }
+            _ => (self.pdf(manifold, ray), 1.0),
         }
     }
 }
@@ -229,35 +490,30 @@ impl Pdf {
                 Ray::new(origin, direction)
             }
             Self::Metallic(roughness) => {
-                let hemisphere = UnitHemisphere::new(manifold.normal.into());
-
                 let origin = manifold.position;
-                let direction = manifold.ray.direction.reflect(manifold.normal);
-                let fuzz: Vec3A = hemisphere.sample(rng);
-                let fuzz = fuzz * roughness;
-                Ray::new(origin, direction + fuzz)
+                let incoming = manifold.ray.direction.normalize();
+                let direction = match Ggx::new(roughness, manifold.normal) {
+                    Some(ggx) => incoming.reflect(ggx.sample_visible(rng, -incoming)),
+                    None => incoming.reflect(manifold.normal),
+                };
+                Ray::new(origin, direction)
             }
             Self::Glass(roughness, ior) => {
-                let hemisphere = UnitHemisphere::new(manifold.normal.into());
-
-                let ior = if manifold.face.is_front() {
-                    ior.recip()
-                } else {
-                    ior
+                let ior = relative_ior(manifold, ior);
+                let incoming = manifold.ray.direction.normalize();
+                let normal = match Ggx::new(roughness, manifold.normal) {
+                    Some(ggx) => ggx.sample_visible(rng, -incoming),
+                    None => manifold.normal,
                 };
-                let cos_theta = (-manifold.ray.direction).dot(manifold.normal).min(1.0);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-                let fresnel = manifold.ray.direction.fresnel(manifold.normal, ior);
+                let fresnel = dielectric_fresnel(-incoming, normal, ior);
 
                 let origin = manifold.position;
-                let direction = if ior * sin_theta > 1.0 || rng.gen_bool(fresnel as _) {
-                    manifold.ray.direction.reflect(manifold.normal)
+                let direction = if rng.gen_bool(fresnel.clamp(0.0, 1.0) as _) {
+                    incoming.reflect(normal)
                 } else {
-                    manifold.ray.direction.refract(manifold.normal, ior)
+                    incoming.refract(normal, ior)
                 };
-                let fuzz: Vec3A = hemisphere.sample(rng);
-                let fuzz = fuzz * roughness;
-                Ray::new(origin, direction + fuzz)
+                Ray::new(origin, direction)
             }
             Self::Light(object) => {
                 let light = manifold.scene.get_object(object);
@@ -302,15 +558,77 @@ fn diffuse_pdf(ray: &Ray, manifold: &Manifold) -> f32 {
     manifold.normal.dot(ray.direction) * f32::consts::FRAC_1_PI
 }
 
-fn metallic_pdf(_ray: &Ray, _manifold: &Manifold, _roughness: f32) -> f32 {
-    1.0
+fn metallic_scattering(ray: &Ray, manifold: &Manifold, roughness: f32) -> f32 {
+    match Ggx::new(roughness, manifold.normal) {
+        Some(ggx) => {
+            ggx.reflection(-manifold.ray.direction.normalize(), ray.direction)
+                .0
+        }
+        None => 1.0,
+    }
+}
+
+fn metallic_pdf(ray: &Ray, manifold: &Manifold, roughness: f32) -> f32 {
+    match Ggx::new(roughness, manifold.normal) {
+        Some(ggx) => {
+            ggx.reflection(-manifold.ray.direction.normalize(), ray.direction)
+                .1
+        }
+        None => 1.0,
+    }
+}
+
+fn glass_scattering(ray: &Ray, manifold: &Manifold, roughness: f32, ior: f32) -> f32 {
+    let ior = relative_ior(manifold, ior);
+    match Ggx::new(roughness, manifold.normal) {
+        Some(ggx) => {
+            ggx.dielectric(-manifold.ray.direction.normalize(), ray.direction, ior)
+                .0
+        }
+        None => 1.0,
+    }
 }
 
-fn glass_pdf(_ray: &Ray, _manifold: &Manifold, _roughness: f32, _ior: f32) -> f32 {
-    1.0
+fn glass_pdf(ray: &Ray, manifold: &Manifold, roughness: f32, ior: f32) -> f32 {
+    let ior = relative_ior(manifold, ior);
+    match Ggx::new(roughness, manifold.normal) {
+        Some(ggx) => {
+            ggx.dielectric(-manifold.ray.direction.normalize(), ray.direction, ior)
+                .1
+        }
+        None => 1.0,
+    }
+}
+
+/// Picks one of the scene's lights at random, if it has any. Lights in the
+/// prototypes of instances aren't sampled.
+fn random_light<R: Rng + ?Sized>(rng: &mut R, manifold: &Manifold) -> Option<ObjectRef> {
+    let scene = manifold.scene;
+    let lights = || {
+        scene.pairs().filter(|&(object_ref, object)| {
+            object.has_flags(ObjectFlags::LIGHT) && !scene.is_instanced(object_ref)
+        })
+    };
+    let count = lights().count();
+    if count == 0 {
+        return None;
+    }
+
+    let index = rng.sample::<usize, _>(Uniform::new(0, count));
+    lights().nth(index).map(|(light, _)| light)
 }
 
 fn light_pdf(object: ObjectRef, ray: &Ray, manifold: &Manifold, clip: &Clip) -> f32 {
     let light = manifold.scene.get_object(object);
     light.pdf(ray, clip, manifold.scene).unwrap_or_default()
 }
+
+/// Ratio of the index of refraction on the side the ray comes from to the
+/// one on the other side of the surface.
+fn relative_ior(manifold: &Manifold, ior: f32) -> f32 {
+    if manifold.face.is_front() {
+        ior.recip()
+    } else {
+        ior
+    }
+}
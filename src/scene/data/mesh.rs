@@ -0,0 +1,327 @@
+use anyhow::{ensure, Error, Result};
+use glam::{Vec2, Vec3A};
+use serde::{Deserialize, Serialize};
+
+use crate::tracer::{Aabb, Bvh, Clip, Intersection, Ray};
+
+/// An indexed triangle mesh, with optional per-vertex normals and UVs.
+///
+/// The triangles are kept in a bounding volume hierarchy, which isn't
+/// serialized but rebuilt whenever a mesh is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "MeshBuffers")]
+pub struct TriangleMesh {
+    positions: Vec<Vec3A>,
+    normals: Vec<Vec3A>,
+    uvs: Vec<Vec2>,
+    indices: Vec<[u32; 3]>,
+    #[serde(skip)]
+    bvh: Bvh<u32>,
+}
+
+#[derive(Deserialize)]
+struct MeshBuffers {
+    positions: Vec<Vec3A>,
+    normals: Vec<Vec3A>,
+    uvs: Vec<Vec2>,
+    indices: Vec<[u32; 3]>,
+}
+
+impl TryFrom<MeshBuffers> for TriangleMesh {
+    type Error = Error;
+
+    fn try_from(buffers: MeshBuffers) -> Result<Self> {
+        Self::try_new(
+            buffers.positions,
+            buffers.normals,
+            buffers.uvs,
+            buffers.indices,
+        )
+    }
+}
+
+/// Where a ray hit a triangle of a `TriangleMesh`.
+#[derive(Debug, Clone, Copy)]
+pub struct TriangleHit {
+    pub t: f32,
+    pub triangle: u32,
+    /// Barycentric coordinates of the hit, the weights of the triangle's
+    /// second and third vertex.
+    pub barycentric: Vec2,
+}
+
+impl Intersection for TriangleHit {
+    fn distance(&self) -> f32 {
+        self.t
+    }
+}
+
+impl TriangleMesh {
+    /// Creates a mesh from its vertex buffers, `normals` and `uvs` are
+    /// either empty or have one entry per position.
+    ///
+    /// Panics if the buffers don't fit together, see `try_new`.
+    pub fn new(
+        positions: Vec<Vec3A>,
+        normals: Vec<Vec3A>,
+        uvs: Vec<Vec2>,
+        indices: Vec<[u32; 3]>,
+    ) -> Self {
+        Self::try_new(positions, normals, uvs, indices).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Creates a mesh from its vertex buffers, or fails if `normals` or
+    /// `uvs` don't have one entry per position or `indices` are out of
+    /// bounds.
+    pub fn try_new(
+        positions: Vec<Vec3A>,
+        normals: Vec<Vec3A>,
+        uvs: Vec<Vec2>,
+        indices: Vec<[u32; 3]>,
+    ) -> Result<Self> {
+        ensure!(
+            normals.is_empty() || normals.len() == positions.len(),
+            "mesh needs one normal per vertex"
+        );
+        ensure!(
+            uvs.is_empty() || uvs.len() == positions.len(),
+            "mesh needs one uv per vertex"
+        );
+        ensure!(
+            indices
+                .iter()
+                .flatten()
+                .all(|&index| (index as usize) < positions.len()),
+            "mesh index out of bounds"
+        );
+
+        let bvh = Bvh::new(indices.iter().enumerate().map(|(i, triangle)| {
+            let [a, b, c] = triangle.map(|index| positions[index as usize]);
+            (i as u32, Aabb::new(a.min(b).min(c), a.max(b).max(c)))
+        }));
+
+        Ok(Self {
+            positions,
+            normals,
+            uvs,
+            indices,
+            bvh,
+        })
+    }
+
+    /// Replaces the normals with smooth ones, averaged over the triangles
+    /// around each vertex and weighted by their area.
+    pub fn with_smooth_normals(self) -> Self {
+        let mut normals = vec![Vec3A::ZERO; self.positions.len()];
+        for triangle in &self.indices {
+            let [a, b, c] = triangle.map(|index| self.positions[index as usize]);
+            let normal = (b - a).cross(c - a);
+            for &index in triangle {
+                normals[index as usize] += normal;
+            }
+        }
+        for normal in &mut normals {
+            *normal = normal.normalize_or_zero();
+        }
+
+        Self { normals, ..self }
+    }
+
+    pub fn positions(&self) -> &[Vec3A] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vec3A] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[Vec2] {
+        &self.uvs
+    }
+
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    /// Number of triangles.
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn bounding_box(&self) -> (Vec3A, Vec3A) {
+        let bounds = self.bvh.bounds();
+        (bounds.min, bounds.max)
+    }
+
+    fn vertices(&self, triangle: u32) -> [Vec3A; 3] {
+        self.indices[triangle as usize].map(|index| self.positions[index as usize])
+    }
+
+    /// Closest triangle hit by `ray` within `clip`. The ray's direction
+    /// doesn't need to be normalized, `t` is measured in its length.
+    pub fn hit(&self, ray: &Ray, clip: &Clip) -> Option<TriangleHit> {
+        self.bvh.hit(ray, clip, |triangle, clip| {
+            let [a, b, c] = self.vertices(triangle);
+            intersect_triangle(ray, clip, a, b, c).map(|(t, barycentric)| TriangleHit {
+                t,
+                triangle,
+                barycentric,
+            })
+        })
+    }
+
+    /// Normal of the triangle's plane at `hit`, following the winding order.
+    pub fn face_normal(&self, hit: &TriangleHit) -> Vec3A {
+        let [a, b, c] = self.vertices(hit.triangle);
+        (b - a).cross(c - a).normalize()
+    }
+
+    /// Normal at `hit`, interpolated between the vertex normals if the mesh
+    /// has any.
+    pub fn normal(&self, hit: &TriangleHit) -> Vec3A {
+        if self.normals.is_empty() {
+            return self.face_normal(hit);
+        }
+
+        let [a, b, c] =
+            self.indices[hit.triangle as usize].map(|index| self.normals[index as usize]);
+        let normal = interpolate(a, b, c, hit.barycentric).normalize_or_zero();
+        if normal == Vec3A::ZERO {
+            self.face_normal(hit)
+        } else {
+            normal
+        }
+    }
+
+    /// Texture coordinates at `hit`, if the mesh has any.
+    pub fn uv(&self, hit: &TriangleHit) -> Option<Vec2> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+
+        let [a, b, c] = self.indices[hit.triangle as usize].map(|index| self.uvs[index as usize]);
+        let Vec2 { x: u, y: v } = hit.barycentric;
+        Some(a * (1.0 - u - v) + b * u + c * v)
+    }
+
+    /// Directions along the triangle at `hit` in which its texture
+    /// coordinates grow, or those of the barycentric coordinates if the
+    /// mesh has none or they don't span the triangle.
+    pub fn tangents(&self, hit: &TriangleHit) -> (Vec3A, Vec3A) {
+        let [a, b, c] = self.vertices(hit.triangle);
+        let (edge1, edge2) = (b - a, c - a);
+        if self.uvs.is_empty() {
+            return (edge1, edge2);
+        }
+
+        let [uv_a, uv_b, uv_c] =
+            self.indices[hit.triangle as usize].map(|index| self.uvs[index as usize]);
+        let (duv1, duv2) = (uv_b - uv_a, uv_c - uv_a);
+        let determinant = duv1.perp_dot(duv2);
+        if determinant.abs() < 1e-12 {
+            return (edge1, edge2);
+        }
+
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+        let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+        (tangent, bitangent)
+    }
+}
+
+fn interpolate(a: Vec3A, b: Vec3A, c: Vec3A, barycentric: Vec2) -> Vec3A {
+    let Vec2 { x: u, y: v } = barycentric;
+    a * (1.0 - u - v) + b * u + c * v
+}
+
+/// Möller-Trumbore ray-triangle intersection, returning the distance and
+/// the barycentric coordinates of the hit.
+pub(super) fn intersect_triangle(
+    ray: &Ray,
+    clip: &Clip,
+    a: Vec3A,
+    b: Vec3A,
+    c: Vec3A,
+) -> Option<(f32, Vec2)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    // relative to the size of the triangle and the length of the direction,
+    // which is unnormalized in the local space of scaled objects
+    if determinant.abs() <= f32::EPSILON * edge1.length() * p.length() {
+        return None;
+    }
+
+    let inverse = determinant.recip();
+    let offset = ray.origin - a;
+    let u = offset.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = offset.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inverse;
+    (t >= clip.min && t <= clip.max).then_some((t, Vec2::new(u, v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Affine3A, Vec3};
+
+    #[test]
+    fn deserialize() {
+        let mesh: TriangleMesh = serde_json::from_str(
+            r#"{"positions": [[0, 0, 0], [1, 0, 0], [0, 1, 0]], "normals": [], "uvs": [], "indices": [[0, 1, 2]]}"#,
+        )
+        .unwrap();
+        assert_eq!(mesh.len(), 1);
+        // the hierarchy is rebuilt
+        let ray = Ray::new(Vec3A::new(0.2, 0.2, 1.0), Vec3A::NEG_Z);
+        let clip = Clip {
+            min: 0.0,
+            max: 10.0,
+        };
+        assert!(mesh.hit(&ray, &clip).is_some());
+
+        let error = serde_json::from_str::<TriangleMesh>(
+            r#"{"positions": [[0, 0, 0], [1, 0, 0], [0, 1, 0]], "normals": [], "uvs": [], "indices": [[0, 1, 3]]}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("out of bounds"));
+    }
+
+    #[test]
+    fn scaled_down() {
+        // a tiny mesh blown up by its transform sees rays with a tiny
+        // direction in local space
+        let size = 1e-4;
+        let mesh = TriangleMesh::new(
+            vec![Vec3A::ZERO, Vec3A::X * size, Vec3A::Y * size],
+            vec![],
+            vec![],
+            vec![[0, 1, 2]],
+        );
+        let inverse = Affine3A::from_scale(Vec3::splat(1e5)).inverse();
+        let ray = Ray::new(Vec3A::new(2.0, 2.0, 10.0), Vec3A::NEG_Z);
+        let local = Ray {
+            origin: inverse.transform_point3a(ray.origin),
+            direction: inverse.transform_vector3a(ray.direction),
+        };
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let hit = mesh.hit(&local, &clip).expect("expected a hit");
+        assert!((hit.t - 10.0).abs() < 1e-3, "{}", hit.t);
+    }
+}
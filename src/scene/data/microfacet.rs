@@ -0,0 +1,244 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::Vec3A;
+use rand::prelude::*;
+use rand_distr::Uniform;
+
+use crate::math::Vec3Ext;
+
+/// Fraction of the light leaving along `outgoing` that is reflected off a
+/// surface with the given normal, which is all of it past the critical
+/// angle.
+pub(super) fn dielectric_fresnel(outgoing: Vec3A, normal: Vec3A, ior: f32) -> f32 {
+    let cos_theta = outgoing.dot(normal).min(1.0);
+    if ior * ior * (1.0 - cos_theta * cos_theta) > 1.0 {
+        1.0
+    } else {
+        (-outgoing).fresnel(normal, ior)
+    }
+}
+
+/// The GGX, or Trowbridge-Reitz, distribution of microfacet normals around
+/// the shading normal of a rough surface, with Smith's height-correlated
+/// masking and shadowing.
+///
+/// Directions all point away from the surface, and `outgoing` is towards
+/// the viewer. Scattering is returned along with the density of sampling
+/// it through the normals visible from `outgoing`, so that their ratio is
+/// just the part of the light that isn't shadowed.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Ggx {
+    alpha: f32,
+    x_axis: Vec3A,
+    y_axis: Vec3A,
+    normal: Vec3A,
+}
+
+impl Ggx {
+    /// Below this width the distribution is too sharp to sample or evaluate
+    /// reliably, and the surface is treated as perfectly smooth.
+    const MIN_ALPHA: f32 = 1e-3;
+
+    /// The distribution for a perceptual `roughness`, or `None` if the
+    /// surface is smooth.
+    pub(super) fn new(roughness: f32, normal: Vec3A) -> Option<Self> {
+        let alpha = roughness * roughness;
+        if alpha < Self::MIN_ALPHA {
+            return None;
+        }
+
+        let normal = normal.normalize();
+        let (x_axis, y_axis) = normal.any_orthonormal_pair();
+        Some(Self {
+            alpha,
+            x_axis,
+            y_axis,
+            normal,
+        })
+    }
+
+    fn local(&self, v: Vec3A) -> Vec3A {
+        Vec3A::new(v.dot(self.x_axis), v.dot(self.y_axis), v.dot(self.normal))
+    }
+
+    fn world(&self, v: Vec3A) -> Vec3A {
+        self.x_axis * v.x + self.y_axis * v.y + self.normal * v.z
+    }
+
+    /// Density of microfacets facing `m`, per unit of projected area.
+    pub(super) fn distribution(&self, m: Vec3A) -> f32 {
+        let cos_theta = m.dot(self.normal);
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let alpha2 = self.alpha * self.alpha;
+        let k = cos_theta * cos_theta * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * k * k)
+    }
+
+    fn lambda(&self, v: Vec3A) -> f32 {
+        let cos2 = v.dot(self.normal).powi(2).max(1e-12);
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) * 0.5
+    }
+
+    /// Fraction of the microfacets visible from `v`.
+    pub(super) fn masking(&self, v: Vec3A) -> f32 {
+        (1.0 + self.lambda(v)).recip()
+    }
+
+    /// Fraction of the microfacets visible from both `a` and `b`.
+    pub(super) fn masking_shadowing(&self, a: Vec3A, b: Vec3A) -> f32 {
+        (1.0 + self.lambda(a) + self.lambda(b)).recip()
+    }
+
+    /// Density of the normals visible from `outgoing`.
+    pub(super) fn visible_distribution(&self, outgoing: Vec3A, m: Vec3A) -> f32 {
+        let cos_theta = outgoing.dot(self.normal).max(1e-6);
+        self.masking(outgoing) * outgoing.dot(m).max(0.0) * self.distribution(m) / cos_theta
+    }
+
+    /// Samples a normal visible from `outgoing`, as described in "Sampling
+    /// the GGX Distribution of Visible Normals" by Heitz.
+    pub(super) fn sample_visible<R: Rng + ?Sized>(&self, rng: &mut R, outgoing: Vec3A) -> Vec3A {
+        let r1 = rng.sample::<f32, _>(Uniform::new_inclusive(0.0, 1.0));
+        let r2 = rng.sample::<f32, _>(Uniform::new(0.0, TAU));
+
+        // stretch the view so the distribution becomes a hemisphere
+        let v = self.local(outgoing);
+        let v = Vec3A::new(self.alpha * v.x, self.alpha * v.y, v.z.max(1e-6)).normalize();
+
+        let len2 = v.x * v.x + v.y * v.y;
+        let t1 = if len2 > 0.0 {
+            Vec3A::new(-v.y, v.x, 0.0) / len2.sqrt()
+        } else {
+            Vec3A::X
+        };
+        let t2 = v.cross(t1);
+
+        // a point on the disk, squeezed onto the part seen from `v`
+        let (p1, p2) = (r1.sqrt() * r2.cos(), r1.sqrt() * r2.sin());
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * p2;
+        let n = t1 * p1 + t2 * p2 + v * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        let m = Vec3A::new(self.alpha * n.x, self.alpha * n.y, n.z.max(1e-6)).normalize();
+        self.world(m)
+    }
+
+    /// Scattering times the cosine, and sampling density, of a reflection
+    /// off a perfect conductor from `incoming` to `outgoing`.
+    pub(super) fn reflection(&self, outgoing: Vec3A, incoming: Vec3A) -> (f32, f32) {
+        let cos_o = outgoing.dot(self.normal).max(1e-6);
+        if incoming.dot(self.normal) <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        let m = (outgoing + incoming).normalize();
+        let value =
+            self.distribution(m) * self.masking_shadowing(outgoing, incoming) / (4.0 * cos_o);
+        let pdf = self.visible_distribution(outgoing, m) / (4.0 * outgoing.dot(m).max(1e-6));
+        (value, pdf)
+    }
+
+    /// Scattering times the cosine, and sampling density, of light from
+    /// `incoming` reflected or refracted through a dielectric with the
+    /// relative index of refraction `ior`, choosing between them by the
+    /// Fresnel term.
+    pub(super) fn dielectric(&self, outgoing: Vec3A, incoming: Vec3A, ior: f32) -> (f32, f32) {
+        let cos_o = outgoing.dot(self.normal).max(1e-6);
+        let reflected = incoming.dot(self.normal) > 0.0;
+
+        let m = if reflected {
+            outgoing + incoming
+        } else {
+            -(outgoing * ior + incoming)
+        };
+        let m = m.normalize_or_zero();
+        let m = if m.dot(self.normal) < 0.0 { -m } else { m };
+
+        let (cos_om, cos_im) = (outgoing.dot(m), incoming.dot(m));
+        if cos_om <= 0.0 || (cos_im > 0.0) != reflected {
+            return (0.0, 0.0);
+        }
+
+        let fresnel = dielectric_fresnel(outgoing, m, ior);
+        let d = self.distribution(m);
+        let g = self.masking_shadowing(outgoing, incoming);
+        let visible = self.visible_distribution(outgoing, m);
+        if reflected {
+            let value = fresnel * d * g / (4.0 * cos_o);
+            let pdf = fresnel * visible / (4.0 * cos_om);
+            (value, pdf)
+        } else {
+            // the change from the refracted direction to the microfacet
+            // normal
+            let denom = (ior * cos_om + cos_im).powi(2).max(1e-12);
+            let jacobian = -cos_im / denom;
+            let value = (1.0 - fresnel) * d * g * cos_om * jacobian / cos_o;
+            let pdf = (1.0 - fresnel) * visible * jacobian;
+            (value, pdf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+
+    use super::*;
+    use crate::math::distr::UnitSphere;
+
+    #[test]
+    fn ggx() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let ggx = Ggx::new(0.6, Vec3A::Z).unwrap();
+        let outgoing = Vec3A::new(0.8, 0.1, 0.5).normalize();
+        let count = 200_000;
+
+        for ior in [None, Some(1.5f32.recip()), Some(1.5)] {
+            let evaluate = |incoming| match ior {
+                Some(ior) => ggx.dielectric(outgoing, incoming, ior),
+                None => ggx.reflection(outgoing, incoming),
+            };
+
+            // sampled directions have the density they are evaluated with,
+            // and are weighted by the part of the light that isn't
+            // shadowed. Some of the samples end up on the wrong side of
+            // the surface and are lost
+            let mut kept = 0;
+            for _ in 0..count {
+                let m = ggx.sample_visible(&mut rng, outgoing);
+                let (incoming, reflected) = match ior {
+                    Some(ior) if !rng.gen_bool(dielectric_fresnel(outgoing, m, ior) as _) => {
+                        ((-outgoing).refract(m, ior), false)
+                    }
+                    _ => ((-outgoing).reflect(m), true),
+                };
+                if (incoming.z > 0.0) != reflected {
+                    continue;
+                }
+
+                kept += 1;
+                let (value, pdf) = evaluate(incoming);
+                let weight = ggx.masking_shadowing(outgoing, incoming) / ggx.masking(outgoing);
+                assert!(
+                    (value / pdf - weight).abs() < 1e-3,
+                    "{ior:?}: {value} / {pdf}"
+                );
+            }
+            let kept = kept as f32 / count as f32;
+
+            // which is what the density misses over the whole sphere,
+            // checked with uniformly spread directions
+            let total = (0..count)
+                .map(|_| evaluate(rng.sample(UnitSphere)).1)
+                .sum::<f32>()
+                * 4.0
+                * PI
+                / count as f32;
+            assert!((total - kept).abs() < 0.03, "{ior:?}: {total} != {kept}");
+        }
+    }
+}
@@ -1,9 +1,24 @@
 use serde::{Deserialize, Serialize};
 
+mod deflection_map;
+mod dispersion;
+mod height_map;
 mod material;
+mod mesh;
+mod microfacet;
+mod normal_map;
+mod principled;
+mod texture;
 mod volume;
 
+pub use self::deflection_map::*;
+pub use self::dispersion::*;
+pub use self::height_map::*;
 pub use self::material::*;
+pub use self::mesh::*;
+pub use self::normal_map::*;
+pub use self::principled::*;
+pub use self::texture::*;
 pub use self::volume::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -41,6 +56,34 @@ impl Data {
             _ => None,
         }
     }
+
+    pub fn as_mesh(&self) -> Option<&TriangleMesh> {
+        match self.inner() {
+            DataKind::Mesh(mesh) => Some(mesh),
+            _ => None,
+        }
+    }
+
+    pub fn as_deflection_map(&self) -> Option<&DeflectionMap> {
+        match self.inner() {
+            DataKind::DeflectionMap(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_height_map(&self) -> Option<&HeightMap> {
+        match self.inner() {
+            DataKind::HeightMap(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_texture(&self) -> Option<&Texture> {
+        match self.inner() {
+            DataKind::Texture(texture) => Some(texture),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +91,10 @@ impl Data {
 pub enum DataKind {
     Material(Material),
     Volume(Volume),
+    DeflectionMap(DeflectionMap),
+    Mesh(TriangleMesh),
+    HeightMap(HeightMap),
+    Texture(Texture),
 }
 
 impl From<Material> for DataKind {
@@ -61,3 +108,27 @@ impl From<Volume> for DataKind {
         Self::Volume(volume)
     }
 }
+
+impl From<DeflectionMap> for DataKind {
+    fn from(map: DeflectionMap) -> Self {
+        Self::DeflectionMap(map)
+    }
+}
+
+impl From<TriangleMesh> for DataKind {
+    fn from(mesh: TriangleMesh) -> Self {
+        Self::Mesh(mesh)
+    }
+}
+
+impl From<HeightMap> for DataKind {
+    fn from(map: HeightMap) -> Self {
+        Self::HeightMap(map)
+    }
+}
+
+impl From<Texture> for DataKind {
+    fn from(texture: Texture) -> Self {
+        Self::Texture(texture)
+    }
+}
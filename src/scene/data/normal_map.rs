@@ -0,0 +1,154 @@
+use glam::{Vec2, Vec3A};
+use serde::{Deserialize, Serialize};
+
+use super::{DataRef, Texel};
+use crate::color::LinearRgb;
+use crate::tracer::Manifold;
+
+/// Distance in texture coordinates over which bump maps take their slopes.
+const BUMP_STEP: f32 = 1e-3;
+
+/// Shading normals are tilted back until they face the viewer at least
+/// this much, so that they don't reflect light from below the surface.
+const MIN_FACING: f32 = 0.05;
+
+/// Surface detail that tilts the shading normal of a material, laid out
+/// along the tangents of the surface.
+///
+/// Textures holding normals or heights should be loaded without sRGB
+/// decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NormalMap {
+    /// Normals stored as colors, with red along u, green up the image and
+    /// blue out of the surface, as in OpenGL. `strength` scales the tilt.
+    Normal { texture: DataRef, strength: f32 },
+    /// Heights stored as brightness, whose slopes along u and v times
+    /// `strength` tilt the normal.
+    Bump { texture: DataRef, strength: f32 },
+}
+
+impl NormalMap {
+    pub fn normal(texture: DataRef, strength: f32) -> Self {
+        Self::Normal { texture, strength }
+    }
+
+    pub fn bump(texture: DataRef, strength: f32) -> Self {
+        Self::Bump { texture, strength }
+    }
+
+    /// Shading normal at `manifold`, which like its normal faces the
+    /// incoming ray. Surfaces without tangents keep their normal.
+    pub fn shading_normal(&self, manifold: &Manifold) -> Vec3A {
+        let normal = manifold.normal;
+        let tangent = manifold.tangent.reject_from_normalized(normal);
+        let bitangent = manifold.bitangent.reject_from_normalized(normal);
+        let (tangent, bitangent) = (tangent.normalize_or_zero(), bitangent.normalize_or_zero());
+        if tangent == Vec3A::ZERO || bitangent == Vec3A::ZERO {
+            return normal;
+        }
+
+        // how far the normal leans along the tangents, for each unit it
+        // rises out of the surface
+        let (tilt, rise) = match *self {
+            Self::Normal { texture, strength } => {
+                let color = sample(manifold, texture, manifold.uv);
+                let mapped = Vec3A::new(color.r, color.g, color.b) * 2.0 - 1.0;
+                // v grows down the image
+                (Vec2::new(mapped.x, -mapped.y) * strength, mapped.z)
+            }
+            Self::Bump { texture, strength } => {
+                let height = |offset: Vec2| {
+                    f32::from_color(sample(manifold, texture, manifold.uv + offset * BUMP_STEP))
+                };
+                let slope = Vec2::new(
+                    height(Vec2::X) - height(Vec2::NEG_X),
+                    height(Vec2::Y) - height(Vec2::NEG_Y),
+                ) / (2.0 * BUMP_STEP);
+                (-slope * strength, 1.0)
+            }
+        };
+
+        // the map is laid out on the front of the surface, so seen from
+        // behind it leans the other way
+        let tilt = if manifold.face.is_back() { -tilt } else { tilt };
+        let shading = (normal * rise + tangent * tilt.x + bitangent * tilt.y).normalize_or_zero();
+        if shading == Vec3A::ZERO {
+            return normal;
+        }
+
+        let outgoing = -manifold.ray.direction.normalize();
+        let facing = shading.dot(outgoing);
+        if facing < MIN_FACING {
+            (shading + outgoing * (MIN_FACING - facing)).normalize()
+        } else {
+            shading
+        }
+    }
+}
+
+fn sample(manifold: &Manifold, texture: DataRef, uv: Vec2) -> LinearRgb {
+    manifold
+        .scene
+        .get_data(texture)
+        .as_texture()
+        .expect("expected texture data")
+        .sample(uv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Data, Material, Object, Plane, Scene, Texture};
+    use crate::tracer::{Clip, Ray};
+
+    #[test]
+    fn tilt() {
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::diffuse(LinearRgb::WHITE, 1.0)));
+        // heights rising along u, and normals that are flat or lean along u
+        let ramp = scene.add_data(Data::new(Texture::gradient(
+            LinearRgb::BLACK,
+            LinearRgb::WHITE,
+        )));
+        let flat = scene.add_data(Data::new(Texture::checker(
+            LinearRgb::new(0.5, 0.5, 1.0),
+            LinearRgb::new(0.5, 0.5, 1.0),
+            1.0,
+        )));
+        let leaning = scene.add_data(Data::new(Texture::checker(
+            LinearRgb::new(1.0, 0.5, 1.0),
+            LinearRgb::new(1.0, 0.5, 1.0),
+            1.0,
+        )));
+        let plane = scene.add_object(Object::new(Plane::new(material)));
+
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let hit = |origin: Vec3A, direction: Vec3A| {
+            let ray = Ray::new(origin, direction);
+            scene.get_object(plane).hit(&ray, &clip, &scene).unwrap()
+        };
+        let above = hit(Vec3A::new(0.5, 1.0, 0.5), Vec3A::NEG_Y);
+        let below = hit(Vec3A::new(0.5, -1.0, 0.5), Vec3A::Y);
+
+        // u runs along x on the plane, so the bump leans the normal back
+        // along -x, on either side
+        let bump = NormalMap::bump(ramp, 1.0);
+        let expected = Vec3A::new(-1.0, 1.0, 0.0).normalize();
+        assert!(bump.shading_normal(&above).abs_diff_eq(expected, 1e-2));
+        assert!(bump.shading_normal(&below).abs_diff_eq(-expected, 1e-2));
+
+        let normal = NormalMap::normal(flat, 1.0);
+        assert!(normal.shading_normal(&above).abs_diff_eq(Vec3A::Y, 1e-4));
+        let normal = NormalMap::normal(leaning, 1.0);
+        let expected = Vec3A::new(1.0, 1.0, 0.0).normalize();
+        assert!(normal.shading_normal(&above).abs_diff_eq(expected, 1e-4));
+
+        // seen at a grazing angle the normal is tilted back to face the ray
+        let grazing = hit(Vec3A::new(-0.5, 0.1, 0.5), Vec3A::new(1.0, -0.1, 0.0));
+        let shading = normal.shading_normal(&grazing);
+        assert!(shading.dot(-grazing.ray.direction.normalize()) >= MIN_FACING - 1e-4);
+    }
+}
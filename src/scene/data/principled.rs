@@ -0,0 +1,461 @@
+use std::f32::consts::FRAC_1_PI;
+
+use glam::{Vec2, Vec3A};
+use rand::prelude::*;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::microfacet::{dielectric_fresnel, Ggx};
+use super::{Material, NormalMap, Param};
+use crate::color::LinearRgb;
+use crate::math::distr::Cosine;
+use crate::math::Vec3Ext;
+use crate::scene::Scene;
+use crate::tracer::Face;
+
+/// Below this the glossy lobes would have to be sampled as mirrors, which
+/// doesn't mix with the rest of the material.
+const MIN_ROUGHNESS: f32 = 0.04;
+
+/// The clear coat is always glossy.
+const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+
+/// Reflectance of the clear coat at normal incidence, that of an index of
+/// refraction of 1.5.
+const CLEARCOAT_REFLECTANCE: f32 = 0.04;
+
+/// One material blending diffuse, metallic and glass-like scattering, with
+/// a clear coat and sheen on top, after the principled model by Burley.
+///
+/// Any field left out of a scene file takes its default. Other materials
+/// can be given in place of the fields, and are converted with
+/// `from_material`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Principled {
+    pub base_color: Param<LinearRgb>,
+    /// Blends from a dielectric to a conductor tinted by the base color.
+    pub metallic: Param<f32>,
+    pub roughness: Param<f32>,
+    /// Reflectance of the dielectric at normal incidence, scaled so that
+    /// 0.5 is the 4% of an index of refraction of 1.5. The index of
+    /// refraction for transmission follows from it.
+    pub specular: Param<f32>,
+    /// Blends the dielectric from diffuse to glass tinted by the base color.
+    pub transmission: Param<f32>,
+    pub clearcoat: Param<f32>,
+    /// Extra reflection at grazing angles, as seen on cloth.
+    pub sheen: Param<f32>,
+    pub emission: Param<LinearRgb>,
+    /// Scales the emission, which textures can only give up to 1.
+    pub emission_strength: f32,
+    pub normal_map: Option<NormalMap>,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: LinearRgb::splat(0.8).into(),
+            metallic: 0.0.into(),
+            roughness: 0.5.into(),
+            specular: 0.5.into(),
+            transmission: 0.0.into(),
+            clearcoat: 0.0.into(),
+            sheen: 0.0.into(),
+            emission: LinearRgb::BLACK.into(),
+            emission_strength: 1.0,
+            normal_map: None,
+        }
+    }
+}
+
+/// The parameters of a `Principled` material read at one point of a
+/// surface.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Surface {
+    pub(super) base_color: LinearRgb,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    transmission: f32,
+    clearcoat: f32,
+    sheen: f32,
+}
+
+/// How much each lobe adds to the scattering, and how likely it is to be
+/// sampled.
+#[derive(Debug, Clone, Copy, Default)]
+struct Lobes {
+    diffuse: (f32, f32),
+    specular: (f32, f32),
+    transmission: (f32, f32),
+    clearcoat: (f32, f32),
+}
+
+impl Principled {
+    /// The closest principled material to `material`, or `None` if it can't
+    /// be expressed as one.
+    pub fn from_material(material: &Material) -> Option<Self> {
+        let black = Self {
+            base_color: LinearRgb::BLACK.into(),
+            specular: 0.0.into(),
+            ..Default::default()
+        };
+        match *material {
+            Material::Flat { albedo } => Some(Self {
+                emission: albedo,
+                ..black
+            }),
+            Material::Diffuse {
+                albedo,
+                roughness,
+                normal_map,
+            } => Some(Self {
+                base_color: albedo,
+                roughness,
+                specular: 0.0.into(),
+                normal_map,
+                ..Default::default()
+            }),
+            Material::Metallic {
+                albedo,
+                roughness,
+                normal_map,
+            } => Some(Self {
+                base_color: albedo,
+                metallic: 1.0.into(),
+                roughness,
+                normal_map,
+                ..Default::default()
+            }),
+            Material::Glass {
+                albedo,
+                roughness,
+                ior,
+                normal_map,
+            } => {
+                // the lobes don't disperse, so they take the reference index
+                let ior = ior.at(None);
+                Some(Self {
+                    base_color: albedo,
+                    roughness,
+                    normal_map,
+                    specular: (((ior - 1.0) / (ior + 1.0)).powi(2) / 0.08).into(),
+                    transmission: 1.0.into(),
+                    ..Default::default()
+                })
+            }
+            Material::Emissive { albedo, intensity } => Some(Self {
+                emission: albedo,
+                emission_strength: intensity,
+                ..black
+            }),
+            Material::Principled(principled) => Some(principled),
+            Material::Blackbody { .. } => None,
+        }
+    }
+
+    pub(super) fn at(&self, scene: &Scene, uv: Vec2) -> Surface {
+        Surface {
+            base_color: self.base_color.get(scene, uv),
+            metallic: self.metallic.get(scene, uv),
+            roughness: self.roughness.get(scene, uv),
+            specular: self.specular.get(scene, uv),
+            transmission: self.transmission.get(scene, uv),
+            clearcoat: self.clearcoat.get(scene, uv),
+            sheen: self.sheen.get(scene, uv),
+        }
+    }
+}
+
+impl Surface {
+    /// Reflectance at normal incidence of the dielectric and metallic
+    /// specular lobe.
+    fn reflectance(&self) -> LinearRgb {
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        LinearRgb::splat(0.08 * self.specular) * (1.0 - metallic) + self.base_color * metallic
+    }
+
+    /// Index of refraction of the transmissive part, inside over outside.
+    fn ior(&self) -> f32 {
+        let r0 = (0.08 * self.specular).max(0.0).sqrt().min(0.99);
+        ((1.0 + r0) / (1.0 - r0)).max(1.01)
+    }
+
+    fn lobes(&self, face: Face) -> Lobes {
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        let transmission = self.transmission.clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.clamp(0.0, 1.0);
+        let mean = |color: LinearRgb| (color.r + color.g + color.b) / 3.0;
+
+        // only the glass is seen from inside
+        if face.is_back() && transmission > 0.0 {
+            return Lobes {
+                transmission: (1.0, 1.0),
+                ..Default::default()
+            };
+        }
+
+        let diffuse = (1.0 - metallic) * (1.0 - transmission);
+        let transmission = (1.0 - metallic) * transmission;
+        let specular = 1.0 - transmission;
+        let clearcoat = 0.25 * clearcoat;
+
+        // a rough guess of how much light each lobe reflects
+        let reflectance = mean(self.reflectance());
+        let weights = [
+            diffuse * mean(self.base_color).max(self.sheen),
+            if reflectance > 0.0 {
+                specular * (0.25 + 0.75 * reflectance)
+            } else {
+                0.0
+            },
+            transmission,
+            clearcoat,
+        ];
+        let total: f32 = weights.iter().sum();
+        let [p_diffuse, p_specular, p_transmission, p_clearcoat] = if total > 0.0 {
+            weights.map(|weight| weight / total)
+        } else {
+            [0.0; 4]
+        };
+
+        Lobes {
+            diffuse: (diffuse, p_diffuse),
+            specular: (specular, p_specular),
+            transmission: (transmission, p_transmission),
+            clearcoat: (clearcoat, p_clearcoat),
+        }
+    }
+
+    fn relative_ior(&self, face: Face) -> f32 {
+        if face.is_back() {
+            self.ior()
+        } else {
+            self.ior().recip()
+        }
+    }
+
+    /// Whether there is any scattering to sample.
+    pub(super) fn scatters(&self, face: Face) -> bool {
+        let lobes = self.lobes(face);
+        lobes.diffuse.1 + lobes.specular.1 + lobes.transmission.1 + lobes.clearcoat.1 > 0.0
+    }
+
+    /// Probability of sampling the diffuse lobe, which light sampling can
+    /// stand in for.
+    pub(super) fn diffuse_probability(&self, face: Face) -> f32 {
+        self.lobes(face).diffuse.1
+    }
+
+    /// Samples a direction light can come from to leave towards `outgoing`.
+    pub(super) fn sample<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        normal: Vec3A,
+        face: Face,
+        outgoing: Vec3A,
+    ) -> Vec3A {
+        let lobes = self.lobes(face);
+        let ggx = Ggx::new(self.roughness.max(MIN_ROUGHNESS), normal)
+            .expect("roughness is above the minimum");
+
+        let x = rng.gen::<f32>();
+        if x < lobes.diffuse.1 {
+            rng.sample(Cosine::new(normal.into()))
+        } else if x < lobes.diffuse.1 + lobes.specular.1 {
+            (-outgoing).reflect(ggx.sample_visible(rng, outgoing))
+        } else if x < lobes.diffuse.1 + lobes.specular.1 + lobes.transmission.1 {
+            let ior = self.relative_ior(face);
+            let m = ggx.sample_visible(rng, outgoing);
+            let fresnel = dielectric_fresnel(outgoing, m, ior);
+            if rng.gen_bool(fresnel.clamp(0.0, 1.0) as _) {
+                (-outgoing).reflect(m)
+            } else {
+                (-outgoing).refract(m, ior)
+            }
+        } else {
+            let coat = Ggx::new(CLEARCOAT_ROUGHNESS, normal).expect("the clear coat is rough");
+            (-outgoing).reflect(coat.sample_visible(rng, outgoing))
+        }
+    }
+
+    /// The scattering function times the cosine, for light from `incoming`
+    /// leaving towards `outgoing`, and the density `sample` picks `incoming`
+    /// with.
+    pub(super) fn evaluate(
+        &self,
+        normal: Vec3A,
+        face: Face,
+        outgoing: Vec3A,
+        incoming: Vec3A,
+    ) -> (LinearRgb, f32) {
+        let lobes = self.lobes(face);
+        let roughness = self.roughness.max(MIN_ROUGHNESS);
+        let ggx = Ggx::new(roughness, normal).expect("roughness is above the minimum");
+        let schlick = |r0: f32, cos_theta: f32| r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5);
+
+        let mut value = LinearRgb::BLACK;
+        let mut pdf = 0.0;
+
+        let cos_i = incoming.dot(normal);
+        if cos_i > 0.0 {
+            let half = (outgoing + incoming).normalize();
+            let cos_d = incoming.dot(half).clamp(0.0, 1.0);
+            let cos_o = outgoing.dot(normal).clamp(0.0, 1.0);
+
+            let (weight, probability) = lobes.diffuse;
+            if weight > 0.0 {
+                // Burley's diffuse, which brightens towards grazing angles
+                // on rough surfaces
+                let f90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+                let retro = (1.0 + (f90 - 1.0) * (1.0 - cos_i).powi(5))
+                    * (1.0 + (f90 - 1.0) * (1.0 - cos_o).powi(5));
+                let sheen = self.sheen * (1.0 - cos_d).powi(5);
+                value += (self.base_color * retro * FRAC_1_PI + LinearRgb::splat(sheen))
+                    * (weight * cos_i);
+                pdf += probability * cos_i * FRAC_1_PI;
+            }
+
+            let (weight, probability) = lobes.specular;
+            if weight > 0.0 {
+                let (scattering, density) = ggx.reflection(outgoing, incoming);
+                let cos_h = outgoing.dot(half).clamp(0.0, 1.0);
+                let r0 = self.reflectance();
+                let fresnel = LinearRgb::new(
+                    schlick(r0.r, cos_h),
+                    schlick(r0.g, cos_h),
+                    schlick(r0.b, cos_h),
+                );
+                value += fresnel * (weight * scattering);
+                pdf += probability * density;
+            }
+
+            let (weight, probability) = lobes.clearcoat;
+            if weight > 0.0 {
+                let coat = Ggx::new(CLEARCOAT_ROUGHNESS, normal).expect("the clear coat is rough");
+                let (scattering, density) = coat.reflection(outgoing, incoming);
+                let fresnel = schlick(CLEARCOAT_REFLECTANCE, outgoing.dot(half).clamp(0.0, 1.0));
+                value += LinearRgb::splat(weight * fresnel * scattering);
+                pdf += probability * density;
+            }
+        }
+
+        let (weight, probability) = lobes.transmission;
+        if weight > 0.0 {
+            let (scattering, density) = ggx.dielectric(outgoing, incoming, self.relative_ior(face));
+            value += self.base_color * (weight * scattering);
+            pdf += probability * density;
+        }
+
+        (value, pdf)
+    }
+}
+
+/// Reads either the fields of a principled material or another material to
+/// convert into one.
+pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Principled, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Fields(Principled),
+        Material(Material),
+    }
+
+    match Repr::deserialize(deserializer)? {
+        Repr::Fields(principled) => Ok(principled),
+        Repr::Material(material) => Principled::from_material(&material).ok_or_else(|| {
+            D::Error::custom(format!(
+                "{material:?} can't be made into a principled material"
+            ))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use rand::rngs::SmallRng;
+
+    use super::*;
+    use crate::math::distr::UnitSphere;
+
+    #[test]
+    fn sampling() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let principled = Principled {
+            base_color: LinearRgb::new(0.9, 0.5, 0.2).into(),
+            metallic: 0.3.into(),
+            roughness: 0.6.into(),
+            transmission: 0.4.into(),
+            sheen: 0.5.into(),
+            ..Default::default()
+        }
+        .at(&Scene::default(), Vec2::ZERO);
+        let outgoing = Vec3A::new(0.6, 0.2, 0.7).normalize();
+        let count = 200_000;
+
+        for face in [Face::Front, Face::Back] {
+            // the light scattered, estimated once through the material's
+            // own sampling and once with uniformly spread directions
+            let sampled = (0..count)
+                .map(|_| {
+                    let incoming = principled.sample(&mut rng, Vec3A::Z, face, outgoing);
+                    let (value, pdf) = principled.evaluate(Vec3A::Z, face, outgoing, incoming);
+                    if pdf > 0.0 {
+                        value.g / pdf
+                    } else {
+                        0.0
+                    }
+                })
+                .sum::<f32>()
+                / count as f32;
+            let uniform = (0..count)
+                .map(|_| {
+                    let incoming = rng.sample(UnitSphere);
+                    principled.evaluate(Vec3A::Z, face, outgoing, incoming).0.g
+                })
+                .sum::<f32>()
+                * 4.0
+                * PI
+                / count as f32;
+
+            assert!(sampled > 0.1 && sampled < 1.0, "{face:?}: {sampled}");
+            assert!(
+                (sampled - uniform).abs() < 0.03,
+                "{face:?}: {sampled} != {uniform}"
+            );
+        }
+    }
+
+    #[test]
+    fn conversion() {
+        let material: Material = serde_json::from_str(
+            r#"{"Principled": {"Glass": {"albedo": {"r": 1, "g": 1, "b": 1}, "roughness": 0.2, "ior": 1.5}}}"#,
+        )
+        .unwrap();
+        let Material::Principled(principled) = material else {
+            panic!("{material:?} isn't principled");
+        };
+        let surface = principled.at(&Scene::default(), Vec2::ZERO);
+        assert_eq!(surface.transmission, 1.0);
+        assert!((surface.specular - 0.5).abs() < 1e-4);
+        assert!((surface.ior() - 1.5).abs() < 1e-3);
+
+        let material: Material =
+            serde_json::from_str(r#"{"Principled": {"metallic": 1.0, "roughness": 0.1}}"#).unwrap();
+        assert!(matches!(
+            material,
+            Material::Principled(Principled { metallic, .. }) if metallic == Param::Constant(1.0)
+        ));
+
+        assert!(serde_json::from_str::<Material>(
+            r#"{"Principled": {"Blackbody": {"temperature": 5000, "intensity": 1}}}"#
+        )
+        .is_err());
+    }
+}
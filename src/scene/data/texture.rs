@@ -0,0 +1,410 @@
+use std::f32::consts::{SQRT_2, TAU};
+
+use anyhow::{ensure, Error, Result};
+use glam::Vec2;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use super::DataRef;
+use crate::color::LinearRgb;
+use crate::math::Interpolate;
+use crate::scene::Scene;
+use crate::tracer::Manifold;
+
+/// Colors looked up by texture coordinates, which run from `(0, 0)` at the
+/// top left of an image to `(1, 1)` at its bottom right.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Texture {
+    Image(ImageTexture),
+    /// Squares alternating between `even` and `odd`, `scale` of them along
+    /// each unit of the coordinates.
+    Checker {
+        even: LinearRgb,
+        odd: LinearRgb,
+        scale: f32,
+    },
+    /// A blend from `start` at `u = 0` to `end` at `u = 1`, repeating.
+    Gradient {
+        start: LinearRgb,
+        end: LinearRgb,
+    },
+    /// Perlin noise blending between `low` and `high`, with `scale` features
+    /// along each unit of the coordinates. Each octave adds details twice as
+    /// fine at half the strength.
+    Perlin {
+        low: LinearRgb,
+        high: LinearRgb,
+        scale: f32,
+        octaves: u32,
+        seed: u32,
+    },
+    /// Worley noise, blending from `low` at the points scattered over the
+    /// coordinates to `high` at a distance between them, with `scale`
+    /// points along each unit of the coordinates.
+    Worley {
+        low: LinearRgb,
+        high: LinearRgb,
+        scale: f32,
+        seed: u32,
+    },
+}
+
+impl Texture {
+    pub fn checker(even: LinearRgb, odd: LinearRgb, scale: f32) -> Self {
+        Self::Checker { even, odd, scale }
+    }
+
+    pub fn gradient(start: LinearRgb, end: LinearRgb) -> Self {
+        Self::Gradient { start, end }
+    }
+
+    pub fn perlin(low: LinearRgb, high: LinearRgb, scale: f32, octaves: u32) -> Self {
+        Self::Perlin {
+            low,
+            high,
+            scale,
+            octaves,
+            seed: 0,
+        }
+    }
+
+    pub fn worley(low: LinearRgb, high: LinearRgb, scale: f32) -> Self {
+        Self::Worley {
+            low,
+            high,
+            scale,
+            seed: 0,
+        }
+    }
+
+    pub fn sample(&self, uv: Vec2) -> LinearRgb {
+        match *self {
+            Self::Image(ref image) => image.sample(uv),
+            Self::Checker { even, odd, scale } => {
+                let cell = (uv * scale).floor();
+                if (cell.x + cell.y).rem_euclid(2.0) < 1.0 {
+                    even
+                } else {
+                    odd
+                }
+            }
+            Self::Gradient { start, end } => start.lerp(end, uv.x.rem_euclid(1.0)),
+            Self::Perlin {
+                low,
+                high,
+                scale,
+                octaves,
+                seed,
+            } => {
+                let (mut sum, mut total) = (0.0, 0.0);
+                let mut amplitude = 1.0;
+                let mut position = uv * scale;
+                for octave in 0..octaves.max(1) {
+                    sum += amplitude * perlin(position, seed.wrapping_add(octave));
+                    total += amplitude;
+                    amplitude *= 0.5;
+                    position *= 2.0;
+                }
+                low.lerp(high, (0.5 + 0.5 * sum / total).clamp(0.0, 1.0))
+            }
+            Self::Worley {
+                low,
+                high,
+                scale,
+                seed,
+            } => low.lerp(high, worley(uv * scale, seed)),
+        }
+    }
+}
+
+/// How coordinates outside of `[0, 1]` are brought back into it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    fn index(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            Self::Repeat => i.rem_euclid(size),
+            Self::Clamp => i.clamp(0, size - 1),
+            Self::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// An image filtered bilinearly between its pixels, stored row by row from
+/// the top.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ImageBuffer")]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<LinearRgb>,
+    pub wrap: Wrap,
+}
+
+#[derive(Deserialize)]
+struct ImageBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<LinearRgb>,
+    wrap: Wrap,
+}
+
+impl TryFrom<ImageBuffer> for ImageTexture {
+    type Error = Error;
+
+    fn try_from(buffer: ImageBuffer) -> Result<Self> {
+        let texture = Self::try_new(buffer.width, buffer.height, buffer.pixels)?;
+        Ok(texture.with_wrap(buffer.wrap))
+    }
+}
+
+impl ImageTexture {
+    /// Creates a texture of `width` by `height` pixels.
+    ///
+    /// Panics if the pixels don't fit the size, see `try_new`.
+    pub fn new(width: usize, height: usize, pixels: Vec<LinearRgb>) -> Self {
+        Self::try_new(width, height, pixels).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Creates a texture of `width` by `height` pixels, or fails if it's
+    /// empty or `pixels` has the wrong size.
+    pub fn try_new(width: usize, height: usize, pixels: Vec<LinearRgb>) -> Result<Self> {
+        ensure!(width >= 1 && height >= 1, "texture has no pixels");
+        ensure!(pixels.len() == width * height, "texture has the wrong size");
+        Ok(Self {
+            width,
+            height,
+            pixels,
+            wrap: Wrap::default(),
+        })
+    }
+
+    /// Reads the pixels of `image`, decoding them from sRGB for colors, or
+    /// keeping them as they are for other data such as roughness.
+    pub fn from_image(image: &DynamicImage, srgb: bool) -> Self {
+        let image = image.to_rgb32f();
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b] = pixel.0;
+                if srgb {
+                    LinearRgb::from_srgb(r, g, b)
+                } else {
+                    LinearRgb::new(r, g, b)
+                }
+            })
+            .collect();
+        Self::new(image.width() as usize, image.height() as usize, pixels)
+    }
+
+    pub fn with_wrap(self, wrap: Wrap) -> Self {
+        Self { wrap, ..self }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> LinearRgb {
+        let x = self.wrap.index(x, self.width);
+        let y = self.wrap.index(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn sample(&self, uv: Vec2) -> LinearRgb {
+        // pixel centers lie half a pixel in from the edges
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(x0, y0).lerp(self.pixel(x0 + 1, y0), fx);
+        let bottom = self.pixel(x0, y0 + 1).lerp(self.pixel(x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+fn hash(x: i32, y: i32, seed: u32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ seed.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
+
+/// Gradient noise in about `[-1, 1]`, with a random gradient at each
+/// integer point.
+fn perlin(position: Vec2, seed: u32) -> f32 {
+    let cell = position.floor();
+    let offset = position - cell;
+    let (x, y) = (cell.x as i32, cell.y as i32);
+
+    let corner = |dx: i32, dy: i32| {
+        let angle = hash(x + dx, y + dy, seed) as f32 / u32::MAX as f32 * TAU;
+        let gradient = Vec2::new(angle.cos(), angle.sin());
+        gradient.dot(offset - Vec2::new(dx as f32, dy as f32))
+    };
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (u, v) = (fade(offset.x), fade(offset.y));
+
+    let bottom = corner(0, 0).lerp(corner(1, 0), u);
+    let top = corner(0, 1).lerp(corner(1, 1), u);
+    bottom.lerp(top, v) * SQRT_2
+}
+
+/// Distance to the nearest of the points scattered one to each integer
+/// cell, up to 1.
+fn worley(position: Vec2, seed: u32) -> f32 {
+    let cell = position.floor();
+    let mut nearest = f32::INFINITY;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let neighbor = cell + Vec2::new(dx as f32, dy as f32);
+            let h = hash(neighbor.x as i32, neighbor.y as i32, seed);
+            let point =
+                neighbor + Vec2::new((h & 0xffff) as f32 / 65535.0, (h >> 16) as f32 / 65535.0);
+            nearest = nearest.min(point.distance(position));
+        }
+    }
+    nearest.min(1.0)
+}
+
+/// A material parameter, either constant or read from a `Texture` at the
+/// texture coordinates of the surface.
+///
+/// Scene files give constants as they are, and textures as
+/// `{"texture": <data ref>}`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Param<T> {
+    Constant(T),
+    Texture { texture: DataRef },
+}
+
+/// Values a `Param` can take from the colors of a texture.
+pub trait Texel: Copy {
+    fn from_color(color: LinearRgb) -> Self;
+}
+
+impl Texel for LinearRgb {
+    fn from_color(color: LinearRgb) -> Self {
+        color
+    }
+}
+
+impl Texel for f32 {
+    /// The mean of the channels, which are all the same in grayscale maps.
+    fn from_color(color: LinearRgb) -> Self {
+        (color.r + color.g + color.b) / 3.0
+    }
+}
+
+impl<T: Texel> Param<T> {
+    pub fn texture(texture: DataRef) -> Self {
+        Self::Texture { texture }
+    }
+
+    pub fn get(&self, scene: &Scene, uv: Vec2) -> T {
+        match *self {
+            Self::Constant(value) => value,
+            Self::Texture { texture } => {
+                let texture = scene
+                    .get_data(texture)
+                    .as_texture()
+                    .expect("expected texture data");
+                T::from_color(texture.sample(uv))
+            }
+        }
+    }
+
+    /// The value at the texture coordinates of `manifold`.
+    pub fn at(&self, manifold: &Manifold) -> T {
+        self.get(manifold.scene, manifold.uv)
+    }
+}
+
+impl From<f32> for Param<f32> {
+    fn from(value: f32) -> Self {
+        Self::Constant(value)
+    }
+}
+
+impl From<LinearRgb> for Param<LinearRgb> {
+    fn from(value: LinearRgb) -> Self {
+        Self::Constant(value)
+    }
+}
+
+impl<T> From<DataRef> for Param<T> {
+    fn from(texture: DataRef) -> Self {
+        Self::Texture { texture }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_filtering() {
+        let (black, white) = (LinearRgb::BLACK, LinearRgb::WHITE);
+        let image = ImageTexture::new(2, 1, vec![black, white]);
+
+        // halfway between the pixel centers, and across the edge
+        assert_eq!(image.sample(Vec2::new(0.5, 0.5)), LinearRgb::splat(0.5));
+        assert_eq!(image.sample(Vec2::new(0.0, 0.5)), LinearRgb::splat(0.5));
+        let image = image.with_wrap(Wrap::Clamp);
+        assert_eq!(image.sample(Vec2::new(0.0, 0.5)), black);
+        assert_eq!(image.sample(Vec2::new(1.0, 0.5)), white);
+        let image = image.with_wrap(Wrap::Mirror);
+        assert_eq!(image.sample(Vec2::new(1.25, 0.5)), white);
+
+        let json = serde_json::to_string(&image).unwrap();
+        let image: ImageTexture = serde_json::from_str(&json).unwrap();
+        assert_eq!(image.sample(Vec2::new(1.25, 0.5)), white);
+        let json = json.replace(r#""width":2"#, r#""width":3"#);
+        assert!(serde_json::from_str::<ImageTexture>(&json).is_err());
+
+        let checker = Texture::checker(black, white, 2.0);
+        assert_eq!(checker.sample(Vec2::new(0.1, 0.1)), black);
+        assert_eq!(checker.sample(Vec2::new(0.6, 0.1)), white);
+        assert_eq!(checker.sample(Vec2::new(-0.1, 0.1)), white);
+
+        // noise stays within its colors and is continuous
+        for texture in [
+            Texture::perlin(black, white, 4.0, 3),
+            Texture::worley(black, white, 4.0),
+        ] {
+            for i in 0..1000 {
+                let uv = Vec2::new(i as f32 * 0.0123, i as f32 * 0.0071);
+                let value = texture.sample(uv).r;
+                assert!((0.0..=1.0).contains(&value));
+                let next = texture.sample(uv + Vec2::splat(1e-4)).r;
+                assert!((value - next).abs() < 1e-2, "{texture:?} at {uv}");
+            }
+        }
+    }
+}
@@ -29,9 +29,17 @@ impl Volume {
         manifold: &Manifold,
         step: f32,
     ) -> (Option<Ray>, Option<ColorData>) {
-        let offset = manifold.bbox.0;
-        let size = manifold.bbox.1 - manifold.bbox.0;
-        let coord = (manifold.position - offset) / size;
+        let coord = manifold
+            .object_ref
+            .and_then(|object_ref| {
+                let object = manifold.scene.get_object(object_ref);
+                object.volume_coord(manifold.position)
+            })
+            .unwrap_or_else(|| {
+                let offset = manifold.bbox.0;
+                let size = manifold.bbox.1 - manifold.bbox.0;
+                (manifold.position - offset) / size
+            });
 
         let density = step * self.sample(coord, SamplingMode::Trilinear);
 
@@ -0,0 +1,319 @@
+use std::path::Path;
+
+use ::gltf::buffer;
+use ::gltf::camera::Projection;
+use ::gltf::material::AlphaMode;
+use ::gltf::mesh::Mode;
+use anyhow::{Context, Result};
+use glam::{Affine3A, Quat, Vec2, Vec3, Vec3A};
+
+use super::{brightest, default_material};
+use crate::color::LinearRgb;
+use crate::scene::{
+    Camera, Data, DataRef, Material, Mesh, Object, ObjectKind, ObjectRef, Scene, TriangleMesh,
+    Update, UpdateQueue,
+};
+
+/// Loads the default scene of a glTF 2.0 file, either `.gltf` or `.glb`,
+/// into `scene` and returns the objects of its root nodes.
+///
+/// Every node becomes an object, linked to its parent with `Object::add`.
+/// A node holds its camera or, if it has a single one, its mesh primitive;
+/// any other primitives become children of the node. Orthographic cameras
+/// aren't supported and are left out.
+pub fn load_gltf<P: AsRef<Path>>(scene: &mut Scene, path: P) -> Result<Vec<ObjectRef>> {
+    let path = path.as_ref();
+    let ::gltf::Gltf { document, blob } =
+        ::gltf::Gltf::open(path).with_context(|| format!("failed to load {}", path.display()))?;
+    let buffers = ::gltf::import_buffers(&document, path.parent(), blob)
+        .with_context(|| format!("failed to load the buffers of {}", path.display()))?;
+
+    let materials: Vec<DataRef> = document
+        .materials()
+        .map(|material| scene.add_data(Data::new(convert_material(&material))))
+        .collect();
+    let mut default = None;
+
+    let mut meshes = Vec::with_capacity(document.meshes().len());
+    for mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            let triangles = read_primitive(&primitive, &buffers).with_context(|| {
+                format!(
+                    "failed to read mesh {} of {}",
+                    mesh.name().unwrap_or(&mesh.index().to_string()),
+                    path.display()
+                )
+            })?;
+            let triangles = match triangles {
+                Some(triangles) => triangles,
+                None => continue,
+            };
+
+            let material = match primitive.material().index() {
+                Some(index) => materials[index],
+                None => default_material(scene, &mut default),
+            };
+            primitives.push((scene.add_data(Data::new(triangles)), material));
+        }
+        meshes.push(primitives);
+    }
+
+    let root = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .with_context(|| format!("{} has no scenes", path.display()))?;
+
+    let mut update_queue = UpdateQueue::new();
+    let objects = root
+        .nodes()
+        .map(|node| add_node(scene, &mut update_queue, &node, &meshes))
+        .collect();
+    update_queue.commit(scene);
+
+    Ok(objects)
+}
+
+/// Adds `node` and its descendants to `scene`, queueing the updates that
+/// link them together.
+fn add_node(
+    scene: &mut Scene,
+    update_queue: &mut UpdateQueue,
+    node: &::gltf::Node,
+    meshes: &[Vec<(DataRef, DataRef)>],
+) -> ObjectRef {
+    let mut children: Vec<ObjectRef> = node
+        .children()
+        .map(|child| add_node(scene, update_queue, &child, meshes))
+        .collect();
+
+    let primitives = node.mesh().map_or(&[][..], |mesh| &meshes[mesh.index()]);
+    let camera = node.camera().and_then(|camera| convert_camera(&camera));
+    let (inner, primitives) = match (camera, primitives) {
+        (Some(camera), _) => (ObjectKind::from(camera), primitives),
+        (None, &[(mesh, material)]) => (mesh_object(scene, mesh, material).into(), &[][..]),
+        (None, _) => (ObjectKind::Empty, primitives),
+    };
+    for &(mesh, material) in primitives {
+        let object = Object::new(mesh_object(scene, mesh, material));
+        children.push(scene.add_object(object));
+    }
+
+    let (translation, rotation, scale) = node.transform().decomposed();
+    let object = Object::new(inner).with_transform(Affine3A::from_scale_rotation_translation(
+        Vec3::from(scale),
+        Quat::from_array(rotation),
+        Vec3::from(translation),
+    ));
+    let object = match node.name() {
+        Some(name) => object.with_tag(name.to_string()),
+        None => object,
+    };
+    let object_ref = scene.add_object(object);
+
+    if !children.is_empty() {
+        update_queue.push(Update::object(
+            object_ref,
+            move |object, update_queue, _| {
+                for child in children {
+                    object.add(update_queue, child);
+                }
+            },
+        ));
+    }
+
+    object_ref
+}
+
+fn mesh_object(scene: &Scene, mesh: DataRef, material: DataRef) -> Mesh {
+    let triangles = scene.get_data(mesh).as_mesh().expect("expected mesh data");
+    Mesh::new(mesh, triangles, material)
+}
+
+/// Reads the triangles of a primitive, or `None` if it is made of points or
+/// lines.
+fn read_primitive(
+    primitive: &::gltf::Primitive,
+    buffers: &[buffer::Data],
+) -> Result<Option<TriangleMesh>> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<Vec3A> = match reader.read_positions() {
+        Some(positions) => positions.map(Vec3A::from).collect(),
+        None => return Ok(None),
+    };
+    let normals: Vec<Vec3A> = reader
+        .read_normals()
+        .map_or_else(Vec::new, |normals| normals.map(Vec3A::from).collect());
+    let uvs: Vec<Vec2> = reader
+        .read_tex_coords(0)
+        .map_or_else(Vec::new, |uvs| uvs.into_f32().map(Vec2::from).collect());
+    let vertices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    let indices = match primitive.mode() {
+        Mode::Triangles => vertices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect(),
+        // every other triangle of a strip is wound the other way around
+        Mode::TriangleStrip => vertices
+            .windows(3)
+            .enumerate()
+            .map(|(i, triangle)| match i % 2 {
+                0 => [triangle[0], triangle[1], triangle[2]],
+                _ => [triangle[1], triangle[0], triangle[2]],
+            })
+            .collect(),
+        Mode::TriangleFan => vertices
+            .windows(2)
+            .skip(1)
+            .map(|edge| [vertices[0], edge[0], edge[1]])
+            .collect(),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => return Ok(None),
+    };
+
+    // glTF asks for flat shading when a primitive has no normals, which is
+    // how meshes without them are shaded
+    Ok(Some(TriangleMesh::try_new(
+        positions, normals, uvs, indices,
+    )?))
+}
+
+fn convert_camera(camera: &::gltf::Camera) -> Option<Camera> {
+    match camera.projection() {
+        Projection::Perspective(perspective) => {
+            let default = Camera::default();
+            Some(Camera {
+                focal_length: 0.5 * default.sensor_size / (0.5 * perspective.yfov()).tan(),
+                aspect_ratio: perspective.aspect_ratio().unwrap_or(default.aspect_ratio),
+                ..default
+            })
+        }
+        Projection::Orthographic(_) => None,
+    }
+}
+
+/// Maps a metallic-roughness material to the closest `Material`.
+///
+/// Materials are either fully metallic or not at all in practice, so the
+/// metallic factor is rounded. Transmissive and blended transparent
+/// materials become glass.
+fn convert_material(material: &::gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let albedo = LinearRgb::new(r, g, b);
+    let roughness = pbr.roughness_factor();
+
+    let emission =
+        LinearRgb::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0);
+    let intensity = brightest(emission);
+    if intensity > 0.0 {
+        return Material::emissive(emission / intensity, intensity);
+    }
+
+    let transmission = material
+        .transmission()
+        .map_or(0.0, |transmission| transmission.transmission_factor());
+    let transparent = material.alpha_mode() == AlphaMode::Blend && alpha < 1.0;
+    if transmission >= 0.5 || transparent {
+        Material::glass(albedo, roughness, material.ior().unwrap_or(1.5))
+    } else if pbr.metallic_factor() >= 0.5 {
+        Material::metallic(albedo, roughness)
+    } else {
+        Material::diffuse(albedo, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::scene::Param;
+    use crate::tracer::{Clip, Ray};
+
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "name": "base", "translation": [0, 0, -5], "children": [1] },
+            { "name": "arm", "translation": [1, 0, 0], "mesh": 0, "children": [2] },
+            { "name": "eye", "translation": [0, 2, 0], "camera": 0 }
+        ],
+        "cameras": [{
+            "type": "perspective",
+            "perspective": { "yfov": 0.5, "aspectRatio": 2.0, "znear": 0.1 }
+        }],
+        "materials": [{
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [0.9, 0.6, 0.2, 1.0],
+                "metallicFactor": 1.0,
+                "roughnessFactor": 0.3
+            }
+        }],
+        "meshes": [{
+            "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }]
+        }],
+        "accessors": [{
+            "bufferView": 0,
+            "componentType": 5126,
+            "count": 3,
+            "type": "VEC3",
+            "min": [0, 0, 0],
+            "max": [1, 1, 0]
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "buffers": [{ "uri": "triangle.bin", "byteLength": 36 }]
+    }"#;
+
+    #[test]
+    fn hierarchy() {
+        let dir = std::env::temp_dir().join(format!("bendy-gltf-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let positions: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        fs::write(dir.join("triangle.bin"), positions).unwrap();
+        fs::write(dir.join("triangle.gltf"), GLTF).unwrap();
+
+        let mut scene = Scene::default();
+        let roots = load_gltf(&mut scene, dir.join("triangle.gltf")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(roots.len(), 1);
+
+        let arm = scene.find_by_tag("arm").unwrap();
+        let eye = scene.find_by_tag("eye").unwrap();
+        let translation = |object_ref| scene.get_object(object_ref).transform().translation;
+        assert!(translation(arm).abs_diff_eq(Vec3A::new(1.0, 0.0, -5.0), 1e-6));
+        assert!(translation(eye).abs_diff_eq(Vec3A::new(1.0, 2.0, -5.0), 1e-6));
+
+        let camera = scene.get_object(eye).as_camera().unwrap();
+        assert_eq!(camera.aspect_ratio, 2.0);
+        let yfov = 2.0 * camera.sensor_size.atan2(2.0 * camera.focal_length);
+        assert!((yfov - 0.5).abs() < 1e-6);
+
+        let mesh = match scene.get_object(arm).inner() {
+            ObjectKind::Mesh(mesh) => mesh,
+            _ => panic!("expected a mesh"),
+        };
+        let triangles = scene.get_data(mesh.mesh).as_mesh().unwrap();
+        assert!(triangles.normals().is_empty());
+        assert!(matches!(
+            scene.get_data(mesh.material).as_material(),
+            Some(Material::Metallic { roughness, .. }) if *roughness == Param::Constant(0.3)
+        ));
+
+        let ray = Ray::new(Vec3A::new(1.25, 0.25, 0.0), Vec3A::NEG_Z);
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let hit = scene.get_object(arm).hit(&ray, &clip, &scene).unwrap();
+        assert!((hit.t - 5.0).abs() < 1e-5);
+        assert!(hit.normal.abs_diff_eq(Vec3A::Z, 1e-6));
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+
+use super::default_material;
+use crate::scene::{Data, HeightMap, Heightfield, Object, ObjectRef, Scene};
+
+/// Loads a grayscale image, such as an 8 or 16 bit PNG, into `scene` as a
+/// `Heightfield` object tagged with the file's name.
+///
+/// The terrain spans `[-1, 1]` along x and z with heights from 0 to 1, to
+/// be scaled by the object's transform, and gets the default material.
+pub fn load_heightfield<P: AsRef<Path>>(scene: &mut Scene, path: P) -> Result<ObjectRef> {
+    let path = path.as_ref();
+    let image = image::open(path).with_context(|| format!("failed to load {}", path.display()))?;
+    ensure!(
+        image.width() >= 2 && image.height() >= 2,
+        "{} is too small for a height map",
+        path.display()
+    );
+
+    let material = default_material(scene, &mut None);
+    let map_ref = scene.add_data(Data::new(HeightMap::from_image(&image)));
+    let map = scene
+        .get_data(map_ref)
+        .as_height_map()
+        .expect("expected height map data");
+    let object = Object::new(Heightfield::new(map_ref, map, material));
+    let object = match path.file_stem() {
+        Some(name) => object.with_tag(name.to_string_lossy().into_owned()),
+        None => object,
+    };
+
+    Ok(scene.add_object(object))
+}
@@ -0,0 +1,34 @@
+//! Loaders that pull models made in other tools into a `Scene`.
+
+mod gltf;
+mod heightfield;
+mod obj;
+mod ply;
+mod texture;
+
+pub use self::gltf::*;
+pub use self::heightfield::*;
+pub use self::obj::*;
+pub use self::ply::*;
+pub use self::texture::*;
+
+use crate::color::LinearRgb;
+use crate::scene::{Data, DataRef, Material, Scene};
+
+/// Albedo of the material given to faces that don't have one.
+const DEFAULT_ALBEDO: f32 = 0.8;
+
+/// Returns the material for faces without one, adding it to `scene` the
+/// first time it is needed.
+fn default_material(scene: &mut Scene, material: &mut Option<DataRef>) -> DataRef {
+    *material.get_or_insert_with(|| {
+        scene.add_data(Data::new(Material::diffuse(
+            LinearRgb::splat(DEFAULT_ALBEDO),
+            1.0,
+        )))
+    })
+}
+
+fn brightest(color: LinearRgb) -> f32 {
+    color.r.max(color.g).max(color.b)
+}
@@ -0,0 +1,206 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use glam::{Vec2, Vec3A};
+
+use super::{brightest, default_material, DEFAULT_ALBEDO};
+use crate::color::LinearRgb;
+use crate::scene::{Data, DataRef, Material, Mesh, Object, ObjectRef, Scene, TriangleMesh};
+
+/// Loads a Wavefront OBJ file and the MTL files it references into
+/// `scene`, with one `Mesh` object per group of faces sharing a material.
+/// Returns the new objects.
+///
+/// Meshes without normals are shaded flat.
+pub fn load_obj<P: AsRef<Path>>(scene: &mut Scene, path: P) -> Result<Vec<ObjectRef>> {
+    let path = path.as_ref();
+    let options = tobj::LoadOptions {
+        triangulate: true,
+        single_index: true,
+        ..Default::default()
+    };
+    let (models, materials) = tobj::load_obj(path, &options)
+        .with_context(|| format!("failed to load {}", path.display()))?;
+    let materials =
+        materials.with_context(|| format!("failed to load the materials of {}", path.display()))?;
+
+    let material_refs: Vec<DataRef> = materials
+        .iter()
+        .map(|material| scene.add_data(Data::new(convert_material(material))))
+        .collect();
+    let mut default = None;
+
+    let mut objects = Vec::with_capacity(models.len());
+    for model in models {
+        let mesh = model.mesh;
+        if mesh.indices.is_empty() {
+            continue;
+        }
+
+        let positions = mesh
+            .positions
+            .chunks_exact(3)
+            .map(Vec3A::from_slice)
+            .collect();
+        let normals = mesh
+            .normals
+            .chunks_exact(3)
+            .map(Vec3A::from_slice)
+            .collect();
+        // OBJ puts the origin of textures at the bottom left
+        let uvs = mesh
+            .texcoords
+            .chunks_exact(2)
+            .map(|uv| Vec2::new(uv[0], 1.0 - uv[1]))
+            .collect();
+        let indices = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+
+        let triangles = TriangleMesh::try_new(positions, normals, uvs, indices)
+            .with_context(|| format!("invalid mesh in {}", path.display()))?;
+
+        let material = match mesh.material_id {
+            Some(id) => material_refs[id],
+            None => default_material(scene, &mut default),
+        };
+
+        let mesh_ref = scene.add_data(Data::new(triangles));
+        let triangles = scene
+            .get_data(mesh_ref)
+            .as_mesh()
+            .expect("expected mesh data");
+        let object = Object::new(Mesh::new(mesh_ref, triangles, material));
+        let object = if model.name.is_empty() {
+            object
+        } else {
+            object.with_tag(model.name)
+        };
+        objects.push(scene.add_object(object));
+    }
+
+    Ok(objects)
+}
+
+/// Maps the Phong-style parameters of an MTL material to the closest
+/// `Material`.
+fn convert_material(material: &tobj::Material) -> Material {
+    let color = |color: Option<[f32; 3]>| color.map_or(LinearRgb::BLACK, LinearRgb::from);
+    let diffuse = material
+        .diffuse
+        .map_or(LinearRgb::splat(DEFAULT_ALBEDO), LinearRgb::from);
+    let specular = color(material.specular);
+    // Blinn-Phong exponents map to roughly this microfacet roughness
+    let roughness = material
+        .shininess
+        .map_or(1.0, |shininess| (2.0 / (shininess.max(0.0) + 2.0)).sqrt());
+
+    let emission = color(material.emissive);
+    let intensity = brightest(emission);
+    if intensity > 0.0 {
+        return Material::emissive(emission / intensity, intensity);
+    }
+
+    let transparent = material.dissolve.is_some_and(|dissolve| dissolve < 1.0);
+    match material.illumination_model {
+        Some(4 | 6 | 7 | 9) => glass(material, diffuse, roughness),
+        _ if transparent => glass(material, diffuse, roughness),
+        Some(3 | 5 | 8) => Material::metallic(specular, roughness),
+        _ if brightest(diffuse) <= 0.0 && brightest(specular) > 0.0 => {
+            Material::metallic(specular, roughness)
+        }
+        _ => Material::diffuse(diffuse, 1.0),
+    }
+}
+
+fn glass(material: &tobj::Material, albedo: LinearRgb, roughness: f32) -> Material {
+    let ior = material.optical_density.unwrap_or(1.5);
+    Material::glass(albedo, roughness, ior)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::tracer::{Clip, Ray};
+
+    const OBJ: &str = "\
+mtllib cube.mtl
+v -1 -1 -1
+v 1 -1 -1
+v 1 1 -1
+v -1 1 -1
+v -1 -1 1
+v 1 -1 1
+v 1 1 1
+v -1 1 1
+o sides
+usemtl red
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f 4 1 5 8
+f 1 4 3 2
+o top
+usemtl light
+f 5 6 7 8
+";
+
+    const MTL: &str = "\
+newmtl red
+Kd 0.8 0.1 0.1
+illum 2
+newmtl light
+Kd 0 0 0
+Ke 4 4 2
+";
+
+    #[test]
+    fn cube() {
+        let dir = std::env::temp_dir().join(format!("bendy-obj-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cube.obj"), OBJ).unwrap();
+        fs::write(dir.join("cube.mtl"), MTL).unwrap();
+
+        let mut scene = Scene::default();
+        let objects = load_obj(&mut scene, dir.join("cube.obj")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(objects.len(), 2);
+
+        let material = |object_ref| match scene.get_object(object_ref).inner() {
+            crate::scene::ObjectKind::Mesh(mesh) => *scene
+                .get_data(mesh.material)
+                .as_material()
+                .expect("expected a material"),
+            _ => panic!("expected a mesh"),
+        };
+        assert!(matches!(material(objects[0]), Material::Diffuse { .. }));
+        assert!(matches!(
+            material(objects[1]),
+            Material::Emissive { intensity, .. } if intensity == 4.0
+        ));
+
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let ray = Ray::new(Vec3A::new(0.2, 0.3, 5.0), Vec3A::NEG_Z);
+        let hit = scene
+            .get_object(objects[1])
+            .hit(&ray, &clip, &scene)
+            .expect("expected to hit the top");
+        assert!((hit.t - 4.0).abs() < 1e-5);
+        assert!(hit.normal.abs_diff_eq(Vec3A::Z, 1e-5));
+
+        let ray = Ray::new(Vec3A::new(5.0, 0.1, 0.0), Vec3A::NEG_X);
+        let hit = scene
+            .get_object(objects[0])
+            .hit(&ray, &clip, &scene)
+            .expect("expected to hit a side");
+        assert!((hit.t - 4.0).abs() < 1e-5);
+        assert!(hit.normal.dot(ray.direction) < 0.0);
+    }
+}
@@ -0,0 +1,413 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::{Vec2, Vec3A};
+
+use super::default_material;
+use crate::scene::{Data, Mesh, Object, ObjectRef, Scene, TriangleMesh};
+
+/// Loads a PLY mesh, in either the ASCII or a binary format, into `scene`
+/// as a single `Mesh` object tagged with the file's name.
+///
+/// PLY files carry no materials, so the mesh gets the default one.
+pub fn load_ply<P: AsRef<Path>>(scene: &mut Scene, path: P) -> Result<ObjectRef> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let triangles = read_ply(BufReader::new(file))
+        .with_context(|| format!("failed to load {}", path.display()))?;
+
+    let material = default_material(scene, &mut None);
+    let mesh_ref = scene.add_data(Data::new(triangles));
+    let triangles = scene
+        .get_data(mesh_ref)
+        .as_mesh()
+        .expect("expected mesh data");
+    let object = Object::new(Mesh::new(mesh_ref, triangles, material));
+    let object = match path.file_stem() {
+        Some(name) => object.with_tag(name.to_string_lossy().into_owned()),
+        None => object,
+    };
+
+    Ok(scene.add_object(object))
+}
+
+/// Reads the `vertex` and `face` elements of a PLY file into a mesh,
+/// skipping any other elements.
+///
+/// Vertices need `x`, `y` and `z` properties and may have normals and
+/// texture coordinates; other properties like colors are ignored. Faces
+/// with more than three vertices are split into fans. Meshes without
+/// normals get smooth ones.
+pub fn read_ply<R: BufRead>(mut reader: R) -> Result<TriangleMesh> {
+    let (format, elements) = read_header(&mut reader)?;
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut body = match format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(&bytes)
+                .context("ASCII PLY isn't valid UTF-8")?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian => Body::Binary {
+            bytes: &bytes,
+            big_endian: false,
+        },
+        Format::BinaryBigEndian => Body::Binary {
+            bytes: &bytes,
+            big_endian: true,
+        },
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut indices = Vec::new();
+    let mut has_faces = false;
+
+    for element in &elements {
+        let find = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|(name, _)| names.contains(&name.as_str()))
+        };
+        let mut row = vec![Vec::new(); element.properties.len()];
+
+        match element.name.as_str() {
+            "vertex" => {
+                let position = match [find(&["x"]), find(&["y"]), find(&["z"])] {
+                    [Some(x), Some(y), Some(z)] => [x, y, z],
+                    _ => bail!("vertices need x, y and z properties"),
+                };
+                let normal = match [find(&["nx"]), find(&["ny"]), find(&["nz"])] {
+                    [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+                    _ => None,
+                };
+                let uv = match [
+                    find(&["u", "s", "texture_u"]),
+                    find(&["v", "t", "texture_v"]),
+                ] {
+                    [Some(u), Some(v)] => Some([u, v]),
+                    _ => None,
+                };
+
+                let scalar = |row: &[Vec<f64>], index: usize| {
+                    row[index].first().copied().unwrap_or_default() as f32
+                };
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                    positions.push(Vec3A::from(position.map(|index| scalar(&row, index))));
+                    if let Some(normal) = normal {
+                        normals.push(Vec3A::from(normal.map(|index| scalar(&row, index))));
+                    }
+                    // PLY puts the origin of textures at the bottom left
+                    if let Some([u, v]) = uv {
+                        uvs.push(Vec2::new(scalar(&row, u), 1.0 - scalar(&row, v)));
+                    }
+                }
+            }
+            "face" => {
+                let vertices = find(&["vertex_indices", "vertex_index"])
+                    .context("faces need a vertex_indices property")?;
+                has_faces = true;
+
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                    let face = &row[vertices];
+                    ensure!(
+                        face.iter().all(|&index| index >= 0.0),
+                        "negative vertex index"
+                    );
+                    let face: Vec<u32> = face.iter().map(|&index| index as u32).collect();
+                    if let Some((&first, rest)) = face.split_first() {
+                        indices.extend(rest.windows(2).map(|edge| [first, edge[0], edge[1]]));
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                }
+            }
+        }
+    }
+
+    ensure!(
+        has_faces,
+        "PLY has no faces, point clouds need to be meshed first"
+    );
+    ensure!(
+        indices
+            .iter()
+            .flatten()
+            .all(|&index| (index as usize) < positions.len()),
+        "vertex index out of bounds"
+    );
+
+    let mesh = TriangleMesh::new(positions, normals, uvs, indices);
+    if mesh.normals().is_empty() {
+        Ok(mesh.with_smooth_normals())
+    } else {
+        Ok(mesh)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    Int8,
+    Uint8,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+    Float64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::Int8,
+            "uchar" | "uint8" => Self::Uint8,
+            "short" | "int16" => Self::Int16,
+            "ushort" | "uint16" => Self::Uint16,
+            "int" | "int32" => Self::Int32,
+            "uint" | "uint32" => Self::Uint32,
+            "float" | "float32" => Self::Float32,
+            "double" | "float64" => Self::Float64,
+            _ => bail!("unknown property type {name}"),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::Int8 | Self::Uint8 => 1,
+            Self::Int16 | Self::Uint16 => 2,
+            Self::Int32 | Self::Uint32 | Self::Float32 => 4,
+            Self::Float64 => 8,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! decode {
+            ($ty:ty) => {{
+                let bytes = bytes.try_into().expect("wrong number of bytes");
+                if big_endian {
+                    <$ty>::from_be_bytes(bytes) as f64
+                } else {
+                    <$ty>::from_le_bytes(bytes) as f64
+                }
+            }};
+        }
+
+        match self {
+            Self::Int8 => decode!(i8),
+            Self::Uint8 => decode!(u8),
+            Self::Int16 => decode!(i16),
+            Self::Uint16 => decode!(u16),
+            Self::Int32 => decode!(i32),
+            Self::Uint32 => decode!(u32),
+            Self::Float32 => decode!(f32),
+            Self::Float64 => decode!(f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Property {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(Format, Vec<Element>)> {
+    let mut line = String::new();
+    let mut next_line = |reader: &mut R| -> Result<String> {
+        line.clear();
+        ensure!(reader.read_line(&mut line)? > 0, "unexpected end of header");
+        Ok(line.trim_end().to_string())
+    };
+
+    ensure!(next_line(reader)? == "ply", "not a PLY file");
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let line = next_line(reader)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, _] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => bail!("unknown format {name}"),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .with_context(|| format!("invalid element count {count}"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = Property::List {
+                    count: Scalar::parse(count)?,
+                    item: Scalar::parse(item)?,
+                };
+                elements
+                    .last_mut()
+                    .context("property outside of an element")?
+                    .properties
+                    .push((name.to_string(), property));
+            }
+            ["property", scalar, name] => {
+                let property = Property::Scalar(Scalar::parse(scalar)?);
+                elements
+                    .last_mut()
+                    .context("property outside of an element")?
+                    .properties
+                    .push((name.to_string(), property));
+            }
+            _ => bail!("invalid header line {line:?}"),
+        }
+    }
+
+    let format = format.context("header has no format")?;
+    Ok((format, elements))
+}
+
+/// The data following the header.
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64> {
+        match self {
+            Self::Ascii(words) => {
+                let word = words.next().context("unexpected end of file")?;
+                word.parse()
+                    .with_context(|| format!("invalid number {word}"))
+            }
+            Self::Binary { bytes, big_endian } => {
+                let size = scalar.size();
+                ensure!(bytes.len() >= size, "unexpected end of file");
+                let (value, rest) = bytes.split_at(size);
+                *bytes = rest;
+                Ok(scalar.decode(value, *big_endian))
+            }
+        }
+    }
+
+    /// Reads one instance of `element` into `row`, which holds the values
+    /// of each of its properties.
+    fn read_row(&mut self, element: &Element, row: &mut [Vec<f64>]) -> Result<()> {
+        for ((_, property), values) in element.properties.iter().zip(row) {
+            values.clear();
+            match *property {
+                Property::Scalar(scalar) => values.push(self.read(scalar)?),
+                Property::List { count, item } => {
+                    let count = self.read(count)?;
+                    ensure!(count >= 0.0, "negative list length");
+                    for _ in 0..count as usize {
+                        values.push(self.read(item)?);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{Clip, Ray};
+
+    const HEADER: &str = "\
+ply
+format {format} 1.0
+comment a unit square split into two triangles
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+element face 1
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+";
+
+    const POSITIONS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+
+    fn check(mesh: &TriangleMesh) {
+        assert_eq!(mesh.len(), 2);
+        assert_eq!(mesh.positions()[2], Vec3A::new(1.0, 1.0, 0.0));
+        assert!(mesh.normals()[0].abs_diff_eq(Vec3A::Z, 1e-6));
+
+        let ray = Ray::new(Vec3A::new(0.25, 0.75, 1.0), Vec3A::NEG_Z);
+        let clip = Clip {
+            min: 0.0,
+            max: 10.0,
+        };
+        let hit = mesh.hit(&ray, &clip).expect("expected to hit the square");
+        assert!((hit.t - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ascii() {
+        let mut ply = HEADER.replace("{format}", "ascii");
+        for [x, y, z] in POSITIONS {
+            ply += &format!("{x} {y} {z} 255\n");
+        }
+        ply += "4 0 1 2 3\n0 2\n";
+
+        check(&read_ply(ply.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn binary() {
+        let mut ply = HEADER.replace("{format}", "binary_big_endian").into_bytes();
+        for position in POSITIONS {
+            ply.extend(position.iter().flat_map(|x| x.to_be_bytes()));
+            ply.push(255);
+        }
+        ply.push(4);
+        ply.extend([0, 1, 2, 3].iter().flat_map(|i: &i32| i.to_be_bytes()));
+        ply.extend([0, 2].iter().flat_map(|i: &i32| i.to_be_bytes()));
+
+        check(&read_ply(&ply[..]).unwrap());
+
+        ply.truncate(ply.len() - 1);
+        assert!(read_ply(&ply[..]).is_err());
+    }
+}
@@ -0,0 +1,17 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::scene::{Data, DataRef, ImageTexture, Scene, Texture};
+
+/// Loads an image into `scene` as a `Texture`, to be given to material
+/// parameters with `Param::texture`.
+///
+/// Colors are decoded from sRGB when `srgb` is set; other data, such as
+/// roughness maps, should be loaded without it.
+pub fn load_texture<P: AsRef<Path>>(scene: &mut Scene, path: P, srgb: bool) -> Result<DataRef> {
+    let path = path.as_ref();
+    let image = image::open(path).with_context(|| format!("failed to load {}", path.display()))?;
+    let texture = Texture::Image(ImageTexture::from_image(&image, srgb));
+    Ok(scene.add_data(Data::new(texture)))
+}
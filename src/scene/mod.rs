@@ -2,13 +2,15 @@ use std::collections::VecDeque;
 use std::hash::Hash;
 use std::mem;
 
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 
 mod data;
+pub mod import;
 mod object;
 
 use crate::color::LinearRgb;
+use crate::tracer::Bvh;
 
 pub use self::data::*;
 pub use self::object::*;
@@ -81,12 +83,45 @@ impl<K: Eq + Hash, V> Default for Collection<K, V> {
     }
 }
 
+/// Objects and data making up a scene.
+///
+/// The hierarchies of the prototypes of `Instance` objects aren't
+/// serialized, but rebuilt whenever the scene is loaded and updates to
+/// their objects are committed.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "SceneCollections")]
 pub struct Scene {
     roots: Vec<ObjectRef>,
     root_material: DataRef,
     objects: ObjectCollection,
     data: DataCollection,
+    #[serde(skip)]
+    prototypes: HashMap<ObjectRef, Bvh>,
+    #[serde(skip)]
+    instanced: HashSet<ObjectRef>,
+}
+
+#[derive(Deserialize)]
+struct SceneCollections {
+    roots: Vec<ObjectRef>,
+    root_material: DataRef,
+    objects: ObjectCollection,
+    data: DataCollection,
+}
+
+impl From<SceneCollections> for Scene {
+    fn from(collections: SceneCollections) -> Self {
+        let mut scene = Self {
+            roots: collections.roots,
+            root_material: collections.root_material,
+            objects: collections.objects,
+            data: collections.data,
+            prototypes: HashMap::new(),
+            instanced: HashSet::new(),
+        };
+        scene.build_prototypes();
+        scene
+    }
 }
 
 impl Scene {
@@ -100,11 +135,21 @@ impl Scene {
             root_material: root_mat,
             objects,
             data,
+            prototypes: HashMap::new(),
+            instanced: HashSet::new(),
         }
     }
 
     pub fn add_object(&mut self, object: Object) -> ObjectRef {
-        self.objects.add(object)
+        let prototype = object.as_instance().map(|instance| instance.prototype);
+        let object_ref = self.objects.add(object);
+
+        if let Some(prototype) = prototype {
+            self.build_prototype(prototype);
+            self.collect_instanced();
+        }
+
+        object_ref
     }
 
     pub fn add_data(&mut self, data: Data) -> DataRef {
@@ -143,6 +188,116 @@ impl Scene {
     pub fn pairs(&self) -> impl Iterator<Item = (ObjectRef, &'_ Object)> {
         self.objects.pairs().map(|(k, v)| (*k, v))
     }
+
+    /// `object` and all of its descendants.
+    pub fn subtree(&self, object: ObjectRef) -> Vec<ObjectRef> {
+        let mut subtree = vec![object];
+        let mut index = 0;
+        while let Some(&object) = subtree.get(index) {
+            subtree.extend(self.get_object(object).children());
+            index += 1;
+        }
+        subtree
+    }
+
+    /// The hierarchy of the subtree that instances of `prototype` share.
+    pub fn prototype(&self, prototype: ObjectRef) -> Option<&Bvh> {
+        self.prototypes.get(&prototype)
+    }
+
+    /// Objects that are the prototype of some instance.
+    pub fn prototypes(&self) -> impl Iterator<Item = ObjectRef> + '_ {
+        self.prototypes.keys().copied()
+    }
+
+    /// Builds the hierarchy of `prototype` unless it's already there,
+    /// along with those of the instances within it. A prototype that holds
+    /// an instance of itself sees it as empty.
+    fn build_prototype(&mut self, prototype: ObjectRef) {
+        if self.prototypes.contains_key(&prototype) {
+            return;
+        }
+        self.prototypes.insert(prototype, Bvh::default());
+
+        let subtree = self.subtree(prototype);
+        let nested: Vec<ObjectRef> = subtree
+            .iter()
+            .filter_map(|&object| self.get_object(object).as_instance())
+            .map(|instance| instance.prototype)
+            .collect();
+        for nested in nested {
+            self.build_prototype(nested);
+        }
+
+        let bvh = Bvh::from_objects(self, subtree);
+        self.prototypes.insert(prototype, bvh);
+    }
+
+    /// Whether `object` is part of a prototype, which is only seen through
+    /// the instances of it.
+    pub fn is_instanced(&self, object: ObjectRef) -> bool {
+        self.instanced.contains(&object)
+    }
+
+    fn collect_instanced(&mut self) {
+        self.instanced = self
+            .prototypes
+            .keys()
+            .flat_map(|&prototype| self.subtree(prototype))
+            .collect();
+    }
+
+    /// Builds the hierarchies of all prototypes.
+    fn build_prototypes(&mut self) {
+        self.prototypes.clear();
+        self.update_prototypes(&HashSet::new());
+    }
+
+    /// Rebuilds the hierarchies of the prototypes holding any of the
+    /// `changed` objects, or instances of such prototypes, and builds those
+    /// of new prototypes. Prototypes no instance refers to anymore are
+    /// dropped.
+    fn update_prototypes(&mut self, changed: &HashSet<ObjectRef>) {
+        let prototypes: HashSet<ObjectRef> = self
+            .iter()
+            .filter_map(|object| object.as_instance())
+            .map(|instance| instance.prototype)
+            .collect();
+        self.prototypes
+            .retain(|prototype, _| prototypes.contains(prototype));
+
+        let subtrees: Vec<(ObjectRef, Vec<ObjectRef>)> = self
+            .prototypes
+            .keys()
+            .map(|&prototype| (prototype, self.subtree(prototype)))
+            .collect();
+        let mut stale: HashSet<ObjectRef> = HashSet::new();
+        loop {
+            let count = stale.len();
+            for (prototype, subtree) in &subtrees {
+                let is_stale = subtree.iter().any(|object| {
+                    changed.contains(object)
+                        || self
+                            .get_object(*object)
+                            .as_instance()
+                            .is_some_and(|instance| stale.contains(&instance.prototype))
+                });
+                if is_stale {
+                    stale.insert(*prototype);
+                }
+            }
+            if stale.len() == count {
+                break;
+            }
+        }
+
+        self.prototypes
+            .retain(|prototype, _| !stale.contains(prototype));
+        for prototype in prototypes {
+            self.build_prototype(prototype);
+        }
+        self.collect_instanced();
+    }
 }
 
 impl Default for Scene {
@@ -174,22 +329,30 @@ impl UpdateQueue {
         self.queue.push_back(update);
     }
 
-    fn commit_one(self, scene: &mut Scene) -> Option<UpdateQueue> {
+    fn commit_one(
+        self,
+        scene: &mut Scene,
+        changed: &mut HashSet<ObjectRef>,
+    ) -> Option<UpdateQueue> {
         let mut update_queue = Self::default();
 
         for update in self.queue {
             match update {
                 Update::Object(object_ref, func) => {
+                    changed.insert(object_ref);
                     let object = scene
                         .objects
                         .get_mut(&object_ref)
                         .expect("invalid object ref");
                     func(object, &mut update_queue, &scene.data);
                 }
-                Update::AllObjects(mut func) => scene
-                    .objects
-                    .iter_mut()
-                    .for_each(|object| func(object, &mut update_queue, &scene.data)),
+                Update::AllObjects(mut func) => {
+                    changed.extend(scene.objects.pairs().map(|(&object_ref, _)| object_ref));
+                    scene
+                        .objects
+                        .iter_mut()
+                        .for_each(|object| func(object, &mut update_queue, &scene.data));
+                }
             }
         }
 
@@ -205,10 +368,13 @@ impl UpdateQueue {
         mem::swap(self, &mut update_queue);
 
         let mut update_queue = Some(update_queue);
+        let mut changed = HashSet::new();
 
         while let Some(queue) = update_queue.take() {
-            update_queue = queue.commit_one(scene);
+            update_queue = queue.commit_one(scene, &mut changed);
         }
+
+        scene.update_prototypes(&changed);
     }
 }
 
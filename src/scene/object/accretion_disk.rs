@@ -0,0 +1,306 @@
+use std::f32;
+
+use glam::{Affine3A, Vec2, Vec3A};
+use rand::Rng;
+use rand_distr::Uniform;
+use serde::{Deserialize, Serialize};
+
+use super::{area_pdf, tangent_frame};
+use crate::math::{spherical_tangent, spherical_uv};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub enum TemperatureProfile {
+    /// `T ∝ r^-exponent`
+    PowerLaw { exponent: f32 },
+    /// Newtonian limit of the Novikov-Thorne thin disk with a zero-torque
+    /// inner edge, `T⁴ ∝ r⁻³ (1 - √(r_in / r))`.
+    #[default]
+    NovikovThorne,
+}
+
+impl TemperatureProfile {
+    /// Temperature at `radius`, relative to the hottest part of the disk.
+    pub fn scale(&self, radius: f32, inner_radius: f32) -> f32 {
+        match *self {
+            Self::PowerLaw { exponent } => (radius / inner_radius).powf(-exponent),
+            Self::NovikovThorne => {
+                let flux = |r: f32| (1.0 - (inner_radius / r).sqrt()).max(0.0) / (r * r * r);
+                // the flux peaks at 49/36 of the inner radius
+                let peak = flux(49.0 / 36.0 * inner_radius);
+                (flux(radius) / peak).powf(0.25)
+            }
+        }
+    }
+}
+
+/// An annular slab in the local xz plane, emitting according to its
+/// temperature profile when given a `Material::Blackbody`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AccretionDisk {
+    pub material: DataRef,
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub thickness: f32,
+    pub profile: TemperatureProfile,
+}
+
+impl AccretionDisk {
+    pub fn new(
+        material: DataRef,
+        inner_radius: f32,
+        outer_radius: f32,
+        thickness: f32,
+        profile: TemperatureProfile,
+    ) -> Self {
+        Self {
+            material,
+            inner_radius,
+            outer_radius,
+            thickness,
+            profile,
+        }
+    }
+
+    fn half_thickness(&self) -> f32 {
+        0.5 * self.thickness
+    }
+
+    fn points(&self, transform: &Affine3A) -> impl Iterator<Item = Vec3A> + '_ {
+        let r = self.outer_radius;
+        let h = self.half_thickness();
+        let transform = *transform;
+        (0..8).map(move |i| {
+            let x = if i & 1 == 0 { -r } else { r };
+            let y = if i & 2 == 0 { -h } else { h };
+            let z = if i & 4 == 0 { -r } else { r };
+            transform.transform_point3a(Vec3A::new(x, y, z))
+        })
+    }
+
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        let min = self
+            .points(transform)
+            .fold(Vec3A::splat(f32::INFINITY), Vec3A::min);
+        let max = self
+            .points(transform)
+            .fold(Vec3A::splat(f32::NEG_INFINITY), Vec3A::max);
+        (min, max)
+    }
+
+    fn face_area(&self) -> f32 {
+        let r_in = self.inner_radius;
+        let r_out = self.outer_radius;
+        f32::consts::PI * (r_out * r_out - r_in * r_in)
+    }
+
+    fn wall_area(&self, radius: f32) -> f32 {
+        f32::consts::TAU * radius * self.thickness
+    }
+
+    /// Area of the faces and walls, where a disk without thickness only
+    /// has the one face.
+    pub fn area(&self) -> f32 {
+        if self.thickness > 0.0 {
+            2.0 * self.face_area()
+                + self.wall_area(self.inner_radius)
+                + self.wall_area(self.outer_radius)
+        } else {
+            self.face_area()
+        }
+    }
+
+    pub fn temperature_scale(&self, transform: &Affine3A, position: Vec3A) -> f32 {
+        let local = transform.inverse().transform_point3a(position);
+        let radius = (local.x * local.x + local.z * local.z).sqrt();
+        let radius = radius.clamp(self.inner_radius, self.outer_radius);
+        self.profile.scale(radius, self.inner_radius)
+    }
+
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, transform: &Affine3A) -> Vec3A {
+        let angle = rng.sample(Uniform::new(0.0, f32::consts::TAU));
+        let (sin, cos) = angle.sin_cos();
+        let h = self.half_thickness();
+
+        let inner_wall = self.wall_area(self.inner_radius);
+        let outer_wall = self.wall_area(self.outer_radius);
+        let pick = rng.sample(Uniform::new(0.0, self.area()));
+        let (radius, y) = if pick < inner_wall + outer_wall {
+            let radius = if pick < inner_wall {
+                self.inner_radius
+            } else {
+                self.outer_radius
+            };
+            (radius, rng.sample(Uniform::new_inclusive(-h, h)))
+        } else {
+            let r_in_sqr = self.inner_radius * self.inner_radius;
+            let r_out_sqr = self.outer_radius * self.outer_radius;
+            let radius = rng
+                .sample(Uniform::new_inclusive(r_in_sqr, r_out_sqr))
+                .sqrt();
+            let y = if pick < inner_wall + outer_wall + self.face_area() {
+                h
+            } else {
+                -h
+            };
+            (radius, y)
+        };
+
+        transform.transform_point3a(Vec3A::new(radius * cos, y, radius * sin))
+    }
+
+    pub fn pdf(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &Scene,
+    ) -> Option<f32> {
+        self.hit(object_ref, transform, ray, clip, scene)
+            .map(|manifold| area_pdf(transform, self.area(), ray, &manifold))
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        // the direction is left unnormalized so `t` is the same in both spaces
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+
+        let h = self.half_thickness();
+        let r_in_sqr = self.inner_radius * self.inner_radius;
+        let r_out_sqr = self.outer_radius * self.outer_radius;
+        let in_annulus = |p: Vec3A| {
+            let r_sqr = p.x * p.x + p.z * p.z;
+            r_sqr >= r_in_sqr && r_sqr <= r_out_sqr
+        };
+        let in_slab = |p: Vec3A| p.y.abs() <= h;
+
+        let mut result: Option<(f32, Vec3A)> = None;
+        let mut consider = |t: f32, normal: Vec3A| {
+            if t >= clip.min && t <= clip.max && result.is_none_or(|(best, _)| t < best) {
+                result = Some((t, normal));
+            }
+        };
+
+        // top and bottom faces
+        if direction.y.abs() > 1e-8 {
+            for y in [h, -h] {
+                let t = (y - origin.y) / direction.y;
+                if in_annulus(origin + direction * t) {
+                    consider(t, Vec3A::Y * y.signum());
+                }
+            }
+        }
+
+        // inner and outer walls
+        if h > 0.0 {
+            let a = direction.x * direction.x + direction.z * direction.z;
+            let half_b = origin.x * direction.x + origin.z * direction.z;
+            let c0 = origin.x * origin.x + origin.z * origin.z;
+            if a > 1e-8 {
+                for (r_sqr, sign) in [(r_in_sqr, -1.0), (r_out_sqr, 1.0)] {
+                    let discriminant = half_b * half_b - a * (c0 - r_sqr);
+                    if discriminant.is_sign_negative() {
+                        continue;
+                    }
+                    let sqrtd = discriminant.sqrt();
+                    for t in [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a] {
+                        let p = origin + direction * t;
+                        if in_slab(p) {
+                            consider(t, Vec3A::new(p.x, 0.0, p.z).normalize() * sign);
+                        }
+                    }
+                }
+            }
+        }
+
+        let (t, normal) = result?;
+        let local = origin + direction * t;
+        let radius = Vec2::new(local.x, local.z).length();
+        let uv = Vec2::new(
+            spherical_uv(local).x,
+            (radius - self.inner_radius) / (self.outer_radius - self.inner_radius),
+        );
+        let (tangent, bitangent) = tangent_frame(
+            transform,
+            spherical_tangent(local),
+            Vec3A::new(local.x, 0.0, local.z),
+        );
+
+        let normal = inverse.matrix3.transpose() * normal;
+        let normal = normal.normalize();
+        let (normal, face) = if ray.direction.dot(normal) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+
+        Some(Manifold {
+            position: ray.at(t),
+            normal,
+            uv,
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::scene::{Data, Material};
+
+    #[test]
+    fn temperature_profile() {
+        let inner_radius = 6.0;
+        let profile = TemperatureProfile::NovikovThorne;
+        // no torque at the inner edge keeps it cold, the hottest ring is a
+        // bit further out, and far away `T ∝ r^-3/4`
+        assert_eq!(profile.scale(inner_radius, inner_radius), 0.0);
+        let peak = 49.0 / 36.0 * inner_radius;
+        assert!((profile.scale(peak, inner_radius) - 1.0).abs() < 1e-5);
+        assert!(profile.scale(1.1 * peak, inner_radius) < 1.0);
+        assert!(profile.scale(0.9 * peak, inner_radius) < 1.0);
+        let far = profile.scale(2e5, inner_radius) / profile.scale(1e5, inner_radius);
+        assert!((far - 2.0f32.powf(-0.75)).abs() < 1e-3);
+
+        let profile = TemperatureProfile::PowerLaw { exponent: 0.5 };
+        assert_eq!(profile.scale(inner_radius, inner_radius), 1.0);
+        assert!((profile.scale(4.0 * inner_radius, inner_radius) - 0.5).abs() < 1e-6);
+
+        // radii are measured in the plane of the disk, and clamped to it
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::emissive(Default::default(), 1.0)));
+        let disk = AccretionDisk::new(material, 6.0, 20.0, 0.5, profile);
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::splat(2.0),
+            glam::Quat::from_rotation_x(f32::consts::FRAC_PI_2),
+            Vec3::new(1.0, 2.0, 3.0),
+        );
+        let position = transform.transform_point3a(Vec3A::new(0.0, 0.2, 12.0));
+        assert!((disk.temperature_scale(&transform, position) - 0.5f32.sqrt()).abs() < 1e-5);
+        let position = transform.transform_point3a(Vec3A::new(2.0, 0.0, 0.0));
+        assert!((disk.temperature_scale(&transform, position) - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,176 @@
+use std::f32;
+
+use glam::{Affine3A, Vec2, Vec3A};
+use rand::Rng;
+use rand_distr::Uniform;
+use serde::{Deserialize, Serialize};
+
+use super::{area_pdf, tangent_frame, transformed_bounds};
+use crate::math::{spherical_tangent, spherical_uv};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// A cone standing on a disk of `radius` in the local xz plane, with its
+/// apex `height` up the y axis.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Cone {
+    pub material: DataRef,
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl Cone {
+    pub fn new(material: DataRef, radius: f32, height: f32) -> Self {
+        Self {
+            material,
+            radius,
+            height,
+        }
+    }
+
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        let min = Vec3A::new(-self.radius, 0.0, -self.radius);
+        let max = Vec3A::new(self.radius, self.height, self.radius);
+        transformed_bounds(transform, (min, max))
+    }
+
+    fn side_area(&self) -> f32 {
+        let slant = self.radius.hypot(self.height);
+        f32::consts::PI * self.radius * slant
+    }
+
+    fn base_area(&self) -> f32 {
+        f32::consts::PI * self.radius * self.radius
+    }
+
+    pub fn area(&self) -> f32 {
+        self.side_area() + self.base_area()
+    }
+
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, transform: &Affine3A) -> Vec3A {
+        let phi = rng.sample(Uniform::new(0.0, f32::consts::TAU));
+        let (sin, cos) = phi.sin_cos();
+        // both the side and the base grow linearly in area with the
+        // distance from their center
+        let s = rng
+            .sample::<f32, _>(Uniform::new_inclusive(0.0, 1.0))
+            .sqrt();
+
+        let local = if rng.sample(Uniform::new(0.0, self.area())) < self.side_area() {
+            Vec3A::new(
+                self.radius * s * cos,
+                self.height * (1.0 - s),
+                self.radius * s * sin,
+            )
+        } else {
+            Vec3A::new(self.radius * s * cos, 0.0, self.radius * s * sin)
+        };
+
+        transform.transform_point3a(local)
+    }
+
+    pub fn pdf(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &Scene,
+    ) -> Option<f32> {
+        self.hit(object_ref, transform, ray, clip, scene)
+            .map(|manifold| area_pdf(transform, self.area(), ray, &manifold))
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        // the direction isn't normalized, so distances stay the same in
+        // local space
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+        let r_sqr = self.radius * self.radius;
+
+        // the local normal, texture coordinates and tangents of each hit
+        let mut closest: Option<(f32, Vec3A, Vec2, (Vec3A, Vec3A))> = None;
+        let mut consider = |t: f32, normal: Vec3A, uv: Vec2, frame: (Vec3A, Vec3A)| {
+            if t >= clip.min && t <= clip.max && closest.is_none_or(|(closest, ..)| t < closest) {
+                closest = Some((t, normal, uv, frame));
+            }
+        };
+
+        // the side is where x² + z² = k² (h - y)², with k the slope
+        let k = self.radius / self.height;
+        let k_sqr = k * k;
+        let below_apex = self.height - origin.y;
+        let a = direction.x * direction.x + direction.z * direction.z
+            - k_sqr * direction.y * direction.y;
+        let half_b =
+            origin.x * direction.x + origin.z * direction.z + k_sqr * below_apex * direction.y;
+        let c = origin.x * origin.x + origin.z * origin.z - k_sqr * below_apex * below_apex;
+
+        let roots = if a.abs() <= 1e-8 {
+            // parallel to the side, so there is a single crossing
+            [-0.5 * c / half_b, f32::NAN]
+        } else {
+            let discriminant = half_b * half_b - a * c;
+            let sqrtd = discriminant.sqrt();
+            [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a]
+        };
+        for t in roots.into_iter().filter(|t| t.is_finite()) {
+            let local = origin + direction * t;
+            if (0.0..=self.height).contains(&local.y) {
+                let normal = Vec3A::new(local.x, k_sqr * (self.height - local.y), local.z);
+                let uv = Vec2::new(spherical_uv(local).x, 1.0 - local.y / self.height);
+                let tangent = spherical_tangent(local);
+                consider(t, normal, uv, (tangent, normal.cross(tangent)));
+            }
+        }
+
+        if direction.y.abs() > 1e-8 {
+            let t = -origin.y / direction.y;
+            let local = origin + direction * t;
+            if local.x * local.x + local.z * local.z <= r_sqr {
+                let uv = Vec2::new(local.x, local.z) / (2.0 * self.radius) + 0.5;
+                consider(t, Vec3A::NEG_Y, uv, (Vec3A::X, Vec3A::Z));
+            }
+        }
+
+        let (t, normal, uv, (tangent, bitangent)) = closest?;
+        let (tangent, bitangent) = tangent_frame(transform, tangent, bitangent);
+        let normal = (inverse.matrix3.transpose() * normal).normalize_or_zero();
+        let normal = if normal == Vec3A::ZERO {
+            // the apex has no normal, so use the axis
+            transform.transform_vector3a(Vec3A::Y).normalize()
+        } else {
+            normal
+        };
+        let (normal, face) = if ray.direction.dot(normal) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+        Some(Manifold {
+            position: ray.at(t),
+            normal,
+            uv,
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
@@ -0,0 +1,252 @@
+use glam::Vec3A;
+use serde::{Deserialize, Serialize};
+
+use crate::scene::{ObjectRef, Scene};
+use crate::tracer::{Clip, Manifold, Ray};
+
+/// Distance past a crossing from which the next one is searched for,
+/// relative to the distance along the ray and the size of the operand.
+const CROSSING_OFFSET: f32 = 1e-5;
+
+/// Crossings followed along a ray through each operand before giving up.
+const MAX_CROSSINGS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// The first operand with the second one cut out of it.
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(self, a: bool, b: bool) -> bool {
+        match self {
+            Self::Union => a || b,
+            Self::Intersection => a && b,
+            Self::Difference => a && !b,
+        }
+    }
+}
+
+/// A boolean combination of two closed objects of the scene.
+///
+/// The operands keep their own transforms and materials, and should be
+/// flagged `HIDDEN` so they aren't hit on their own. Add them as children
+/// of this object to move them along with it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub a: ObjectRef,
+    pub b: ObjectRef,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, a: ObjectRef, b: ObjectRef) -> Self {
+        Self { operation, a, b }
+    }
+
+    pub fn bounding_box(&self, scene: &Scene) -> Option<(Vec3A, Vec3A)> {
+        let a = scene.get_object(self.a).bounding_box(scene);
+        let b = scene.get_object(self.b).bounding_box(scene);
+        match (self.operation, a, b) {
+            (CsgOperation::Union, Some((min_a, max_a)), Some((min_b, max_b))) => {
+                Some((min_a.min(min_b), max_a.max(max_b)))
+            }
+            (CsgOperation::Union, _, _) => None,
+            (CsgOperation::Intersection, Some((min_a, max_a)), Some((min_b, max_b))) => {
+                Some((min_a.max(min_b), max_a.min(max_b)))
+            }
+            (CsgOperation::Intersection, bounds, None)
+            | (CsgOperation::Intersection, None, bounds) => bounds,
+            (CsgOperation::Difference, bounds, _) => bounds,
+        }
+    }
+
+    /// Finds where the ray crosses the surface of the combined solid by
+    /// walking the intervals the ray spends inside each operand.
+    pub fn hit<'a>(&self, ray: &Ray, clip: &Clip, scene: &'a Scene) -> Option<Manifold<'a>> {
+        let (mut inside_a, crossings_a) = crossings(self.a, ray, clip, scene);
+        let (mut inside_b, crossings_b) = crossings(self.b, ray, clip, scene);
+        let mut inside = self.operation.contains(inside_a, inside_b);
+
+        let mut crossings: Vec<_> = crossings_a
+            .into_iter()
+            .map(|manifold| (true, manifold))
+            .chain(crossings_b.into_iter().map(|manifold| (false, manifold)))
+            .collect();
+        crossings.sort_by(|(_, a), (_, b)| a.t.total_cmp(&b.t));
+
+        for (from_a, mut manifold) in crossings {
+            let entering = manifold.face.is_front();
+            if from_a {
+                inside_a = entering;
+            } else {
+                inside_b = entering;
+            }
+
+            let was_inside = inside;
+            inside = self.operation.contains(inside_a, inside_b);
+            if inside != was_inside {
+                // the normals of the operands already face the ray, only
+                // the side the ray is on can be the other way around
+                if inside != entering {
+                    manifold.face = manifold.face.flip();
+                }
+                return Some(manifold);
+            }
+        }
+
+        None
+    }
+}
+
+/// Whether the ray starts inside the object at `clip.min`, and where it
+/// crosses the object's surface within `clip`.
+///
+/// The first crossing is looked for past `clip.max` too, as the ray can lie
+/// inside the object all the way through `clip`.
+fn crossings<'a>(
+    object_ref: ObjectRef,
+    ray: &Ray,
+    clip: &Clip,
+    scene: &'a Scene,
+) -> (bool, Vec<Manifold<'a>>) {
+    let object = scene.get_object(object_ref);
+    // in the units of `t`, as rays passed down by instances aren't normalized
+    let size = object
+        .bounding_box(scene)
+        .map_or(0.0, |(min, max)| (max - min).max_element())
+        / ray.direction.length();
+    let mut min = clip.min;
+    let mut inside = None;
+    let mut crossings = Vec::new();
+
+    for _ in 0..MAX_CROSSINGS {
+        let search = Clip {
+            min,
+            max: f32::INFINITY,
+        };
+        let manifold = match object.hit(ray, &search, scene) {
+            Some(manifold) => manifold,
+            None => break,
+        };
+
+        inside.get_or_insert(manifold.face.is_back());
+        if manifold.t > clip.max {
+            break;
+        }
+        min = manifold.t + CROSSING_OFFSET * (manifold.t.abs() + size);
+        crossings.push(manifold);
+    }
+
+    (inside.unwrap_or(false), crossings)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Affine3A, Vec3};
+
+    use super::*;
+    use crate::scene::{Cuboid, Data, Material, Object, ObjectFlags, Sphere};
+    use crate::tracer::{Bvh, Face};
+
+    #[test]
+    fn intervals() {
+        let mut scene = Scene::default();
+        let glass = scene.add_data(Data::new(Material::glass(Default::default(), 0.0, 1.5)));
+        let ball =
+            scene.add_object(Object::new(Sphere::new(glass, 1.0)).with_flags(ObjectFlags::HIDDEN));
+        // covers z > 0.5
+        let slab = scene.add_object(
+            Object::new(Cuboid::new(glass, Vec3A::X * 4.0, Vec3A::Y * 4.0, Vec3A::Z))
+                .with_transform(Affine3A::from_translation(Vec3::new(0.0, 0.0, 1.5)))
+                .with_flags(ObjectFlags::HIDDEN),
+        );
+
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let down = Ray::new(Vec3A::new(0.0, 0.0, 5.0), Vec3A::NEG_Z);
+        let hit = |operation, ray: &Ray, clip: &Clip, scene: &Scene| {
+            let object = scene.get_object(scene.find_by_tag(&format!("{operation:?}")).unwrap());
+            object
+                .hit(ray, clip, scene)
+                .map(|manifold| (manifold.t, manifold.face))
+        };
+        for operation in [
+            CsgOperation::Union,
+            CsgOperation::Intersection,
+            CsgOperation::Difference,
+        ] {
+            scene.add_object(
+                Object::new(Csg::new(operation, ball, slab)).with_tag(format!("{operation:?}")),
+            );
+        }
+
+        // the slab is entered first, and its back face is what's left
+        // at the cut of the difference
+        let (t, face) = hit(CsgOperation::Union, &down, &clip, &scene).unwrap();
+        assert!((t - 2.5).abs() < 1e-4 && face == Face::Front);
+        let (t, face) = hit(CsgOperation::Intersection, &down, &clip, &scene).unwrap();
+        assert!((t - 4.0).abs() < 1e-4 && face == Face::Front);
+        let (t, face) = hit(CsgOperation::Difference, &down, &clip, &scene).unwrap();
+        assert!((t - 4.5).abs() < 1e-4 && face == Face::Front);
+
+        // leaving the cut ball, from inside it
+        let inside = Clip { min: 4.6, ..clip };
+        let (t, face) = hit(CsgOperation::Difference, &down, &inside, &scene).unwrap();
+        assert!((t - 6.0).abs() < 1e-4 && face == Face::Back);
+
+        // the ray stays inside the solid all through a short clip
+        let short = Clip { min: 4.6, max: 5.5 };
+        assert!(hit(CsgOperation::Difference, &down, &short, &scene).is_none());
+
+        // from below, the cut is where the intersection is entered and
+        // the difference is left
+        let up = Ray::new(Vec3A::new(0.0, 0.0, -5.0), Vec3A::Z);
+        let (t, face) = hit(CsgOperation::Intersection, &up, &clip, &scene).unwrap();
+        assert!((t - 5.5).abs() < 1e-4 && face == Face::Front);
+        let (t, face) = hit(CsgOperation::Difference, &up, &inside, &scene).unwrap();
+        assert!((t - 5.5).abs() < 1e-4 && face == Face::Back);
+
+        // only the combinations are hit through the scene
+        assert_eq!(Bvh::from_scene(&scene).len(), 3);
+    }
+
+    #[test]
+    fn thin_operands() {
+        let mut scene = Scene::default();
+        let glass = scene.add_data(Data::new(Material::glass(Default::default(), 0.0, 1.5)));
+        let ball = scene.add_object(
+            Object::new(Sphere::new(glass, 1.0))
+                .with_translation(Vec3A::new(0.0, 0.0, -2.0))
+                .with_flags(ObjectFlags::HIDDEN),
+        );
+        let foil = scene.add_object(
+            Object::new(Cuboid::new(
+                glass,
+                Vec3A::X * 4.0,
+                Vec3A::Y * 4.0,
+                Vec3A::Z * 2e-4,
+            ))
+            .with_flags(ObjectFlags::HIDDEN),
+        );
+        let csg = Csg::new(CsgOperation::Intersection, foil, ball);
+
+        // the ray leaves the foil long before it reaches the ball
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let down = Ray::new(Vec3A::new(0.0, 0.0, 5.0), Vec3A::NEG_Z);
+        assert!(csg.hit(&down, &clip, &scene).is_none());
+
+        // starting halfway through the foil
+        let through = Csg::new(CsgOperation::Union, foil, ball);
+        let inside = Clip { min: 5.0, ..clip };
+        let manifold = through.hit(&down, &inside, &scene).unwrap();
+        assert!((manifold.t - 5.0002).abs() < 1e-5 && manifold.face == Face::Back);
+    }
+}
@@ -19,8 +19,8 @@ impl Cuboid {
     pub fn new(material: DataRef, x: Vec3A, y: Vec3A, z: Vec3A) -> Self {
         Self {
             faces: Box::new([
-                (-z, Rect::new(material, x, y)),
-                (z, Rect::new(material, -x, y)),
+                (-z, Rect::new(material, -x, y)),
+                (z, Rect::new(material, x, y)),
                 (-x, Rect::new(material, z, y)),
                 (x, Rect::new(material, -z, y)),
                 (-y, Rect::new(material, x, z)),
@@ -104,3 +104,51 @@ impl Cuboid {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::LinearRgb;
+    use crate::scene::{Data, Material, Object};
+    use crate::tracer::Face;
+
+    #[test]
+    fn faces() {
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::diffuse(LinearRgb::WHITE, 1.0)));
+        let cuboid = scene.add_object(Object::new(Cuboid::new(
+            material,
+            Vec3A::X,
+            2.0 * Vec3A::Y,
+            3.0 * Vec3A::Z,
+        )));
+
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let hit = |origin: Vec3A, direction: Vec3A| {
+            let ray = Ray::new(origin, direction);
+            scene.get_object(cuboid).hit(&ray, &clip, &scene).unwrap()
+        };
+
+        // every face is entered from the front and left from the back, with
+        // the normal against the ray
+        for axis in [
+            Vec3A::X,
+            Vec3A::NEG_X,
+            Vec3A::Y,
+            Vec3A::NEG_Y,
+            Vec3A::Z,
+            Vec3A::NEG_Z,
+        ] {
+            let outside = hit(-10.0 * axis, axis);
+            assert_eq!(outside.face, Face::Front, "entering along {axis}");
+            assert!(outside.normal.abs_diff_eq(-axis, 1e-4));
+
+            let inside = hit(Vec3A::ZERO, axis);
+            assert_eq!(inside.face, Face::Back, "leaving along {axis}");
+            assert!(inside.normal.abs_diff_eq(-axis, 1e-4));
+        }
+    }
+}
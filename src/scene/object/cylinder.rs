@@ -0,0 +1,162 @@
+use std::f32;
+
+use glam::{Affine3A, Vec2, Vec3A};
+use rand::Rng;
+use rand_distr::Uniform;
+use serde::{Deserialize, Serialize};
+
+use super::{area_pdf, tangent_frame, transformed_bounds};
+use crate::math::{spherical_tangent, spherical_uv};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// A cylinder around the local y axis, closed by a disk at either end.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Cylinder {
+    pub material: DataRef,
+    pub radius: f32,
+    pub half_height: f32,
+}
+
+impl Cylinder {
+    pub fn new(material: DataRef, radius: f32, half_height: f32) -> Self {
+        Self {
+            material,
+            radius,
+            half_height,
+        }
+    }
+
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        let half_size = Vec3A::new(self.radius, self.half_height, self.radius);
+        transformed_bounds(transform, (-half_size, half_size))
+    }
+
+    fn side_area(&self) -> f32 {
+        2.0 * f32::consts::TAU * self.radius * self.half_height
+    }
+
+    fn cap_area(&self) -> f32 {
+        f32::consts::PI * self.radius * self.radius
+    }
+
+    pub fn area(&self) -> f32 {
+        self.side_area() + 2.0 * self.cap_area()
+    }
+
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, transform: &Affine3A) -> Vec3A {
+        let phi = rng.sample(Uniform::new(0.0, f32::consts::TAU));
+        let (sin, cos) = phi.sin_cos();
+
+        let pick = rng.sample(Uniform::new(0.0, self.area()));
+        let local = if pick < self.side_area() {
+            let y = rng.sample(Uniform::new_inclusive(-self.half_height, self.half_height));
+            Vec3A::new(self.radius * cos, y, self.radius * sin)
+        } else {
+            let r = self.radius
+                * rng
+                    .sample::<f32, _>(Uniform::new_inclusive(0.0, 1.0))
+                    .sqrt();
+            let y = if pick < self.side_area() + self.cap_area() {
+                self.half_height
+            } else {
+                -self.half_height
+            };
+            Vec3A::new(r * cos, y, r * sin)
+        };
+
+        transform.transform_point3a(local)
+    }
+
+    pub fn pdf(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &Scene,
+    ) -> Option<f32> {
+        self.hit(object_ref, transform, ray, clip, scene)
+            .map(|manifold| area_pdf(transform, self.area(), ray, &manifold))
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        // the direction isn't normalized, so distances stay the same in
+        // local space
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+        let r_sqr = self.radius * self.radius;
+
+        // the local normal, texture coordinates and tangents of each hit
+        let mut closest: Option<(f32, Vec3A, Vec2, (Vec3A, Vec3A))> = None;
+        let mut consider = |t: f32, normal: Vec3A, uv: Vec2, frame: (Vec3A, Vec3A)| {
+            if t >= clip.min && t <= clip.max && closest.is_none_or(|(closest, ..)| t < closest) {
+                closest = Some((t, normal, uv, frame));
+            }
+        };
+
+        let a = direction.x * direction.x + direction.z * direction.z;
+        let half_b = origin.x * direction.x + origin.z * direction.z;
+        let c = origin.x * origin.x + origin.z * origin.z - r_sqr;
+        let discriminant = half_b * half_b - a * c;
+        if a > 0.0 && discriminant >= 0.0 {
+            let sqrtd = discriminant.sqrt();
+            for t in [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a] {
+                let local = origin + direction * t;
+                if local.y.abs() <= self.half_height {
+                    let uv = Vec2::new(
+                        spherical_uv(local).x,
+                        0.5 - 0.5 * local.y / self.half_height,
+                    );
+                    let frame = (spherical_tangent(local), Vec3A::NEG_Y);
+                    consider(t, Vec3A::new(local.x, 0.0, local.z), uv, frame);
+                }
+            }
+        }
+
+        if direction.y.abs() > 1e-8 {
+            for y in [-self.half_height, self.half_height] {
+                let t = (y - origin.y) / direction.y;
+                let local = origin + direction * t;
+                if local.x * local.x + local.z * local.z <= r_sqr {
+                    let uv = Vec2::new(local.x, local.z) / (2.0 * self.radius) + 0.5;
+                    consider(t, Vec3A::new(0.0, y, 0.0), uv, (Vec3A::X, Vec3A::Z));
+                }
+            }
+        }
+
+        let (t, normal, uv, (tangent, bitangent)) = closest?;
+        let (tangent, bitangent) = tangent_frame(transform, tangent, bitangent);
+        let normal = (inverse.matrix3.transpose() * normal).normalize();
+        let (normal, face) = if ray.direction.dot(normal) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+        Some(Manifold {
+            position: ray.at(t),
+            normal,
+            uv,
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
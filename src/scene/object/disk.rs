@@ -0,0 +1,106 @@
+use std::f32;
+
+use glam::{Affine3A, Vec2, Vec3A};
+use rand::Rng;
+use rand_distr::Uniform;
+use serde::{Deserialize, Serialize};
+
+use super::{area_pdf, tangent_frame, transformed_bounds};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// A flat disk in the local xz plane, facing the y axis.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Disk {
+    pub material: DataRef,
+    pub radius: f32,
+}
+
+impl Disk {
+    pub fn new(material: DataRef, radius: f32) -> Self {
+        Self { material, radius }
+    }
+
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        let half_size = Vec3A::new(self.radius, 0.0, self.radius);
+        transformed_bounds(transform, (-half_size, half_size))
+    }
+
+    pub fn area(&self) -> f32 {
+        f32::consts::PI * self.radius * self.radius
+    }
+
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, transform: &Affine3A) -> Vec3A {
+        let r = self.radius
+            * rng
+                .sample::<f32, _>(Uniform::new_inclusive(0.0, 1.0))
+                .sqrt();
+        let phi = rng.sample(Uniform::new(0.0, f32::consts::TAU));
+        transform.transform_point3a(Vec3A::new(r * phi.cos(), 0.0, r * phi.sin()))
+    }
+
+    pub fn pdf(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &Scene,
+    ) -> Option<f32> {
+        self.hit(object_ref, transform, ray, clip, scene)
+            .map(|manifold| area_pdf(transform, self.area(), ray, &manifold))
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        // the direction isn't normalized, so distances stay the same in
+        // local space
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+
+        if direction.y.abs() <= 1e-8 {
+            return None;
+        }
+        let t = -origin.y / direction.y;
+        if t < clip.min || t > clip.max {
+            return None;
+        }
+
+        let local = origin + direction * t;
+        if local.x * local.x + local.z * local.z > self.radius * self.radius {
+            return None;
+        }
+
+        let normal = (inverse.matrix3.transpose() * Vec3A::Y).normalize();
+        let (tangent, bitangent) = tangent_frame(transform, Vec3A::X, Vec3A::Z);
+        let (normal, face) = if ray.direction.dot(normal) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+        Some(Manifold {
+            position: ray.at(t),
+            normal,
+            uv: Vec2::new(local.x, local.z) / (2.0 * self.radius) + 0.5,
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use glam::{Affine3A, Vec2, Vec3A};
+use serde::{Deserialize, Serialize};
+
+use super::{tangent_frame, transformed_bounds};
+use crate::scene::{DataRef, HeightMap, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// Terrain following the `HeightMap` in `map`, over `[-1, 1]` along the
+/// local x and z axes, shaded with `material`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Heightfield {
+    pub map: DataRef,
+    pub material: DataRef,
+    /// Bounds of the map in local space, kept here so the object can be
+    /// placed in the scene's hierarchy without looking up its data.
+    bounds: (Vec3A, Vec3A),
+}
+
+impl Heightfield {
+    pub fn new(map_ref: DataRef, map: &HeightMap, material: DataRef) -> Self {
+        Self {
+            map: map_ref,
+            material,
+            bounds: map.bounding_box(),
+        }
+    }
+
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        transformed_bounds(transform, self.bounds)
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        let map = scene
+            .get_data(self.map)
+            .as_height_map()
+            .expect("expected height map data");
+
+        // the direction isn't normalized, so distances stay the same in
+        // local space
+        let inverse = transform.inverse();
+        let local = Ray {
+            origin: inverse.transform_point3a(ray.origin),
+            direction: inverse.transform_vector3a(ray.direction),
+        };
+        let (t, face_normal) = map.hit(&local, clip)?;
+
+        let to_world = |normal: Vec3A| (inverse.matrix3.transpose() * normal).normalize();
+        let face_normal = to_world(face_normal);
+        let normal = to_world(map.normal(local.at(t)));
+        let uv = (Vec2::new(local.at(t).x, local.at(t).z) + 1.0) * 0.5;
+        let (tangent, bitangent) = tangent_frame(transform, Vec3A::X, Vec3A::Z);
+
+        let (normal, face) = if face_normal.dot(ray.direction) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+
+        Some(Manifold {
+            position: ray.at(t),
+            normal,
+            uv,
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
@@ -0,0 +1,189 @@
+use glam::{Affine3A, Vec3A};
+use serde::{Deserialize, Serialize};
+
+use super::{tangent_frame, transformed_bounds};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Manifold, Ray};
+
+/// A copy of the subtree of objects under `prototype`, placed by the
+/// transform of this object and optionally shaded with another material.
+///
+/// The subtree is laid out in prototype space by its own transforms, and
+/// isn't rendered on its own. Rays are moved into prototype space and
+/// traced through the subtree's hierarchy, which the scene builds once and
+/// shares between all instances of the prototype.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Instance {
+    pub prototype: ObjectRef,
+    pub material: Option<DataRef>,
+}
+
+impl Instance {
+    pub fn new(prototype: ObjectRef) -> Self {
+        Self {
+            prototype,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: DataRef) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    /// Bounds in world space, or `None` if the prototype has unbounded
+    /// objects in it.
+    pub fn bounding_box(&self, transform: &Affine3A, scene: &Scene) -> Option<(Vec3A, Vec3A)> {
+        let bvh = scene.prototype(self.prototype)?;
+        let bounds = bvh.bounds();
+        bvh.is_bounded()
+            .then(|| transformed_bounds(transform, (bounds.min, bounds.max)))
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        let bvh = scene.prototype(self.prototype)?;
+
+        // the direction isn't normalized, so distances stay the same in
+        // prototype space
+        let inverse = transform.inverse();
+        let local = Ray {
+            origin: inverse.transform_point3a(ray.origin),
+            direction: inverse.transform_vector3a(ray.direction),
+        };
+        let manifold = bvh.hit(&local, clip, |object_ref, clip| {
+            scene.get_object(object_ref).hit(&local, clip, scene)
+        })?;
+
+        // the prototype's objects are placed for prototype space, so
+        // anything looked up through the hit object is this instance
+        let (tangent, bitangent) = tangent_frame(transform, manifold.tangent, manifold.bitangent);
+        Some(Manifold {
+            position: ray.at(manifold.t),
+            normal: (inverse.matrix3.transpose() * manifold.normal).normalize(),
+            tangent,
+            bitangent,
+            bbox: transformed_bounds(transform, manifold.bbox),
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: self.material.or(manifold.mat_ref),
+            ..manifold
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+
+    use super::*;
+    use crate::scene::{Data, MassivePoint, Material, Object, Sphere, Update, UpdateQueue};
+    use crate::tracer::{Bvh, Face, Spacetime};
+
+    #[test]
+    fn shared_prototype() {
+        let mut scene = Scene::default();
+        let white = scene.add_data(Data::new(Material::diffuse(Default::default(), 1.0)));
+        let red = scene.add_data(Data::new(Material::diffuse(Default::default(), 1.0)));
+
+        // two balls side by side, moved away from the origin
+        let prototype = scene.add_object(
+            Object::new(Sphere::new(white, 1.0)).with_translation(Vec3A::new(0.0, 10.0, 0.0)),
+        );
+        let child = scene.add_object(Object::new(Sphere::new(white, 1.0)));
+        let mass = scene.add_object(Object::new(MassivePoint::new(1.0)));
+        let mut update_queue = UpdateQueue::new();
+        update_queue.push(Update::object(prototype, move |object, update_queue, _| {
+            object.add(update_queue, child);
+            object.add(update_queue, mass);
+        }));
+        update_queue.push(Update::object(child, |object, update_queue, _| {
+            object.apply_transform(
+                update_queue,
+                Affine3A::from_translation(Vec3::new(3.0, 0.0, 0.0)),
+            )
+        }));
+        update_queue.commit(&mut scene);
+
+        let plain = scene.add_object(
+            Object::new(Instance::new(prototype))
+                .with_transform(Affine3A::from_translation(Vec3::new(0.0, -10.0, -10.0))),
+        );
+        let turned = scene.add_object(
+            Object::new(Instance::new(prototype).with_material(red)).with_transform(
+                Affine3A::from_scale_rotation_translation(
+                    Vec3::splat(2.0),
+                    Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+                    Vec3::new(0.0, -20.0, -10.0),
+                ),
+            ),
+        );
+
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        let hit = |object_ref, origin: Vec3A| {
+            let ray = Ray::new(origin, Vec3A::NEG_Z);
+            scene.get_object(object_ref).hit(&ray, &clip, &scene)
+        };
+
+        let manifold = hit(plain, Vec3A::new(3.0, 0.0, 0.0)).unwrap();
+        assert!((manifold.t - 9.0).abs() < 1e-4);
+        assert!(manifold
+            .position
+            .abs_diff_eq(Vec3A::new(3.0, 0.0, -9.0), 1e-4));
+        assert_eq!(manifold.mat_ref, Some(white));
+        assert_eq!(manifold.object_ref, Some(plain));
+
+        // the balls now lie along z, twice as far apart and twice as large
+        assert!(hit(turned, Vec3A::new(3.0, 0.0, 0.0)).is_none());
+        let manifold = hit(turned, Vec3A::new(1.0, 0.0, 0.0)).unwrap();
+        let expected = -10.0 + 3.0f32.sqrt();
+        assert!(manifold
+            .position
+            .abs_diff_eq(Vec3A::new(1.0, 0.0, expected), 1e-3));
+        assert!(manifold
+            .normal
+            .abs_diff_eq(Vec3A::new(0.5, 0.0, 0.75f32.sqrt()), 1e-3));
+        assert_eq!(manifold.face, Face::Front);
+        assert_eq!(manifold.mat_ref, Some(red));
+
+        let (min, max) = scene.get_object(turned).bounding_box(&scene).unwrap();
+        assert!(min.abs_diff_eq(Vec3A::new(-2.0, -2.0, -18.0), 1e-3));
+        assert!(max.abs_diff_eq(Vec3A::new(2.0, 2.0, -8.0), 1e-3));
+
+        // only the instances are in the scene's hierarchy and spacetime
+        assert_eq!(Bvh::from_scene(&scene).len(), 2);
+        assert!(scene.is_instanced(mass) && !scene.is_instanced(plain));
+        assert!(Spacetime::from_scene(&scene).lenses().is_empty());
+
+        // updates elsewhere leave the prototype's hierarchy alone, moving
+        // one of its objects rebuilds it
+        let before: *const Bvh = scene.prototype(prototype).unwrap();
+        update_queue.push(Update::object(plain, |object, update_queue, _| {
+            object.apply_transform(update_queue, Affine3A::from_translation(Vec3::X))
+        }));
+        update_queue.commit(&mut scene);
+        assert!(std::ptr::eq(before, scene.prototype(prototype).unwrap()));
+
+        update_queue.push(Update::object(child, |object, update_queue, _| {
+            object.apply_transform(update_queue, Affine3A::from_translation(Vec3::X * 2.0))
+        }));
+        update_queue.commit(&mut scene);
+        let object = scene.get_object(plain);
+        let ray = Ray::new(Vec3A::new(4.0, 0.0, 0.0), Vec3A::NEG_Z);
+        assert!(object.hit(&ray, &clip, &scene).is_none());
+        let ray = Ray::new(Vec3A::new(6.0, 0.0, 0.0), Vec3A::NEG_Z);
+        let manifold = object.hit(&ray, &clip, &scene).unwrap();
+        assert!((manifold.t - 9.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,39 @@
+use std::f32;
+
+use glam::Vec3A;
+use serde::{Deserialize, Serialize};
+
+use crate::tracer::{Lens, MassProfile};
+
+/// A singular isothermal sphere, the simplest model of a galaxy's halo, in
+/// geometrized units (`G = c = 1`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct IsothermalSphere {
+    pub velocity_dispersion: f32,
+    pub truncation_radius: f32,
+}
+
+impl IsothermalSphere {
+    pub fn new(velocity_dispersion: f32, truncation_radius: f32) -> Self {
+        Self {
+            velocity_dispersion,
+            truncation_radius,
+        }
+    }
+
+    pub fn profile(&self) -> MassProfile {
+        MassProfile::Isothermal {
+            velocity_dispersion: self.velocity_dispersion,
+            truncation_radius: self.truncation_radius,
+        }
+    }
+
+    /// Deflection angle `4πσ²` of rays passing inside the truncation radius.
+    pub fn deflection_angle(&self) -> f32 {
+        2.0 * f32::consts::TAU * self.velocity_dispersion * self.velocity_dispersion
+    }
+
+    pub fn lens(&self, translation: Vec3A) -> Lens {
+        Lens::halo(translation, self.profile())
+    }
+}
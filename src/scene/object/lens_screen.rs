@@ -0,0 +1,37 @@
+use glam::{Affine3A, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::scene::{DataRef, Scene};
+use crate::tracer::Screen;
+
+/// A thin lens in the local xz plane that bends rays by the angles in a
+/// `DeflectionMap`, stretched over `width` along x and `height` along z.
+///
+/// This reproduces observed lens systems from their measured deflection
+/// without modelling the mass behind them.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LensScreen {
+    pub map: DataRef,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl LensScreen {
+    pub fn new(map: DataRef, width: f32, height: f32) -> Self {
+        Self { map, width, height }
+    }
+
+    pub fn screen(&self, transform: &Affine3A, scene: &Scene) -> Screen {
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+        let map = scene
+            .get_data(self.map)
+            .as_deflection_map()
+            .expect("lens screen data must be a deflection map");
+        Screen::map(
+            translation.into(),
+            rotation,
+            map.clone(),
+            Vec2::new(self.width, self.height),
+        )
+    }
+}
@@ -0,0 +1,28 @@
+use glam::Vec3A;
+use serde::{Deserialize, Serialize};
+
+use crate::tracer::Lens;
+
+/// A non-rotating point mass in geometrized units (`G = c = 1`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MassivePoint {
+    pub mass: f32,
+}
+
+impl MassivePoint {
+    pub fn new(mass: f32) -> Self {
+        Self { mass }
+    }
+
+    pub fn with_schwarzschild_radius(radius: f32) -> Self {
+        Self { mass: 0.5 * radius }
+    }
+
+    pub fn schwarzschild_radius(&self) -> f32 {
+        2.0 * self.mass
+    }
+
+    pub fn lens(&self, translation: Vec3A) -> Lens {
+        Lens::schwarzschild(translation, self.mass)
+    }
+}
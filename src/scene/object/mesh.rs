@@ -0,0 +1,83 @@
+use glam::{Affine3A, Vec3A};
+use serde::{Deserialize, Serialize};
+
+use super::{tangent_frame, transformed_bounds};
+use crate::scene::{DataRef, ObjectRef, Scene, TriangleMesh};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// An instance of the `TriangleMesh` in `mesh`, shaded with `material`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Mesh {
+    pub mesh: DataRef,
+    pub material: DataRef,
+    /// Bounds of the mesh in local space, kept here so the object can be
+    /// placed in the scene's hierarchy without looking up its data.
+    bounds: (Vec3A, Vec3A),
+}
+
+impl Mesh {
+    pub fn new(mesh_ref: DataRef, mesh: &TriangleMesh, material: DataRef) -> Self {
+        Self {
+            mesh: mesh_ref,
+            material,
+            bounds: mesh.bounding_box(),
+        }
+    }
+
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        transformed_bounds(transform, self.bounds)
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        let mesh = scene
+            .get_data(self.mesh)
+            .as_mesh()
+            .expect("expected mesh data");
+
+        // the direction isn't normalized, so distances stay the same in
+        // local space
+        let inverse = transform.inverse();
+        let local = Ray {
+            origin: inverse.transform_point3a(ray.origin),
+            direction: inverse.transform_vector3a(ray.direction),
+        };
+        let hit = mesh.hit(&local, clip)?;
+
+        let to_world = |normal: Vec3A| (inverse.matrix3.transpose() * normal).normalize();
+        let face_normal = to_world(mesh.face_normal(&hit));
+        let normal = to_world(mesh.normal(&hit));
+        let (tangent, bitangent) = mesh.tangents(&hit);
+        let (tangent, bitangent) = tangent_frame(transform, tangent, bitangent);
+
+        let (normal, face) = if face_normal.dot(ray.direction) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+
+        Some(Manifold {
+            position: ray.at(hit.t),
+            normal,
+            uv: mesh.uv(&hit).unwrap_or(hit.barycentric),
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
+            face,
+            t: hit.t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
@@ -1,29 +1,67 @@
 use bitflags::bitflags;
-use glam::{Affine3A, Quat, Vec3A};
+use glam::{Affine3A, BVec3A, Quat, Vec3A};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use crate::tracer::{Clip, Manifold, Ray};
+use crate::tracer::{Clip, Emitter, Lens, Manifold, Ray, Screen};
 
 use super::{Scene, Update, UpdateQueue};
 
+mod accretion_disk;
 mod camera;
+mod cone;
+mod csg;
 mod cuboid;
+mod cylinder;
+mod disk;
+mod heightfield;
+mod instance;
+mod isothermal_sphere;
+mod lens_screen;
+mod massive_point;
+mod mesh;
+mod nfw_halo;
+mod plane;
 mod rect;
+mod rotating_mass;
+mod sdf;
 mod sphere;
+mod torus;
 mod transform;
+mod uniform_disk;
+mod wormhole;
 
 use self::transform::{Space, Transform};
 
+pub use self::accretion_disk::{AccretionDisk, TemperatureProfile};
 pub use self::camera::Camera;
+pub use self::cone::Cone;
+pub use self::csg::{Csg, CsgOperation};
 pub use self::cuboid::Cuboid;
+pub use self::cylinder::Cylinder;
+pub use self::disk::Disk;
+pub use self::heightfield::Heightfield;
+pub use self::instance::Instance;
+pub use self::isothermal_sphere::IsothermalSphere;
+pub use self::lens_screen::LensScreen;
+pub use self::massive_point::MassivePoint;
+pub use self::mesh::Mesh;
+pub use self::nfw_halo::NfwHalo;
+pub use self::plane::Plane;
 pub use self::rect::Rect;
+pub use self::rotating_mass::RotatingMass;
+pub use self::sdf::{Sdf, SdfExpr};
 pub use self::sphere::Sphere;
+pub use self::torus::Torus;
+pub use self::uniform_disk::UniformDisk;
+pub use self::wormhole::{Passage, Wormhole};
 
 bitflags! {
     #[derive(Default, Serialize, Deserialize)]
     pub struct ObjectFlags: u32 {
         const LIGHT = 0x1;
+        /// Only hit through the `Csg` objects it is an operand of.
+        const HIDDEN = 0x2;
     }
 }
 
@@ -133,20 +171,97 @@ impl Object {
         }
     }
 
-    pub fn bounding_box(&self) -> Option<(Vec3A, Vec3A)> {
+    pub fn as_instance(&self) -> Option<&Instance> {
         match self.inner() {
-            ObjectKind::Sphere(sphere) => Some(sphere.bounding_box(self.transform().translation)),
+            ObjectKind::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+
+    pub fn as_wormhole(&self) -> Option<&Wormhole> {
+        match self.inner() {
+            ObjectKind::Wormhole(wormhole) => Some(wormhole),
+            _ => None,
+        }
+    }
+
+    pub fn bounding_box(&self, scene: &Scene) -> Option<(Vec3A, Vec3A)> {
+        match self.inner() {
+            ObjectKind::Sphere(sphere) => Some(sphere.bounding_box(self.transform())),
             ObjectKind::Rect(rect) => Some(rect.bounding_box(self.transform())),
             ObjectKind::Cuboid(cuboid) => Some(cuboid.bounding_box(self.transform())),
+            ObjectKind::AccretionDisk(disk) => Some(disk.bounding_box(self.transform())),
+            ObjectKind::Mesh(mesh) => Some(mesh.bounding_box(self.transform())),
+            ObjectKind::Heightfield(heightfield) => {
+                Some(heightfield.bounding_box(self.transform()))
+            }
+            ObjectKind::Disk(disk) => Some(disk.bounding_box(self.transform())),
+            ObjectKind::Cylinder(cylinder) => Some(cylinder.bounding_box(self.transform())),
+            ObjectKind::Cone(cone) => Some(cone.bounding_box(self.transform())),
+            ObjectKind::Torus(torus) => Some(torus.bounding_box(self.transform())),
+            ObjectKind::Sdf(sdf) => sdf.bounding_box(self.transform()),
+            ObjectKind::Csg(csg) => csg.bounding_box(scene),
+            ObjectKind::Instance(instance) => instance.bounding_box(self.transform(), scene),
+            _ => None,
+        }
+    }
+
+    pub fn lens(&self) -> Option<Lens> {
+        match self.inner() {
+            ObjectKind::MassivePoint(point) => Some(point.lens(self.transform().translation)),
+            ObjectKind::RotatingMass(mass) => Some(mass.lens(self.transform())),
+            ObjectKind::IsothermalSphere(sphere) => Some(sphere.lens(self.transform().translation)),
+            ObjectKind::NfwHalo(halo) => Some(halo.lens(self.transform().translation)),
+            _ => None,
+        }
+    }
+
+    pub fn screen(&self, scene: &Scene) -> Option<Screen> {
+        match self.inner() {
+            ObjectKind::UniformDisk(disk) => Some(disk.screen(self.transform())),
+            ObjectKind::LensScreen(screen) => Some(screen.screen(self.transform(), scene)),
             _ => None,
         }
     }
 
+    /// Scale applied to the temperature of blackbody materials at `position`.
+    pub fn temperature_scale(&self, position: Vec3A) -> f32 {
+        match self.inner() {
+            ObjectKind::AccretionDisk(disk) => disk.temperature_scale(self.transform(), position),
+            _ => 1.0,
+        }
+    }
+
+    /// Where `position` falls in the unit cube that volumes inside this
+    /// object are mapped onto, following the object's orientation.
+    pub fn volume_coord(&self, position: Vec3A) -> Option<Vec3A> {
+        match self.inner() {
+            ObjectKind::Sphere(sphere) => Some(sphere.volume_coord(self.transform(), position)),
+            _ => None,
+        }
+    }
+
+    pub fn emitter(&self) -> Emitter {
+        match self.inner() {
+            ObjectKind::AccretionDisk(_) => Emitter::Orbit {
+                center: self.transform().translation,
+                axis: self.transform().transform_vector3a(Vec3A::Y).normalize(),
+            },
+            _ => Emitter::Static,
+        }
+    }
+
     pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec3A {
         match self.inner() {
-            ObjectKind::Sphere(sphere) => sphere.random_point(rng, self.transform().translation),
+            ObjectKind::Sphere(sphere) => sphere.random_point(rng, self.transform()),
             ObjectKind::Rect(rect) => rect.random_point(rng, self.transform()),
             ObjectKind::Cuboid(cuboid) => cuboid.random_point(rng, self.transform()),
+            ObjectKind::AccretionDisk(disk) => disk.random_point(rng, self.transform()),
+            ObjectKind::Disk(disk) => disk.random_point(rng, self.transform()),
+            ObjectKind::Cylinder(cylinder) => cylinder.random_point(rng, self.transform()),
+            ObjectKind::Cone(cone) => cone.random_point(rng, self.transform()),
+            ObjectKind::Torus(torus) => torus.random_point(rng, self.transform()),
+            ObjectKind::Plane(plane) => plane.random_point(rng, self.transform()),
             _ => self.transform().translation,
         }
     }
@@ -155,26 +270,80 @@ impl Object {
         let object_ref = self.object_ref.expect("can't hit-test orphan objects");
         match self.inner() {
             ObjectKind::Sphere(sphere) => {
-                sphere.pdf(object_ref, self.transform().translation, ray, clip, scene)
+                sphere.pdf(object_ref, self.transform(), ray, clip, scene)
             }
             ObjectKind::Rect(rect) => rect.pdf(object_ref, self.transform(), ray, clip, scene),
             ObjectKind::Cuboid(cuboid) => {
                 cuboid.pdf(object_ref, self.transform(), ray, clip, scene)
             }
+            ObjectKind::AccretionDisk(disk) => {
+                disk.pdf(object_ref, self.transform(), ray, clip, scene)
+            }
+            ObjectKind::Disk(disk) => disk.pdf(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Cylinder(cylinder) => {
+                cylinder.pdf(object_ref, self.transform(), ray, clip, scene)
+            }
+            ObjectKind::Cone(cone) => cone.pdf(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Torus(torus) => torus.pdf(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Plane(plane) => plane.pdf(object_ref, self.transform(), ray, clip, scene),
             _ => None,
         }
     }
 
+    /// Whether rays can hit this object at all, see `hit`.
+    pub fn is_hittable(&self) -> bool {
+        matches!(
+            self.inner(),
+            ObjectKind::Sphere(_)
+                | ObjectKind::Rect(_)
+                | ObjectKind::Cuboid(_)
+                | ObjectKind::AccretionDisk(_)
+                | ObjectKind::Wormhole(_)
+                | ObjectKind::Mesh(_)
+                | ObjectKind::Disk(_)
+                | ObjectKind::Cylinder(_)
+                | ObjectKind::Cone(_)
+                | ObjectKind::Torus(_)
+                | ObjectKind::Plane(_)
+                | ObjectKind::Sdf(_)
+                | ObjectKind::Csg(_)
+                | ObjectKind::Instance(_)
+                | ObjectKind::Heightfield(_)
+        )
+    }
+
     pub fn hit<'a>(&self, ray: &Ray, clip: &Clip, scene: &'a Scene) -> Option<Manifold<'a>> {
         let object_ref = self.object_ref.expect("can't hit-test orphan objects");
         match self.inner() {
             ObjectKind::Sphere(sphere) => {
-                sphere.hit(object_ref, self.transform().translation, ray, clip, scene)
+                sphere.hit(object_ref, self.transform(), ray, clip, scene)
             }
             ObjectKind::Rect(rect) => rect.hit(object_ref, self.transform(), ray, clip, scene),
             ObjectKind::Cuboid(cuboid) => {
                 cuboid.hit(object_ref, self.transform(), ray, clip, scene)
             }
+            ObjectKind::AccretionDisk(disk) => {
+                disk.hit(object_ref, self.transform(), ray, clip, scene)
+            }
+            ObjectKind::Wormhole(wormhole) => {
+                wormhole.hit(object_ref, self.transform(), ray, clip, scene)
+            }
+            ObjectKind::Mesh(mesh) => mesh.hit(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Disk(disk) => disk.hit(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Cylinder(cylinder) => {
+                cylinder.hit(object_ref, self.transform(), ray, clip, scene)
+            }
+            ObjectKind::Cone(cone) => cone.hit(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Torus(torus) => torus.hit(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Plane(plane) => plane.hit(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Sdf(sdf) => sdf.hit(object_ref, self.transform(), ray, clip, scene),
+            ObjectKind::Csg(csg) => csg.hit(ray, clip, scene),
+            ObjectKind::Instance(instance) => {
+                instance.hit(object_ref, self.transform(), ray, clip, scene)
+            }
+            ObjectKind::Heightfield(heightfield) => {
+                heightfield.hit(object_ref, self.transform(), ray, clip, scene)
+            }
             _ => None,
         }
     }
@@ -188,7 +357,7 @@ impl Object {
         match self.inner() {
             ObjectKind::Sphere(sphere) => sphere.hit_volumetric(
                 self.object_ref.expect("can't hit-test orphan objects"),
-                self.transform().translation,
+                self.transform(),
                 ray,
                 clip,
                 scene,
@@ -253,6 +422,24 @@ pub enum ObjectKind {
     Sphere(Sphere),
     Rect(Rect),
     Cuboid(Cuboid),
+    MassivePoint(MassivePoint),
+    RotatingMass(RotatingMass),
+    AccretionDisk(AccretionDisk),
+    Wormhole(Wormhole),
+    IsothermalSphere(IsothermalSphere),
+    NfwHalo(NfwHalo),
+    UniformDisk(UniformDisk),
+    LensScreen(LensScreen),
+    Mesh(Mesh),
+    Disk(Disk),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Plane(Plane),
+    Sdf(Sdf),
+    Csg(Csg),
+    Instance(Instance),
+    Heightfield(Heightfield),
 }
 
 impl From<()> for ObjectKind {
@@ -284,3 +471,242 @@ impl From<Cuboid> for ObjectKind {
         Self::Cuboid(cuboid)
     }
 }
+
+impl From<MassivePoint> for ObjectKind {
+    fn from(point: MassivePoint) -> Self {
+        Self::MassivePoint(point)
+    }
+}
+
+impl From<RotatingMass> for ObjectKind {
+    fn from(mass: RotatingMass) -> Self {
+        Self::RotatingMass(mass)
+    }
+}
+
+impl From<AccretionDisk> for ObjectKind {
+    fn from(disk: AccretionDisk) -> Self {
+        Self::AccretionDisk(disk)
+    }
+}
+
+impl From<Wormhole> for ObjectKind {
+    fn from(wormhole: Wormhole) -> Self {
+        Self::Wormhole(wormhole)
+    }
+}
+
+impl From<IsothermalSphere> for ObjectKind {
+    fn from(sphere: IsothermalSphere) -> Self {
+        Self::IsothermalSphere(sphere)
+    }
+}
+
+impl From<NfwHalo> for ObjectKind {
+    fn from(halo: NfwHalo) -> Self {
+        Self::NfwHalo(halo)
+    }
+}
+
+impl From<UniformDisk> for ObjectKind {
+    fn from(disk: UniformDisk) -> Self {
+        Self::UniformDisk(disk)
+    }
+}
+
+impl From<LensScreen> for ObjectKind {
+    fn from(screen: LensScreen) -> Self {
+        Self::LensScreen(screen)
+    }
+}
+
+impl From<Mesh> for ObjectKind {
+    fn from(mesh: Mesh) -> Self {
+        Self::Mesh(mesh)
+    }
+}
+
+impl From<Disk> for ObjectKind {
+    fn from(disk: Disk) -> Self {
+        Self::Disk(disk)
+    }
+}
+
+impl From<Cylinder> for ObjectKind {
+    fn from(cylinder: Cylinder) -> Self {
+        Self::Cylinder(cylinder)
+    }
+}
+
+impl From<Cone> for ObjectKind {
+    fn from(cone: Cone) -> Self {
+        Self::Cone(cone)
+    }
+}
+
+impl From<Torus> for ObjectKind {
+    fn from(torus: Torus) -> Self {
+        Self::Torus(torus)
+    }
+}
+
+impl From<Plane> for ObjectKind {
+    fn from(plane: Plane) -> Self {
+        Self::Plane(plane)
+    }
+}
+
+impl From<Sdf> for ObjectKind {
+    fn from(sdf: Sdf) -> Self {
+        Self::Sdf(sdf)
+    }
+}
+
+impl From<Csg> for ObjectKind {
+    fn from(csg: Csg) -> Self {
+        Self::Csg(csg)
+    }
+}
+
+impl From<Instance> for ObjectKind {
+    fn from(instance: Instance) -> Self {
+        Self::Instance(instance)
+    }
+}
+
+impl From<Heightfield> for ObjectKind {
+    fn from(heightfield: Heightfield) -> Self {
+        Self::Heightfield(heightfield)
+    }
+}
+
+/// Bounds in world space of the local box `(min, max)` placed by
+/// `transform`.
+fn transformed_bounds(transform: &Affine3A, (min, max): (Vec3A, Vec3A)) -> (Vec3A, Vec3A) {
+    (0..8)
+        .map(|i| {
+            let corner = Vec3A::select(BVec3A::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), max, min);
+            transform.transform_point3a(corner)
+        })
+        .fold(
+            (Vec3A::splat(f32::INFINITY), Vec3A::splat(f32::NEG_INFINITY)),
+            |(min, max), corner| (min.min(corner), max.max(corner)),
+        )
+}
+
+/// Factor by which `transform` stretches the area of a surface around a
+/// point with the world space `normal`.
+fn area_scale(transform: &Affine3A, normal: Vec3A) -> f32 {
+    let linear = transform.matrix3;
+    linear.determinant().abs() / (linear.transpose() * normal).length()
+}
+
+/// World space directions of the local `tangent` and `bitangent` of a
+/// surface, normalized or zero where they vanish.
+fn tangent_frame(transform: &Affine3A, tangent: Vec3A, bitangent: Vec3A) -> (Vec3A, Vec3A) {
+    (
+        (transform.matrix3 * tangent).normalize_or_zero(),
+        (transform.matrix3 * bitangent).normalize_or_zero(),
+    )
+}
+
+/// Solid angle density of picking the point of `manifold` out of a surface
+/// sampled uniformly over its `area` in local space.
+fn area_pdf(transform: &Affine3A, area: f32, ray: &Ray, manifold: &Manifold) -> f32 {
+    let area = area * area_scale(transform, manifold.normal);
+    let cosine = ray.direction.dot(manifold.normal).abs();
+    manifold.t * manifold.t / (area * cosine)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::scene::{Data, Material};
+
+    /// Checks that integrating the pdf of each area light over all
+    /// directions matches its `random_point`s seen from outside. Both sides
+    /// are weighted by the cosine at the light, which keeps grazing
+    /// directions from making the estimates noisy.
+    #[test]
+    fn area_lights() {
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::emissive(Default::default(), 1.0)));
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::new(1.0, 0.7, 1.3),
+            Quat::from_euler(glam::EulerRot::YXZ, 0.4, 0.9, 0.2),
+            Vec3::new(0.5, -0.2, 0.1),
+        );
+        let kinds: [ObjectKind; 6] = [
+            Disk::new(material, 1.0).into(),
+            Cylinder::new(material, 0.8, 0.6).into(),
+            Cone::new(material, 0.9, 1.4).into(),
+            Torus::new(material, 1.0, 0.3).into(),
+            Plane::new(material).into(),
+            AccretionDisk::new(material, 0.4, 1.2, 0.3, Default::default()).into(),
+        ];
+
+        let viewpoint = Vec3A::new(0.3, 3.5, 2.0);
+        let clip = Clip {
+            min: 1e-4,
+            max: 1e4,
+        };
+        let mut rng = SmallRng::seed_from_u64(1);
+        for kind in kinds {
+            let name = format!("{kind:?}");
+            let light = scene.add_object(Object::new(kind).with_transform(transform));
+            let light = scene.get_object(light);
+
+            let samples = 50000;
+            let visible = (0..samples)
+                .filter_map(|_| {
+                    let point = light.random_point(&mut rng);
+                    let ray = Ray::new(viewpoint, point - viewpoint);
+                    let manifold = light.hit(&ray, &clip, &scene)?;
+                    let seen = (manifold.t - viewpoint.distance(point)).abs() < 1e-3;
+                    seen.then(|| ray.direction.dot(manifold.normal).abs())
+                })
+                .sum::<f32>()
+                / samples as f32;
+
+            // directions are drawn from a cone around the light's bounds,
+            // or all of them for the plane
+            let (axis, cos_max) = match light.bounding_box(&scene) {
+                Some((min, max)) => {
+                    let offset = 0.5 * (min + max) - viewpoint;
+                    let radius = 0.5 * min.distance(max);
+                    let sin_max = radius / offset.length();
+                    (offset.normalize(), (1.0 - sin_max * sin_max).sqrt())
+                }
+                None => (Vec3A::Y, -1.0),
+            };
+            let (u, v) = axis.any_orthonormal_pair();
+            let cone = std::f32::consts::TAU * (1.0 - cos_max);
+
+            let samples = 100000;
+            let integral = (0..samples)
+                .filter_map(|_| {
+                    let cos_theta = rng.gen_range(cos_max..1.0);
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let phi = rng.gen_range(0.0..std::f32::consts::TAU);
+                    let direction = axis * cos_theta + (u * phi.cos() + v * phi.sin()) * sin_theta;
+
+                    let ray = Ray::new(viewpoint, direction);
+                    let manifold = light.hit(&ray, &clip, &scene)?;
+                    let pdf = light.pdf(&ray, &clip, &scene)?;
+                    Some(pdf * ray.direction.dot(manifold.normal).abs())
+                })
+                .sum::<f32>()
+                * cone
+                / samples as f32;
+
+            assert!(
+                (integral - visible).abs() < 0.03 * visible,
+                "{name}: {integral} != {visible}"
+            );
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use glam::Vec3A;
+use serde::{Deserialize, Serialize};
+
+use crate::tracer::{Lens, MassProfile};
+
+/// A Navarro-Frenk-White dark matter halo in geometrized units
+/// (`G = c = 1`), with a characteristic `density` at its `scale_radius`.
+///
+/// The halo is traced in the weak field limit, so its central potential
+/// `-4πρ_s r_s²` should stay well below one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NfwHalo {
+    pub density: f32,
+    pub scale_radius: f32,
+    pub truncation_radius: f32,
+}
+
+impl NfwHalo {
+    pub fn new(density: f32, scale_radius: f32, truncation_radius: f32) -> Self {
+        Self {
+            density,
+            scale_radius,
+            truncation_radius,
+        }
+    }
+
+    pub fn profile(&self) -> MassProfile {
+        MassProfile::Nfw {
+            density: self.density,
+            scale_radius: self.scale_radius,
+            truncation_radius: self.truncation_radius,
+        }
+    }
+
+    pub fn mass(&self) -> f32 {
+        self.profile().mass()
+    }
+
+    /// Ratio of the truncation radius to the scale radius.
+    pub fn concentration(&self) -> f32 {
+        self.truncation_radius / self.scale_radius
+    }
+
+    pub fn lens(&self, translation: Vec3A) -> Lens {
+        Lens::halo(translation, self.profile())
+    }
+}
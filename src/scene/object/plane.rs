@@ -0,0 +1,104 @@
+use std::f32;
+
+use glam::{Affine3A, Vec2, Vec3A};
+use rand::Rng;
+use rand_distr::Uniform;
+use serde::{Deserialize, Serialize};
+
+use super::{area_scale, tangent_frame};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// An infinite plane through the local origin, facing the y axis.
+///
+/// The plane has no bounding box, so it is tested against every ray. As a
+/// light it is sampled through the directions seen from one unit above its
+/// origin, which puts most samples near the origin while still covering
+/// the whole plane.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Plane {
+    pub material: DataRef,
+}
+
+impl Plane {
+    pub fn new(material: DataRef) -> Self {
+        Self { material }
+    }
+
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, transform: &Affine3A) -> Vec3A {
+        // directions uniform over the lower hemisphere have a uniform
+        // cosine, which is never zero here
+        let cos_theta = 1.0 - rng.sample::<f32, _>(Uniform::new(0.0, 1.0));
+        let r = (1.0 - cos_theta * cos_theta).sqrt() / cos_theta;
+        let phi = rng.sample(Uniform::new(0.0, f32::consts::TAU));
+        transform.transform_point3a(Vec3A::new(r * phi.cos(), 0.0, r * phi.sin()))
+    }
+
+    pub fn pdf(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &Scene,
+    ) -> Option<f32> {
+        let manifold = self.hit(object_ref, transform, ray, clip, scene)?;
+
+        // area density of `random_point` in local space, from the solid
+        // angle it covers as seen from one unit above the origin
+        let local = transform.inverse().transform_point3a(manifold.position);
+        let distance = (local.length_squared() + 1.0).sqrt();
+        let density = 1.0 / (f32::consts::TAU * distance.powi(3));
+
+        let density = density / area_scale(transform, manifold.normal);
+        let cosine = ray.direction.dot(manifold.normal).abs();
+        Some(density * manifold.t * manifold.t / cosine)
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+
+        if direction.y.abs() <= 1e-8 {
+            return None;
+        }
+        let t = -origin.y / direction.y;
+        if t < clip.min || t > clip.max {
+            return None;
+        }
+
+        let local = origin + direction * t;
+        let normal = (inverse.matrix3.transpose() * Vec3A::Y).normalize();
+        let (tangent, bitangent) = tangent_frame(transform, Vec3A::X, Vec3A::Z);
+        let (normal, face) = if ray.direction.dot(normal) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+        Some(Manifold {
+            position: ray.at(t),
+            normal,
+            uv: Vec2::new(local.x, local.z),
+            tangent,
+            bitangent,
+            bbox: (Vec3A::splat(f32::NEG_INFINITY), Vec3A::splat(f32::INFINITY)),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
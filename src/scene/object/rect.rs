@@ -1,10 +1,11 @@
 use std::f32;
 
-use glam::{Affine3A, Vec3A};
+use glam::{Affine3A, Vec2, Vec3A};
 use rand::Rng;
 use rand_distr::Uniform;
 use serde::{Deserialize, Serialize};
 
+use super::tangent_frame;
 use crate::scene::{DataRef, ObjectRef, Scene};
 use crate::tracer::{Clip, Face, Manifold, Ray};
 
@@ -131,10 +132,12 @@ impl Rect {
 
         let position = ray.at(t);
 
-        if !self.contains_point(transform.inverse().transform_point3a(position)) {
+        let local = transform.inverse().transform_point3a(position);
+        if !self.contains_point(local) {
             return None;
         }
 
+        let (tangent, bitangent) = tangent_frame(transform, self.x, -self.y);
         let (normal, face) = if p < 0.0 {
             (normal, Face::Front)
         } else {
@@ -143,6 +146,12 @@ impl Rect {
         Some(Manifold {
             position,
             normal,
+            uv: Vec2::new(
+                0.5 + 0.5 * local.dot(self.x) / self.half_width,
+                0.5 - 0.5 * local.dot(self.y) / self.half_height,
+            ),
+            tangent,
+            bitangent,
             bbox: self.bounding_box(transform),
             face,
             t,
@@ -150,6 +159,8 @@ impl Rect {
             object_ref: Some(object_ref),
             mat_ref: Some(self.material),
             vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
             scene,
         })
     }
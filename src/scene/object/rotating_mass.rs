@@ -0,0 +1,39 @@
+use glam::Affine3A;
+use serde::{Deserialize, Serialize};
+
+use crate::tracer::Lens;
+
+/// A rotating (Kerr) black hole in geometrized units (`G = c = 1`).
+///
+/// `spin` is the dimensionless spin `a / M` in `[-1; 1]`, the spin axis is
+/// the local y axis of the object.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RotatingMass {
+    pub mass: f32,
+    pub spin: f32,
+}
+
+impl RotatingMass {
+    pub fn new(mass: f32, spin: f32) -> Self {
+        Self { mass, spin }
+    }
+
+    pub fn angular_momentum(&self) -> f32 {
+        self.spin.clamp(-1.0, 1.0) * self.mass * self.mass
+    }
+
+    pub fn horizon_radius(&self) -> f32 {
+        let a = self.spin.clamp(-1.0, 1.0) * self.mass;
+        self.mass + (self.mass * self.mass - a * a).sqrt()
+    }
+
+    pub fn lens(&self, transform: &Affine3A) -> Lens {
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+        Lens::kerr(
+            translation.into(),
+            rotation,
+            self.mass,
+            self.spin.clamp(-1.0, 1.0) * self.mass,
+        )
+    }
+}
@@ -0,0 +1,465 @@
+use std::f32;
+
+use glam::{Affine3A, Mat3A, Vec3A};
+use serde::{Deserialize, Serialize};
+
+use super::{tangent_frame, transformed_bounds};
+use crate::math::{spherical_tangent, spherical_uv};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// Distance below which sphere tracing counts as a hit, in local units.
+const HIT_DISTANCE: f32 = 1e-4;
+
+/// Steps after which sphere tracing gives up on a ray.
+const MAX_STEPS: usize = 512;
+
+/// Offset of the samples the gradient is estimated from, in local units.
+const GRADIENT_OFFSET: f32 = 1e-4;
+
+/// A surface given by the zero set of a signed distance field, found by
+/// sphere tracing.
+///
+/// The field is negative inside and may only be a lower bound of the
+/// distance to the surface, which is what `Twist`, `SmoothUnion` and
+/// `Mandelbulb` give.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sdf {
+    pub material: DataRef,
+    pub expr: SdfExpr,
+}
+
+/// An expression tree making up a signed distance field in local space.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SdfExpr {
+    Sphere {
+        radius: f32,
+    },
+    Cuboid {
+        half_size: Vec3A,
+    },
+    /// A torus around the y axis.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// A capped cylinder around the y axis.
+    Cylinder {
+        radius: f32,
+        half_height: f32,
+    },
+    /// The power `power` Mandelbulb, with its axis along y.
+    Mandelbulb {
+        power: f32,
+        iterations: u32,
+    },
+    Translate {
+        offset: Vec3A,
+        expr: Box<SdfExpr>,
+    },
+    Union(Box<SdfExpr>, Box<SdfExpr>),
+    Intersect(Box<SdfExpr>, Box<SdfExpr>),
+    /// The first expression with the second one cut out of it.
+    Subtract(Box<SdfExpr>, Box<SdfExpr>),
+    /// A union blending the surfaces together where they are closer than
+    /// `smoothness`.
+    SmoothUnion {
+        smoothness: f32,
+        a: Box<SdfExpr>,
+        b: Box<SdfExpr>,
+    },
+    /// Rotates every slice across the y axis by `rate` radians per unit of
+    /// height.
+    Twist {
+        rate: f32,
+        expr: Box<SdfExpr>,
+    },
+    /// Repeats the expression every `period` along each axis, or not at all
+    /// along axes where it is zero. The expression should fit in a single
+    /// cell.
+    Repeat {
+        period: Vec3A,
+        expr: Box<SdfExpr>,
+    },
+}
+
+impl Sdf {
+    pub fn new(material: DataRef, expr: SdfExpr) -> Self {
+        Self { material, expr }
+    }
+
+    /// Bounds in world space, or `None` if the field repeats forever.
+    pub fn bounding_box(&self, transform: &Affine3A) -> Option<(Vec3A, Vec3A)> {
+        self.expr
+            .bounds()
+            .map(|bounds| transformed_bounds(transform, bounds))
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        // the direction isn't normalized, so distances stay the same in
+        // local space and a local step of `d` advances `t` by `d / speed`
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+        let speed = direction.length();
+
+        let (mut t, t_max) = match self.expr.bounds() {
+            Some((min, max)) => {
+                let t0 = (min - origin) / direction;
+                let t1 = (max - origin) / direction;
+                let near = t0.min(t1).max_element().max(clip.min);
+                let far = t0.max(t1).min_element().min(clip.max);
+                (near, far)
+            }
+            None => (clip.min, clip.max),
+        };
+
+        for _ in 0..MAX_STEPS {
+            if t > t_max {
+                return None;
+            }
+
+            // rays that were refracted start inside, so the march is on the
+            // unsigned distance
+            let distance = self.expr.distance(origin + direction * t).abs();
+            if distance < HIT_DISTANCE {
+                return Some(self.manifold(object_ref, transform, &inverse, ray, t, scene));
+            }
+            t += distance / speed;
+        }
+
+        None
+    }
+
+    fn manifold<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        inverse: &Affine3A,
+        ray: &Ray,
+        t: f32,
+        scene: &'a Scene,
+    ) -> Manifold<'a> {
+        let local = inverse.transform_point3a(ray.at(t));
+        let gradient = self.expr.gradient(local);
+        let normal = (inverse.matrix3.transpose() * gradient).normalize();
+        // the tangents of the spherical projection, which only lie along
+        // the surface where it is round
+        let tangent = spherical_tangent(local);
+        let (tangent, bitangent) = tangent_frame(transform, tangent, local.cross(tangent));
+        let (normal, face) = if ray.direction.dot(normal) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+
+        Manifold {
+            position: ray.at(t),
+            normal,
+            uv: spherical_uv(local),
+            tangent,
+            bitangent,
+            bbox: self
+                .bounding_box(transform)
+                .unwrap_or((Vec3A::splat(f32::NEG_INFINITY), Vec3A::splat(f32::INFINITY))),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        }
+    }
+}
+
+impl SdfExpr {
+    pub fn sphere(radius: f32) -> Self {
+        Self::Sphere { radius }
+    }
+
+    pub fn cuboid(half_size: Vec3A) -> Self {
+        Self::Cuboid { half_size }
+    }
+
+    pub fn torus(major_radius: f32, minor_radius: f32) -> Self {
+        Self::Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn cylinder(radius: f32, half_height: f32) -> Self {
+        Self::Cylinder {
+            radius,
+            half_height,
+        }
+    }
+
+    pub fn mandelbulb(power: f32, iterations: u32) -> Self {
+        Self::Mandelbulb { power, iterations }
+    }
+
+    pub fn translate(self, offset: Vec3A) -> Self {
+        Self::Translate {
+            offset,
+            expr: Box::new(self),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: Self) -> Self {
+        Self::Intersect(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: Self) -> Self {
+        Self::Subtract(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Self, smoothness: f32) -> Self {
+        Self::SmoothUnion {
+            smoothness,
+            a: Box::new(self),
+            b: Box::new(other),
+        }
+    }
+
+    pub fn twist(self, rate: f32) -> Self {
+        Self::Twist {
+            rate,
+            expr: Box::new(self),
+        }
+    }
+
+    pub fn repeat(self, period: Vec3A) -> Self {
+        Self::Repeat {
+            period,
+            expr: Box::new(self),
+        }
+    }
+
+    /// Signed distance from `position` to the surface, negative inside.
+    pub fn distance(&self, position: Vec3A) -> f32 {
+        match self {
+            Self::Sphere { radius } => position.length() - radius,
+            Self::Cuboid { half_size } => {
+                let q = position.abs() - *half_size;
+                q.max(Vec3A::ZERO).length() + q.max_element().min(0.0)
+            }
+            Self::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (position.x * position.x + position.z * position.z).sqrt();
+                (ring - major_radius).hypot(position.y) - minor_radius
+            }
+            Self::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let ring = (position.x * position.x + position.z * position.z).sqrt();
+                let q = Vec3A::new(ring - radius, position.y.abs() - half_height, 0.0);
+                q.max(Vec3A::ZERO).length() + q.x.max(q.y).min(0.0)
+            }
+            Self::Mandelbulb { power, iterations } => mandelbulb(position, *power, *iterations),
+            Self::Translate { offset, expr } => expr.distance(position - *offset),
+            Self::Union(a, b) => a.distance(position).min(b.distance(position)),
+            Self::Intersect(a, b) => a.distance(position).max(b.distance(position)),
+            Self::Subtract(a, b) => a.distance(position).max(-b.distance(position)),
+            Self::SmoothUnion { smoothness, a, b } => {
+                let (a, b) = (a.distance(position), b.distance(position));
+                let h = (smoothness - (a - b).abs()).max(0.0) / smoothness;
+                a.min(b) - 0.25 * h * h * smoothness
+            }
+            Self::Twist { rate, expr } => {
+                let rotation = Mat3A::from_rotation_y(-rate * position.y);
+                // the twist stretches space by up to this much around
+                // `position`, which would make the steps overshoot
+                let ring = (position.x * position.x + position.z * position.z).sqrt();
+                let stretch = (1.0 + (rate * ring).powi(2)).sqrt();
+                expr.distance(rotation * position) / stretch
+            }
+            Self::Repeat { period, expr } => {
+                let cell = (position / *period).round() * *period;
+                let cell = Vec3A::select(period.cmpgt(Vec3A::ZERO), cell, Vec3A::ZERO);
+                expr.distance(position - cell)
+            }
+        }
+    }
+
+    /// Gradient of the field at `position`, sampled at the corners of a
+    /// tetrahedron.
+    pub fn gradient(&self, position: Vec3A) -> Vec3A {
+        [
+            Vec3A::new(1.0, -1.0, -1.0),
+            Vec3A::new(-1.0, -1.0, 1.0),
+            Vec3A::new(-1.0, 1.0, -1.0),
+            Vec3A::new(1.0, 1.0, 1.0),
+        ]
+        .into_iter()
+        .fold(Vec3A::ZERO, |gradient, corner| {
+            gradient + corner * self.distance(position + corner * GRADIENT_OFFSET)
+        }) / (4.0 * GRADIENT_OFFSET)
+    }
+
+    /// Local bounds of the surface, or `None` if it repeats forever.
+    pub fn bounds(&self) -> Option<(Vec3A, Vec3A)> {
+        match self {
+            Self::Sphere { radius } => Some((Vec3A::splat(-radius), Vec3A::splat(*radius))),
+            Self::Cuboid { half_size } => Some((-*half_size, *half_size)),
+            Self::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = major_radius + minor_radius;
+                let half_size = Vec3A::new(ring, *minor_radius, ring);
+                Some((-half_size, half_size))
+            }
+            Self::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let half_size = Vec3A::new(*radius, *half_height, *radius);
+                Some((-half_size, half_size))
+            }
+            // any point further out than this escapes on the first
+            // iteration
+            Self::Mandelbulb { power, .. } => {
+                let radius = 2f32.powf(1.0 / (power - 1.0).max(1.0));
+                Some((Vec3A::splat(-radius), Vec3A::splat(radius)))
+            }
+            Self::Translate { offset, expr } => expr
+                .bounds()
+                .map(|(min, max)| (min + *offset, max + *offset)),
+            Self::Union(a, b) => {
+                let ((min_a, max_a), (min_b, max_b)) = (a.bounds()?, b.bounds()?);
+                Some((min_a.min(min_b), max_a.max(max_b)))
+            }
+            Self::Intersect(a, b) => match (a.bounds(), b.bounds()) {
+                (Some((min_a, max_a)), Some((min_b, max_b))) => {
+                    Some((min_a.max(min_b), max_a.min(max_b)))
+                }
+                (bounds, None) | (None, bounds) => bounds,
+            },
+            Self::Subtract(a, _) => a.bounds(),
+            Self::SmoothUnion { smoothness, a, b } => {
+                let ((min_a, max_a), (min_b, max_b)) = (a.bounds()?, b.bounds()?);
+                let margin = 0.25 * smoothness;
+                Some((min_a.min(min_b) - margin, max_a.max(max_b) + margin))
+            }
+            Self::Twist { expr, .. } => {
+                let (min, max) = expr.bounds()?;
+                let corner = min.abs().max(max.abs());
+                let ring = (corner.x * corner.x + corner.z * corner.z).sqrt();
+                Some((
+                    Vec3A::new(-ring, min.y, -ring),
+                    Vec3A::new(ring, max.y, ring),
+                ))
+            }
+            Self::Repeat { .. } => None,
+        }
+    }
+}
+
+/// Distance estimate of the Mandelbulb from the running derivative of its
+/// iteration.
+fn mandelbulb(position: Vec3A, power: f32, iterations: u32) -> f32 {
+    let mut z = position;
+    let mut derivative = 1.0;
+    let mut r = z.length();
+
+    for _ in 0..iterations {
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+
+        let theta = (z.y / r).acos() * power;
+        let phi = z.z.atan2(z.x) * power;
+        derivative = power * r.powf(power - 1.0) * derivative + 1.0;
+
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        z = r.powf(power) * Vec3A::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi)
+            + position;
+        r = z.length();
+    }
+
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / derivative
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+
+    use super::*;
+    use crate::scene::{Data, Material, Object};
+
+    #[test]
+    fn sphere_tracing() {
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::diffuse(Default::default(), 1.0)));
+
+        // a cube with a ball carved out of its front, blended with a
+        // sphere above it
+        let expr = SdfExpr::cuboid(Vec3A::ONE)
+            .subtract(SdfExpr::sphere(0.5).translate(Vec3A::Z))
+            .smooth_union(
+                SdfExpr::sphere(0.5).translate(Vec3A::new(0.0, 3.0, 0.0)),
+                0.2,
+            );
+        let expr: SdfExpr = serde_json::from_str(&serde_json::to_string(&expr).unwrap()).unwrap();
+
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::new(2.0, 1.0, 1.0),
+            Quat::from_rotation_y(f32::consts::FRAC_PI_2),
+            Vec3::new(0.0, 0.0, -10.0),
+        );
+        let object =
+            scene.add_object(Object::new(Sdf::new(material, expr)).with_transform(transform));
+        let object = scene.get_object(object);
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+
+        // the local -x face, stretched twice and turned towards the camera
+        let ray = Ray::new(Vec3A::new(0.5, 0.0, 0.0), Vec3A::NEG_Z);
+        let manifold = object.hit(&ray, &clip, &scene).unwrap();
+        assert!((manifold.t - 8.0).abs() < 1e-3);
+        assert!(manifold.normal.abs_diff_eq(Vec3A::Z, 1e-3));
+
+        // the local +z face has the ball carved out of it
+        let ray = Ray::new(Vec3A::new(5.0, 0.0, -10.0), Vec3A::NEG_X);
+        let manifold = object.hit(&ray, &clip, &scene).unwrap();
+        assert!((manifold.t - 4.5).abs() < 1e-3);
+        assert!(manifold.normal.abs_diff_eq(Vec3A::X, 1e-3));
+        assert_eq!(manifold.face, Face::Front);
+
+        // from the inside, the cube is left through its bottom
+        let ray = Ray::new(Vec3A::new(0.0, 0.0, -10.0), Vec3A::NEG_Y);
+        let manifold = object.hit(&ray, &clip, &scene).unwrap();
+        assert!((manifold.t - 1.0).abs() < 1e-3);
+        assert_eq!(manifold.face, Face::Back);
+
+        let (min, max) = object.bounding_box(&scene).unwrap();
+        assert!(max.y > 3.5 && min.y < -1.0 && max.z > -8.0 && min.z < -12.0);
+
+        let ray = Ray::new(Vec3A::new(5.0, 0.0, 0.0), Vec3A::NEG_Z);
+        assert!(object.hit(&ray, &clip, &scene).is_none());
+    }
+}
@@ -1,10 +1,12 @@
 use std::f32;
 
-use glam::Vec3A;
+use glam::{Affine3A, Vec2, Vec3A};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+use super::tangent_frame;
 use crate::math::distr::UnitSphere;
+use crate::math::{spherical_tangent, spherical_uv};
 use crate::scene::{DataRef, ObjectRef, Scene};
 use crate::tracer::{Clip, Face, Manifold, Ray};
 
@@ -32,26 +34,45 @@ impl Sphere {
         }
     }
 
-    pub fn bounding_box(&self, translation: Vec3A) -> (Vec3A, Vec3A) {
-        let half_size = Vec3A::splat(self.radius);
-        (translation - half_size, translation + half_size)
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        // the extent along each axis is the radius times the length of the
+        // matching row of the linear part
+        let rows = transform.matrix3.transpose();
+        let half_size = Vec3A::new(
+            rows.x_axis.length(),
+            rows.y_axis.length(),
+            rows.z_axis.length(),
+        ) * self.radius;
+        let center = transform.translation;
+        (center - half_size, center + half_size)
     }
 
-    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, translation: Vec3A) -> Vec3A {
-        translation + rng.sample::<Vec3A, _>(UnitSphere) * self.radius
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, transform: &Affine3A) -> Vec3A {
+        transform.transform_point3a(rng.sample::<Vec3A, _>(UnitSphere) * self.radius)
+    }
+
+    /// Where `position` falls in the unit cube spanned by the sphere's
+    /// bounds in its own space, which volumes are mapped onto.
+    pub fn volume_coord(&self, transform: &Affine3A, position: Vec3A) -> Vec3A {
+        let local = transform.inverse().transform_point3a(position);
+        (local / self.radius + 1.0) * 0.5
     }
 
     pub fn pdf(
         &self,
         object_ref: ObjectRef,
-        translation: Vec3A,
+        transform: &Affine3A,
         ray: &Ray,
         clip: &Clip,
         scene: &Scene,
     ) -> Option<f32> {
-        if let Some(manifold) = self.hit(object_ref, translation, ray, clip, scene) {
+        if let Some(manifold) = self.hit(object_ref, transform, ray, clip, scene) {
+            // an ellipsoid's shadow is that of the sphere, stretched by the
+            // determinant and squashed along the direction of view
             let r = self.radius;
-            let shadow = f32::consts::PI * r * r;
+            let linear = transform.matrix3;
+            let stretch = linear.determinant().abs() * (linear.inverse() * ray.direction).length();
+            let shadow = f32::consts::PI * r * r * stretch;
             let dist_sqr = manifold.t * manifold.t;
 
             Some(dist_sqr / shadow)
@@ -64,20 +85,25 @@ impl Sphere {
         &self,
         scene: &'a Scene,
         object_ref: ObjectRef,
-        translation: Vec3A,
+        transform: &Affine3A,
         ray: Ray,
         t: f32,
     ) -> Manifold<'a> {
         Manifold {
             position: ray.at(t),
             normal: Vec3A::ZERO,
-            bbox: self.bounding_box(translation),
+            uv: Vec2::ZERO,
+            tangent: Vec3A::ZERO,
+            bitangent: Vec3A::ZERO,
+            bbox: self.bounding_box(transform),
             face: Face::Volume,
             t,
             ray,
             object_ref: Some(object_ref),
             mat_ref: Some(self.material),
             vol_ref: self.volume,
+            shift: 1.0,
+            wavelength: None,
             scene,
         }
     }
@@ -86,9 +112,10 @@ impl Sphere {
         &self,
         scene: &'a Scene,
         object_ref: ObjectRef,
-        translation: Vec3A,
+        transform: &Affine3A,
         ray: Ray,
         t: f32,
+        local: Vec3A,
     ) -> Manifold<'a> {
         let (front_face, back_face) = if self.volume.is_some() {
             (Face::VolumeFront, Face::VolumeBack)
@@ -96,8 +123,11 @@ impl Sphere {
             (Face::Front, Face::Back)
         };
 
-        let position = ray.at(t);
-        let normal = (position - translation) / self.radius;
+        // normals transform with the inverse transpose to stay
+        // perpendicular to the stretched surface
+        let normal = (transform.matrix3.inverse().transpose() * local).normalize();
+        let tangent = spherical_tangent(local);
+        let (tangent, bitangent) = tangent_frame(transform, tangent, local.cross(tangent));
 
         let (normal, face) = if ray.direction.dot(normal) < 0.0 {
             (normal, front_face)
@@ -105,15 +135,20 @@ impl Sphere {
             (-normal, back_face)
         };
         Manifold {
-            position,
+            position: ray.at(t),
             normal,
-            bbox: self.bounding_box(translation),
+            uv: spherical_uv(local),
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
             face,
             t,
             ray,
             object_ref: Some(object_ref),
             mat_ref: Some(self.material),
             vol_ref: self.volume,
+            shift: 1.0,
+            wavelength: None,
             scene,
         }
     }
@@ -121,47 +156,138 @@ impl Sphere {
     pub fn hit<'a>(
         &self,
         object_ref: ObjectRef,
-        translation: Vec3A,
+        transform: &Affine3A,
         ray: &Ray,
         clip: &Clip,
         scene: &'a Scene,
     ) -> Option<Manifold<'a>> {
-        let oc = ray.origin - translation;
-        let half_b = oc.dot(ray.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
+        // the direction isn't normalized, so distances stay the same in
+        // local space
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+
+        let a = direction.length_squared();
+        let half_b = origin.dot(direction);
+        let c = origin.length_squared() - self.radius * self.radius;
 
-        let discriminant = half_b * half_b - c;
+        let discriminant = half_b * half_b - a * c;
         if discriminant.is_sign_negative() {
             return None;
         }
 
         let sqrtd = discriminant.sqrt();
-        let mut t = -half_b - sqrtd;
+        let mut t = (-half_b - sqrtd) / a;
         if t < clip.min || t > clip.max {
-            t = -half_b + sqrtd;
+            t = (-half_b + sqrtd) / a;
             if t < clip.min || t > clip.max {
                 return None;
             }
         }
 
-        Some(self.generate_surface_manifold(scene, object_ref, translation, *ray, t))
+        let local = origin + direction * t;
+        Some(self.generate_surface_manifold(scene, object_ref, transform, *ray, t, local))
     }
 
     pub fn hit_volumetric<'a>(
         &self,
         object_ref: ObjectRef,
-        translation: Vec3A,
+        transform: &Affine3A,
         ray: &Ray,
         clip: &Clip,
         scene: &'a Scene,
     ) -> Option<Manifold<'a>> {
         let t = clip.max;
-        let dist_sqr = ray.at(t).distance_squared(translation);
-        let r_sqr = self.radius * self.radius;
-        if dist_sqr <= r_sqr {
-            return Some(self.generate_volume_manifold(scene, object_ref, translation, *ray, t));
+        let local = transform.inverse().transform_point3a(ray.at(t));
+        if local.length_squared() <= self.radius * self.radius {
+            return Some(self.generate_volume_manifold(scene, object_ref, transform, *ray, t));
         }
 
-        self.hit(object_ref, translation, ray, clip, scene)
+        self.hit(object_ref, transform, ray, clip, scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::scene::{Data, Material, Object};
+
+    #[test]
+    fn ellipsoid() {
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::flat(Default::default())));
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::new(2.0, 1.0, 0.5),
+            Quat::from_rotation_z(f32::consts::FRAC_PI_2),
+            Vec3::new(1.0, 0.0, 0.0),
+        );
+        let object_ref =
+            scene.add_object(Object::new(Sphere::new(material, 1.0)).with_transform(transform));
+        let sphere = Sphere::new(material, 1.0);
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+
+        // the long axis now points along y
+        let (min, max) = sphere.bounding_box(&transform);
+        assert!(min.abs_diff_eq(Vec3A::new(0.0, -2.0, -0.5), 1e-5));
+        assert!(max.abs_diff_eq(Vec3A::new(2.0, 2.0, 0.5), 1e-5));
+
+        let ray = Ray::new(Vec3A::new(1.0, 5.0, 0.0), Vec3A::NEG_Y);
+        let manifold = sphere
+            .hit(object_ref, &transform, &ray, &clip, &scene)
+            .unwrap();
+        assert!((manifold.t - 3.0).abs() < 1e-5);
+        assert!(manifold.normal.abs_diff_eq(Vec3A::Y, 1e-5));
+
+        // normals follow the gradient of the implicit surface
+        let ray = Ray::new(Vec3A::new(1.5, 1.0, 5.0), Vec3A::NEG_Z);
+        let manifold = sphere
+            .hit(object_ref, &transform, &ray, &clip, &scene)
+            .unwrap();
+        let p = manifold.position - Vec3A::new(1.0, 0.0, 0.0);
+        let gradient = (p / Vec3A::new(1.0, 4.0, 0.25)).normalize();
+        assert!(manifold.normal.abs_diff_eq(gradient, 1e-4));
+        assert!(sphere
+            .hit(
+                object_ref,
+                &transform,
+                &Ray::new(Vec3A::new(2.5, 0.0, 5.0), Vec3A::NEG_Z),
+                &clip,
+                &scene
+            )
+            .is_none());
+
+        // the shadow used by the pdf matches the area hit by parallel rays
+        let direction = Vec3A::new(1.0, 2.0, -3.0).normalize();
+        let (u, v) = direction.any_orthonormal_pair();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let samples = 20000;
+        let hits = (0..samples)
+            .filter(|_| {
+                let (x, y): (f32, f32) = (rng.gen_range(-3.0..3.0), rng.gen_range(-3.0..3.0));
+                let origin = Vec3A::new(1.0, 0.0, 0.0) + u * x + v * y - direction * 10.0;
+                let ray = Ray::new(origin, direction);
+                sphere
+                    .hit(object_ref, &transform, &ray, &clip, &scene)
+                    .is_some()
+            })
+            .count();
+        let area = 36.0 * hits as f32 / samples as f32;
+
+        let ray = Ray::new(Vec3A::new(1.0, 0.0, 0.0) - direction * 10.0, direction);
+        let manifold = sphere
+            .hit(object_ref, &transform, &ray, &clip, &scene)
+            .unwrap();
+        let pdf = sphere
+            .pdf(object_ref, &transform, &ray, &clip, &scene)
+            .unwrap();
+        let shadow = manifold.t * manifold.t / pdf;
+        assert!((shadow - area).abs() < 0.03 * area, "{shadow} != {area}");
     }
 }
@@ -0,0 +1,231 @@
+use std::f32;
+
+use glam::{Affine3A, Vec2, Vec3A};
+use rand::Rng;
+use rand_distr::Uniform;
+use serde::{Deserialize, Serialize};
+
+use super::{area_pdf, tangent_frame, transformed_bounds};
+use crate::math::{spherical_tangent, spherical_uv};
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// The highest degree of polynomial `real_roots` can solve.
+const MAX_DEGREE: usize = 4;
+
+/// A ring in the local xz plane, made of a tube of `minor_radius` swept
+/// around the y axis at `major_radius`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Torus {
+    pub material: DataRef,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+}
+
+impl Torus {
+    pub fn new(material: DataRef, major_radius: f32, minor_radius: f32) -> Self {
+        Self {
+            material,
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn bounding_box(&self, transform: &Affine3A) -> (Vec3A, Vec3A) {
+        let outer = self.major_radius + self.minor_radius;
+        let half_size = Vec3A::new(outer, self.minor_radius, outer);
+        transformed_bounds(transform, (-half_size, half_size))
+    }
+
+    pub fn area(&self) -> f32 {
+        f32::consts::TAU * f32::consts::TAU * self.major_radius * self.minor_radius
+    }
+
+    pub fn random_point<R: Rng + ?Sized>(&self, rng: &mut R, transform: &Affine3A) -> Vec3A {
+        let (major, minor) = (self.major_radius, self.minor_radius);
+
+        // the outside of the ring has more area than the inside, so angles
+        // around the tube are picked in proportion to their distance from
+        // the axis
+        let angles = Uniform::new(0.0, f32::consts::TAU);
+        let theta = loop {
+            let theta: f32 = rng.sample(angles);
+            let accept = (major + minor * theta.cos()) / (major + minor);
+            if rng.sample::<f32, _>(Uniform::new(0.0, 1.0)) < accept {
+                break theta;
+            }
+        };
+        let phi = rng.sample(angles);
+
+        let distance = major + minor * theta.cos();
+        let local = Vec3A::new(
+            distance * phi.cos(),
+            minor * theta.sin(),
+            distance * phi.sin(),
+        );
+        transform.transform_point3a(local)
+    }
+
+    pub fn pdf(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &Scene,
+    ) -> Option<f32> {
+        self.hit(object_ref, transform, ray, clip, scene)
+            .map(|manifold| area_pdf(transform, self.area(), ray, &manifold))
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        let inverse = transform.inverse();
+        let origin = inverse.transform_point3a(ray.origin);
+        let direction = inverse.transform_vector3a(ray.direction);
+
+        // the quartic is solved in double precision with a normalized
+        // direction, and only across the torus' bounding sphere
+        let scale = direction.length() as f64;
+        let o = origin.as_dvec3();
+        let d = direction.as_dvec3() / scale;
+        let major_sqr = (self.major_radius as f64).powi(2);
+        let minor_sqr = (self.minor_radius as f64).powi(2);
+
+        let outer = self.major_radius as f64 + self.minor_radius as f64;
+        let f = o.dot(d);
+        let discriminant = f * f - o.length_squared() + outer * outer;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let lo = (-f - discriminant.sqrt()).max(clip.min as f64 * scale);
+        let hi = (-f + discriminant.sqrt()).min(clip.max as f64 * scale);
+        if lo > hi {
+            return None;
+        }
+
+        // (|p|² - R² - r²)² = 4 R² (r² - y²) along the ray
+        let e = o.length_squared() - major_sqr - minor_sqr;
+        let coefficients = [
+            1.0,
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + 4.0 * major_sqr * d.y * d.y,
+            4.0 * f * e + 8.0 * major_sqr * o.y * d.y,
+            e * e - 4.0 * major_sqr * (minor_sqr - o.y * o.y),
+        ];
+        let (roots, count) = real_roots(&coefficients, lo, hi);
+        let s = *roots[..count].first()?;
+        let t = (s / scale) as f32;
+
+        let local = origin + direction * t;
+        let ring = Vec3A::new(local.x, 0.0, local.z).normalize_or_zero() * self.major_radius;
+        let normal = (inverse.matrix3.transpose() * (local - ring)).normalize();
+        let tangent = spherical_tangent(local);
+        let (tangent, bitangent) = tangent_frame(transform, tangent, tangent.cross(local - ring));
+
+        let (normal, face) = if ray.direction.dot(normal) < 0.0 {
+            (normal, Face::Front)
+        } else {
+            (-normal, Face::Back)
+        };
+        Some(Manifold {
+            position: ray.at(t),
+            normal,
+            uv: Vec2::new(
+                spherical_uv(local).x,
+                0.5 + (local - ring)
+                    .y
+                    .atan2((local - ring).dot(ring) / self.major_radius)
+                    / f32::consts::TAU,
+            ),
+            tangent,
+            bitangent,
+            bbox: self.bounding_box(transform),
+            face,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: Some(self.material),
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+}
+
+/// Real roots in `[lo, hi]` of the polynomial with `coefficients`, highest
+/// degree first, in ascending order along with their count.
+///
+/// The range is split at the roots of the derivative, found the same way,
+/// which leaves intervals where the polynomial is monotonic and has at most
+/// one root to bisect for. Roots that only touch zero are missed, which
+/// amounts to grazing the surface.
+fn real_roots(coefficients: &[f64], lo: f64, hi: f64) -> ([f64; MAX_DEGREE], usize) {
+    let degree = coefficients.len() - 1;
+    debug_assert!((1..=MAX_DEGREE).contains(&degree));
+
+    let mut roots = [0.0; MAX_DEGREE];
+    if degree == 1 {
+        let root = -coefficients[1] / coefficients[0];
+        if (lo..=hi).contains(&root) {
+            roots[0] = root;
+            return (roots, 1);
+        }
+        return (roots, 0);
+    }
+
+    let mut derivative = [0.0; MAX_DEGREE];
+    for (i, coefficient) in coefficients[..degree].iter().enumerate() {
+        derivative[i] = coefficient * (degree - i) as f64;
+    }
+    let (critical, critical_count) = real_roots(&derivative[..degree], lo, hi);
+
+    let mut count = 0;
+    let mut start = lo;
+    for end in critical[..critical_count].iter().copied().chain([hi]) {
+        if let Some(root) = bisect(coefficients, start, end) {
+            roots[count] = root;
+            count += 1;
+        }
+        start = end;
+    }
+
+    (roots, count)
+}
+
+fn bisect(coefficients: &[f64], mut lo: f64, mut hi: f64) -> Option<f64> {
+    let mut lo_sign = evaluate(coefficients, lo) < 0.0;
+    let hi_value = evaluate(coefficients, hi);
+    if hi_value != 0.0 && lo_sign == (hi_value < 0.0) {
+        return None;
+    }
+
+    for _ in 0..64 {
+        let mid = 0.5 * (lo + hi);
+        if mid <= lo || mid >= hi {
+            break;
+        }
+        let mid_sign = evaluate(coefficients, mid) < 0.0;
+        if mid_sign == lo_sign {
+            lo = mid;
+            lo_sign = mid_sign;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Some(0.5 * (lo + hi))
+}
+
+fn evaluate(coefficients: &[f64], x: f64) -> f64 {
+    coefficients
+        .iter()
+        .fold(0.0, |value, coefficient| value * x + coefficient)
+}
@@ -0,0 +1,30 @@
+use std::f32;
+
+use glam::Affine3A;
+use serde::{Deserialize, Serialize};
+
+use crate::tracer::Screen;
+
+/// A thin disk of uniform surface density in the local xz plane, in
+/// geometrized units (`G = c = 1`). It bends the rays crossing its plane as
+/// a thin lens.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct UniformDisk {
+    pub mass: f32,
+    pub radius: f32,
+}
+
+impl UniformDisk {
+    pub fn new(mass: f32, radius: f32) -> Self {
+        Self { mass, radius }
+    }
+
+    pub fn surface_density(&self) -> f32 {
+        self.mass / (f32::consts::PI * self.radius * self.radius)
+    }
+
+    pub fn screen(&self, transform: &Affine3A) -> Screen {
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+        Screen::uniform_disk(translation.into(), rotation, self.mass, self.radius)
+    }
+}
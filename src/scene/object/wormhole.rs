@@ -0,0 +1,302 @@
+use glam::{Affine3A, Quat, Vec3A};
+use serde::{Deserialize, Serialize};
+
+use crate::math::spherical_uv;
+use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::tracer::{Clip, Face, Manifold, Ray};
+
+/// Step size inside the mouths, relative to the current areal radius.
+const PASSAGE_STEP: f32 = 0.02;
+
+/// A traversable Ellis (Morris-Thorne) wormhole connecting the mouth at this
+/// object to a second mouth at the `exit` object.
+///
+/// Inside the mouth spheres space follows the wormhole metric
+/// `dl² + r(l)² dΩ²`, with `r(l)² = (|l| - length / 2)₊² + throat_radius²`,
+/// outside of them it is flat. Nothing should be placed inside the mouths,
+/// objects there are only seen through the throat. Rays leaving through the
+/// exit see `environment`, or the scene's root material when it is `None`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Wormhole {
+    pub exit: ObjectRef,
+    pub throat_radius: f32,
+    pub length: f32,
+    pub mouth_radius: f32,
+    pub environment: Option<DataRef>,
+}
+
+impl Wormhole {
+    pub fn new(exit: ObjectRef, throat_radius: f32, length: f32, mouth_radius: f32) -> Self {
+        Self {
+            exit,
+            throat_radius,
+            length,
+            mouth_radius,
+            environment: None,
+        }
+    }
+
+    pub fn with_environment(self, environment: DataRef) -> Self {
+        Self {
+            environment: Some(environment),
+            ..self
+        }
+    }
+
+    fn mouths(&self, transform: &Affine3A, scene: &Scene) -> [(Quat, Vec3A); 2] {
+        let exit = scene.get_object(self.exit).transform();
+        [transform, exit].map(|transform| {
+            let (_, rotation, translation) = transform.to_scale_rotation_translation();
+            (rotation, translation.into())
+        })
+    }
+
+    pub fn hit<'a>(
+        &self,
+        object_ref: ObjectRef,
+        transform: &Affine3A,
+        ray: &Ray,
+        clip: &Clip,
+        scene: &'a Scene,
+    ) -> Option<Manifold<'a>> {
+        let r = self.mouth_radius;
+        let mut result: Option<(f32, Vec3A)> = None;
+
+        for (_, center) in self.mouths(transform, scene) {
+            let oc = ray.origin - center;
+            let a = ray.direction.length_squared();
+            let half_b = oc.dot(ray.direction);
+            let c = oc.length_squared() - r * r;
+
+            // rays can only enter a mouth, they leave it through the passage,
+            // so rays starting inside or on it are ignored
+            let discriminant = half_b * half_b - a * c;
+            if c < 1e-3 * r * r || discriminant.is_sign_negative() {
+                continue;
+            }
+
+            let t = (-half_b - discriminant.sqrt()) / a;
+            if t >= clip.min && t <= clip.max && result.is_none_or(|(best, _)| t < best) {
+                result = Some((t, center));
+            }
+        }
+
+        let (t, center) = result?;
+        let position = ray.at(t);
+
+        Some(Manifold {
+            position,
+            normal: (position - center) / r,
+            uv: spherical_uv(position - center),
+            tangent: Vec3A::ZERO,
+            bitangent: Vec3A::ZERO,
+            bbox: (center - Vec3A::splat(r), center + Vec3A::splat(r)),
+            face: Face::Front,
+            t,
+            ray: *ray,
+            object_ref: Some(object_ref),
+            mat_ref: None,
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene,
+        })
+    }
+
+    /// Starts a passage for a ray entering either mouth at `position`.
+    pub fn enter(
+        &self,
+        transform: &Affine3A,
+        scene: &Scene,
+        position: Vec3A,
+        direction: Vec3A,
+    ) -> Passage {
+        let mouths = self.mouths(transform, scene);
+        let side =
+            if position.distance_squared(mouths[0].1) <= position.distance_squared(mouths[1].1) {
+                0
+            } else {
+                1
+            };
+        let sign = if side == 0 { 1.0 } else { -1.0 };
+
+        let (rotation, center) = mouths[side];
+        let local = rotation.inverse() * (position - center);
+        let direction = rotation.inverse() * direction.normalize();
+
+        let normal = local.normalize();
+        let radial = direction.dot(normal);
+        let tangent = direction - normal * radial;
+        let tangent_length = tangent.length();
+        let tangent = if tangent_length > 1e-6 {
+            tangent / tangent_length
+        } else {
+            normal.any_orthonormal_vector()
+        };
+
+        let throat = self.throat_radius;
+        let mouth = self.mouth_radius.max(throat);
+        let half_length = 0.5 * self.length;
+
+        Passage {
+            mouths,
+            throat_radius: throat,
+            half_length,
+            mouth_radius: mouth,
+            normal,
+            tangent,
+            coordinate: sign * (half_length + (mouth * mouth - throat * throat).sqrt()),
+            velocity: sign * radial,
+            angle: 0.0,
+            angular_momentum: mouth * tangent_length,
+        }
+    }
+}
+
+/// A ray travelling between the mouths of a `Wormhole`.
+///
+/// Wormholes are spherically symmetric, so the ray stays in the plane spanned
+/// by `normal` and `tangent` and is tracked in proper radial distance `l`,
+/// positive on the side of the entrance, and the angle within that plane.
+#[derive(Debug, Clone, Copy)]
+pub struct Passage {
+    mouths: [(Quat, Vec3A); 2],
+    throat_radius: f32,
+    half_length: f32,
+    mouth_radius: f32,
+    normal: Vec3A,
+    tangent: Vec3A,
+    coordinate: f32,
+    velocity: f32,
+    angle: f32,
+    angular_momentum: f32,
+}
+
+impl Passage {
+    fn radius(&self, coordinate: f32) -> f32 {
+        let l = (coordinate.abs() - self.half_length).max(0.0);
+        (l * l + self.throat_radius * self.throat_radius).sqrt()
+    }
+
+    fn acceleration(&self, coordinate: f32) -> (f32, f32) {
+        let l = (coordinate.abs() - self.half_length).max(0.0);
+        let r = self.radius(coordinate);
+        let dr = coordinate.signum() * l / r;
+        let momentum = self.angular_momentum;
+        (momentum * momentum * dr / (r * r * r), momentum / (r * r))
+    }
+
+    /// Index of the mouth on whose side the ray currently is.
+    pub fn side(&self) -> usize {
+        if self.coordinate >= 0.0 {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn local(&self) -> (Vec3A, Vec3A) {
+        let (sin, cos) = self.angle.sin_cos();
+        (
+            self.normal * cos + self.tangent * sin,
+            self.tangent * cos - self.normal * sin,
+        )
+    }
+
+    /// Position of the ray, embedded in flat space around the current mouth.
+    pub fn position(&self) -> Vec3A {
+        let (rotation, center) = self.mouths[self.side()];
+        let (normal, _) = self.local();
+        center + rotation * normal * self.radius(self.coordinate)
+    }
+
+    /// Advances the ray with RK4 and returns the proper distance travelled.
+    pub fn step(&mut self) -> f32 {
+        let h = PASSAGE_STEP * self.radius(self.coordinate);
+
+        let derivative = |l: f32, v: f32| {
+            let (a, w) = self.acceleration(l);
+            (v, a, w)
+        };
+
+        let (l, v) = (self.coordinate, self.velocity);
+        let k1 = derivative(l, v);
+        let k2 = derivative(l + 0.5 * h * k1.0, v + 0.5 * h * k1.1);
+        let k3 = derivative(l + 0.5 * h * k2.0, v + 0.5 * h * k2.1);
+        let k4 = derivative(l + h * k3.0, v + h * k3.1);
+
+        self.coordinate += h / 6.0 * (k1.0 + 2.0 * k2.0 + 2.0 * k3.0 + k4.0);
+        self.velocity += h / 6.0 * (k1.1 + 2.0 * k2.1 + 2.0 * k3.1 + k4.1);
+        self.angle += h / 6.0 * (k1.2 + 2.0 * k2.2 + 2.0 * k3.2 + k4.2);
+
+        h
+    }
+
+    /// The ray leaving the wormhole, once it has left either mouth.
+    pub fn exit(&self) -> Option<Ray> {
+        let r = self.radius(self.coordinate);
+        let outward = self.coordinate.signum() * self.velocity;
+        if r < self.mouth_radius || outward <= 0.0 {
+            return None;
+        }
+
+        let (rotation, _) = self.mouths[self.side()];
+        let (normal, tangent) = self.local();
+        let direction = normal * outward + tangent * (self.angular_momentum / r);
+        Some(Ray::new(self.position(), rotation * direction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::scene::{Data, Material, Object, Sphere};
+
+    #[test]
+    fn passage() {
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::diffuse(Default::default(), 1.0)));
+        let exit = scene.add_object(
+            Object::new(Sphere::new(material, 0.1))
+                .with_rotation(Vec3A::new(50.0, 0.0, 0.0), Quat::from_rotation_z(FRAC_PI_2)),
+        );
+        let wormhole = Wormhole::new(exit, 0.5, 1.0, 2.0);
+        let entrance = scene.add_object(Object::new(wormhole));
+        let object = scene.get_object(entrance);
+
+        let clip = Clip {
+            min: 0.0,
+            max: 100.0,
+        };
+        // the direction of rays passed down by instances isn't normalized
+        let ray = Ray {
+            origin: Vec3A::new(-20.0, 0.0, 0.0),
+            direction: Vec3A::new(3.0, 0.0, 0.0),
+        };
+        let hit = wormhole
+            .hit(entrance, object.transform(), &ray, &clip, &scene)
+            .expect("expected to hit the entrance");
+        assert!((hit.t - 6.0).abs() < 1e-4);
+        assert!(hit.position.abs_diff_eq(Vec3A::new(-2.0, 0.0, 0.0), 1e-4));
+
+        let mut passage = wormhole.enter(object.transform(), &scene, hit.position, ray.direction);
+        assert_eq!(passage.side(), 0);
+        let ray = loop {
+            if let Some(ray) = passage.exit() {
+                break ray;
+            }
+            passage.step();
+        };
+
+        // a radial ray goes straight through the throat and leaves the exit
+        // mouth on the mirrored side, turned with it
+        assert_eq!(passage.side(), 1);
+        let outward = Quat::from_rotation_z(FRAC_PI_2) * Vec3A::NEG_X;
+        assert!(ray.direction.normalize().abs_diff_eq(outward, 1e-3));
+        let offset = ray.origin - Vec3A::new(50.0, 0.0, 0.0);
+        assert!(offset.normalize().abs_diff_eq(outward, 1e-3));
+        assert!(offset.length() >= 2.0 && offset.length() < 2.1);
+    }
+}
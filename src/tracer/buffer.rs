@@ -99,13 +99,13 @@ impl Buffer {
         self.clear();
     }
 
-    pub fn chunks(&mut self, chunks_x: usize, chunks_y: usize) -> Chunks {
-        let chunk_width = if self.width() % chunks_x == 0 {
+    pub fn chunks(&mut self, chunks_x: usize, chunks_y: usize) -> Chunks<'_> {
+        let chunk_width = if self.width().is_multiple_of(chunks_x) {
             self.width() / chunks_x
         } else {
             self.width() / chunks_x + 1
         };
-        let chunk_height = if self.height() % chunks_y == 0 {
+        let chunk_height = if self.height().is_multiple_of(chunks_y) {
             self.height() / chunks_y
         } else {
             self.height() / chunks_y + 1
@@ -188,7 +188,7 @@ impl Deref for Buffer {
 
 impl AsRef<Rgba32FImage> for Buffer {
     fn as_ref(&self) -> &Rgba32FImage {
-        &**self
+        self
     }
 }
 
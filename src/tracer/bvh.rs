@@ -0,0 +1,694 @@
+use glam::Vec3A;
+
+use super::{Clip, Manifold, Ray};
+use crate::scene::{ObjectFlags, ObjectRef, Scene};
+
+/// Number of buckets the centroids are sorted into to evaluate the surface
+/// area heuristic.
+const BUCKETS: usize = 12;
+/// Largest number of objects kept in a leaf.
+const MAX_LEAF_SIZE: usize = 4;
+/// Cost of traversing a node relative to hit-testing an object.
+const TRAVERSAL_COST: f32 = 0.5;
+/// Padding added to every bounding box, so flat objects like rects still
+/// have a volume to hit.
+const PADDING: f32 = 1e-4;
+/// How far the neighbourhood cached for segments reaches around a segment,
+/// relative to its length.
+const REGION_SCALE: f32 = 32.0;
+/// Largest number of objects cached for segments, beyond which every
+/// segment traverses the hierarchy on its own.
+const MAX_CANDIDATES: usize = 32;
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3A,
+    pub max: Vec3A,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3A::splat(f32::INFINITY),
+        max: Vec3A::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: Vec3A, max: Vec3A) -> Self {
+        Self { min, max }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn grow(self, margin: f32) -> Self {
+        Self::new(self.min - margin, self.max + margin)
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        self.min.cmple(other.min).all() && self.max.cmpge(other.max).all()
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.min.cmple(other.max).all() && self.max.cmpge(other.min).all()
+    }
+
+    pub fn centroid(&self) -> Vec3A {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = (self.max - self.min).max(Vec3A::ZERO);
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// Distance along the ray at which it enters the box within `clip`, if
+    /// it does. `inverse` is the reciprocal of the ray's direction.
+    pub fn intersect(&self, origin: Vec3A, inverse: Vec3A, clip: &Clip) -> Option<f32> {
+        let t0 = (self.min - origin) * inverse;
+        let t1 = (self.max - origin) * inverse;
+        let near = t0.min(t1).max_element().max(clip.min);
+        let far = t0.max(t1).min_element().min(clip.max);
+        (near <= far).then_some(near)
+    }
+}
+
+impl From<(Vec3A, Vec3A)> for Aabb {
+    fn from((min, max): (Vec3A, Vec3A)) -> Self {
+        Self::new(min, max)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    /// `count` objects starting at `start`.
+    Leaf {
+        bbox: Aabb,
+        start: usize,
+        count: usize,
+    },
+    /// The first child directly follows its parent, the second one is at
+    /// `second`.
+    Interior {
+        bbox: Aabb,
+        second: usize,
+        axis: usize,
+    },
+}
+
+impl Node {
+    fn bbox(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bbox, .. } | Node::Interior { bbox, .. } => bbox,
+        }
+    }
+}
+
+/// The result of hit-testing one of the primitives in a `Bvh`.
+pub trait Intersection {
+    /// Distance along the ray to the hit.
+    fn distance(&self) -> f32;
+}
+
+impl<'a> Intersection for Manifold<'a> {
+    fn distance(&self) -> f32 {
+        self.t
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Primitive<T> {
+    id: T,
+    bbox: Aabb,
+    centroid: Vec3A,
+}
+
+/// A bounding volume hierarchy split by the surface area heuristic, over
+/// the objects of a scene or any other primitives identified by `T`.
+///
+/// Primitives without a bounding box, like wormholes, are kept in a
+/// separate list and tested against every ray.
+#[derive(Debug, Clone)]
+pub struct Bvh<T = ObjectRef> {
+    nodes: Vec<Node>,
+    objects: Vec<(T, Aabb)>,
+    unbounded: Vec<T>,
+}
+
+impl Bvh {
+    /// Builds the top level of the scene's hierarchy, which leaves out
+    /// hidden objects and the prototypes of instances.
+    pub fn from_scene(scene: &Scene) -> Self {
+        Self::from_objects(
+            scene,
+            scene
+                .pairs()
+                .map(|(object_ref, _)| object_ref)
+                .filter(|&object_ref| !scene.is_instanced(object_ref)),
+        )
+    }
+
+    /// Builds a hierarchy over `objects`, leaving out hidden ones.
+    pub fn from_objects<I>(scene: &Scene, objects: I) -> Self
+    where
+        I: IntoIterator<Item = ObjectRef>,
+    {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+
+        for object_ref in objects {
+            let object = scene.get_object(object_ref);
+            if object.has_flags(ObjectFlags::HIDDEN) {
+                continue;
+            }
+            match object.bounding_box(scene) {
+                Some(bbox) => bounded.push((object_ref, Aabb::from(bbox))),
+                None if object.is_hittable() => unbounded.push(object_ref),
+                None => {}
+            }
+        }
+
+        Self::with_unbounded(bounded, unbounded)
+    }
+}
+
+impl<T> Default for Bvh<T> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            objects: Vec::new(),
+            unbounded: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy> Bvh<T> {
+    pub fn new<I>(primitives: I) -> Self
+    where
+        I: IntoIterator<Item = (T, Aabb)>,
+    {
+        Self::with_unbounded(primitives, Vec::new())
+    }
+
+    pub fn with_unbounded<I>(primitives: I, unbounded: Vec<T>) -> Self
+    where
+        I: IntoIterator<Item = (T, Aabb)>,
+    {
+        let mut primitives: Vec<_> = primitives
+            .into_iter()
+            .map(|(id, bbox)| {
+                let bbox = bbox.grow(PADDING);
+                Primitive {
+                    id,
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * primitives.len()),
+            objects: Vec::with_capacity(primitives.len()),
+            unbounded,
+        };
+        if !primitives.is_empty() {
+            bvh.build(&mut primitives);
+        }
+        bvh
+    }
+
+    /// Bounds of everything in the hierarchy, apart from the unbounded
+    /// primitives.
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| *node.bbox())
+    }
+
+    /// Whether every primitive is within `bounds`.
+    pub fn is_bounded(&self) -> bool {
+        self.unbounded.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len() + self.unbounded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn depth(&self) -> usize {
+        fn depth(nodes: &[Node], index: usize) -> usize {
+            match nodes[index] {
+                Node::Leaf { .. } => 1,
+                Node::Interior { second, .. } => {
+                    1 + depth(nodes, index + 1).max(depth(nodes, second))
+                }
+            }
+        }
+
+        if self.nodes.is_empty() {
+            0
+        } else {
+            depth(&self.nodes, 0)
+        }
+    }
+
+    fn build(&mut self, primitives: &mut [Primitive<T>]) {
+        let bbox = primitives
+            .iter()
+            .fold(Aabb::EMPTY, |bbox, primitive| bbox.union(primitive.bbox));
+        let leaf = |bvh: &mut Self, primitives: &[Primitive<T>]| {
+            bvh.nodes.push(Node::Leaf {
+                bbox,
+                start: bvh.objects.len(),
+                count: primitives.len(),
+            });
+            bvh.objects.extend(
+                primitives
+                    .iter()
+                    .map(|primitive| (primitive.id, primitive.bbox)),
+            );
+        };
+
+        if primitives.len() == 1 {
+            leaf(self, primitives);
+            return;
+        }
+
+        let bounds = primitives.iter().fold(Aabb::EMPTY, |bounds, primitive| {
+            Aabb::new(
+                bounds.min.min(primitive.centroid),
+                bounds.max.max(primitive.centroid),
+            )
+        });
+        let extent = bounds.max - bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        // all centroids coincide, there is nothing to split
+        if extent[axis] <= 0.0 {
+            if primitives.len() <= MAX_LEAF_SIZE {
+                leaf(self, primitives);
+            } else {
+                let mid = primitives.len() / 2;
+                self.split(bbox, axis, primitives, mid);
+            }
+            return;
+        }
+
+        let bucket = |primitive: &Primitive<T>| {
+            let offset = (primitive.centroid[axis] - bounds.min[axis]) / extent[axis];
+            ((offset * BUCKETS as f32) as usize).min(BUCKETS - 1)
+        };
+
+        let mut buckets = [(0, Aabb::EMPTY); BUCKETS];
+        for primitive in primitives.iter() {
+            let (count, bounds) = &mut buckets[bucket(primitive)];
+            *count += 1;
+            *bounds = bounds.union(primitive.bbox);
+        }
+
+        // cost of splitting after each bucket, sweeping from both ends
+        let mut costs = [0.0; BUCKETS - 1];
+        let (mut count, mut bounds) = (0, Aabb::EMPTY);
+        for (cost, &(n, b)) in costs.iter_mut().zip(&buckets) {
+            count += n;
+            bounds = bounds.union(b);
+            *cost = count as f32 * bounds.surface_area();
+        }
+        let (mut count, mut bounds) = (0, Aabb::EMPTY);
+        for (cost, &(n, b)) in costs.iter_mut().zip(&buckets[1..]).rev() {
+            count += n;
+            bounds = bounds.union(b);
+            *cost += count as f32 * bounds.surface_area();
+        }
+
+        let (split, cost) = costs
+            .iter()
+            .map(|cost| TRAVERSAL_COST + cost / bbox.surface_area())
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("there is more than one bucket");
+
+        if primitives.len() <= MAX_LEAF_SIZE && cost >= primitives.len() as f32 {
+            leaf(self, primitives);
+            return;
+        }
+
+        let mid = partition(primitives, |primitive| bucket(primitive) <= split);
+        let mid = if mid == 0 || mid == primitives.len() {
+            primitives.len() / 2
+        } else {
+            mid
+        };
+        self.split(bbox, axis, primitives, mid);
+    }
+
+    fn split(&mut self, bbox: Aabb, axis: usize, primitives: &mut [Primitive<T>], mid: usize) {
+        let index = self.nodes.len();
+        self.nodes.push(Node::Interior {
+            bbox,
+            second: 0,
+            axis,
+        });
+
+        let (first, second) = primitives.split_at_mut(mid);
+        self.build(first);
+        let second_index = self.nodes.len();
+        self.build(second);
+
+        if let Node::Interior { second, .. } = &mut self.nodes[index] {
+            *second = second_index;
+        }
+    }
+
+    /// Calls `hit` for every primitive whose bounding box the ray enters
+    /// within `clip`, front to back, shrinking `clip.max` to the closest
+    /// hit found so far, and returns that hit.
+    pub fn hit<H, F>(&self, ray: &Ray, clip: &Clip, mut hit: F) -> Option<H>
+    where
+        H: Intersection,
+        F: FnMut(T, &Clip) -> Option<H>,
+    {
+        let mut result = None;
+        let mut clip = *clip;
+
+        for &id in &self.unbounded {
+            if let Some(found) = hit(id, &clip) {
+                clip.max = found.distance();
+                result = Some(found);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return result;
+        }
+
+        let inverse = ray.direction.recip();
+        let negative = [
+            ray.direction.x < 0.0,
+            ray.direction.y < 0.0,
+            ray.direction.z < 0.0,
+        ];
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bbox().intersect(ray.origin, inverse, &clip).is_none() {
+                continue;
+            }
+
+            match *node {
+                Node::Leaf { start, count, .. } => {
+                    for &(id, _) in &self.objects[start..start + count] {
+                        if let Some(found) = hit(id, &clip) {
+                            clip.max = found.distance();
+                            result = Some(found);
+                        }
+                    }
+                }
+                Node::Interior { second, axis, .. } => {
+                    // visit the child closer to the origin first
+                    if negative[axis] {
+                        stack.push(index + 1);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(index + 1);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Like `hit`, but for the line segment from `start` to `end`, which is
+    /// passed to `hit` as a ray clipped to the segment's length.
+    ///
+    /// The objects near the segment are cached in `cursor`, so consecutive
+    /// segments of the same curved path that stay in that neighbourhood
+    /// don't traverse the hierarchy again.
+    pub fn hit_segment<H, F>(
+        &self,
+        cursor: &mut SegmentCursor<T>,
+        start: Vec3A,
+        end: Vec3A,
+        mut hit: F,
+    ) -> Option<H>
+    where
+        H: Intersection,
+        F: FnMut(T, &Ray, &Clip) -> Option<H>,
+    {
+        let offset = end - start;
+        let length = offset.length();
+        if length <= 0.0 {
+            return None;
+        }
+
+        let ray = Ray::new(start, offset);
+        let mut clip = Clip {
+            min: 0.0,
+            max: length,
+        };
+
+        let bounds = Aabb::new(start.min(end), start.max(end));
+        if !cursor.region.contains(&bounds) {
+            cursor.refill(self, bounds, length);
+        } else {
+            cursor.reused += 1;
+        }
+
+        // too many objects near the path to be worth caching
+        if cursor.overflow {
+            return self.hit(&ray, &clip, |id, clip| hit(id, &ray, clip));
+        }
+
+        let mut result = None;
+
+        for &id in &self.unbounded {
+            if let Some(found) = hit(id, &ray, &clip) {
+                clip.max = found.distance();
+                result = Some(found);
+            }
+        }
+
+        let inverse = ray.direction.recip();
+        for &(id, bbox) in &cursor.candidates {
+            if bbox.intersect(ray.origin, inverse, &clip).is_none() {
+                continue;
+            }
+            if let Some(found) = hit(id, &ray, &clip) {
+                clip.max = found.distance();
+                result = Some(found);
+            }
+        }
+
+        result
+    }
+
+    /// Collects the primitives whose bounding boxes overlap `region`, or
+    /// returns false if there are more than `limit` of them.
+    fn overlapping(&self, region: &Aabb, objects: &mut Vec<(T, Aabb)>, limit: usize) -> bool {
+        if self.nodes.is_empty() {
+            return true;
+        }
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bbox().overlaps(region) {
+                continue;
+            }
+
+            match *node {
+                Node::Leaf { start, count, .. } => {
+                    for &(id, bbox) in &self.objects[start..start + count] {
+                        if !bbox.overlaps(region) {
+                            continue;
+                        }
+                        if objects.len() >= limit {
+                            return false;
+                        }
+                        objects.push((id, bbox));
+                    }
+                }
+                Node::Interior { second, .. } => {
+                    stack.push(second);
+                    stack.push(index + 1);
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Traversal state carried between consecutive segments of a path, see
+/// `Bvh::hit_segment`.
+#[derive(Debug, Clone)]
+pub struct SegmentCursor<T = ObjectRef> {
+    region: Aabb,
+    candidates: Vec<(T, Aabb)>,
+    overflow: bool,
+    refilled: usize,
+    reused: usize,
+}
+
+impl<T: Copy> SegmentCursor<T> {
+    pub fn new() -> Self {
+        Self {
+            region: Aabb::EMPTY,
+            candidates: Vec::new(),
+            overflow: false,
+            refilled: 0,
+            reused: 0,
+        }
+    }
+
+    /// Forgets the cached neighbourhood, for the start of a new path.
+    pub fn reset(&mut self) {
+        self.region = Aabb::EMPTY;
+        self.candidates.clear();
+        self.overflow = false;
+    }
+
+    /// Number of segments that had to traverse the hierarchy.
+    pub fn refilled(&self) -> usize {
+        self.refilled
+    }
+
+    /// Number of segments that reused the cached neighbourhood.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    fn refill(&mut self, bvh: &Bvh<T>, bounds: Aabb, length: f32) {
+        self.refilled += 1;
+        self.region = bounds.grow(REGION_SCALE * length);
+        self.candidates.clear();
+        self.overflow = !bvh.overlapping(&self.region, &mut self.candidates, MAX_CANDIDATES);
+    }
+}
+
+impl<T: Copy> Default for SegmentCursor<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Moves the elements matching `pred` to the front and returns how many
+/// there are.
+fn partition<T, P>(slice: &mut [T], mut pred: P) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    let mut mid = 0;
+    for i in 0..slice.len() {
+        if pred(&slice[i]) {
+            slice.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::color::LinearRgb;
+    use crate::math::distr::UnitSphere;
+    use crate::scene::{Cuboid, Data, Material, Object, Rect, Sphere};
+
+    #[test]
+    fn matches_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut scene = Scene::default();
+        let material = scene.add_data(Data::new(Material::diffuse(LinearRgb::splat(0.5), 1.0)));
+
+        for i in 0..500 {
+            let position = Vec3A::new(
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(-50.0..50.0),
+            );
+            let object = match i % 3 {
+                0 => Object::new(Sphere::new(material, rng.gen_range(0.1..3.0))),
+                1 => Object::new(Rect::new(material, Vec3A::new(2.0, 0.0, 1.0), Vec3A::Y)),
+                _ => Object::new(Cuboid::new(
+                    material,
+                    Vec3A::X,
+                    2.0 * Vec3A::Y,
+                    0.5 * Vec3A::Z,
+                )),
+            };
+            scene.add_object(object.with_translation(position));
+        }
+
+        let bvh = Bvh::from_scene(&scene);
+        assert_eq!(bvh.len(), 500);
+        assert!(bvh.depth() < 40, "depth {}", bvh.depth());
+
+        let clip = Clip {
+            min: 0.01,
+            max: 1000.0,
+        };
+        let mut cursor = SegmentCursor::new();
+        for _ in 0..2000 {
+            let ray = Ray::new(
+                Vec3A::new(
+                    rng.gen_range(-60.0..60.0),
+                    rng.gen_range(-60.0..60.0),
+                    rng.gen_range(-60.0..60.0),
+                ),
+                rng.sample(UnitSphere),
+            );
+
+            let expected = scene
+                .iter()
+                .filter_map(|object| object.hit(&ray, &clip, &scene))
+                .min_by(|a, b| a.t.total_cmp(&b.t));
+            let result = bvh.hit(&ray, &clip, |object_ref, clip| {
+                scene.get_object(object_ref).hit(&ray, clip, &scene)
+            });
+
+            assert_eq!(
+                expected.map(|manifold| manifold.object_ref),
+                result.map(|manifold| manifold.object_ref)
+            );
+
+            // the same ray, walked in segments
+            let expected = bvh.hit(
+                &ray,
+                &Clip {
+                    min: 0.0,
+                    max: 200.0,
+                },
+                |object_ref, clip| scene.get_object(object_ref).hit(&ray, clip, &scene),
+            );
+            cursor.reset();
+            let result = (0..100).find_map(|i| {
+                let start = ray.at(2.0 * i as f32);
+                let end = ray.at(2.0 * (i + 1) as f32);
+                bvh.hit_segment(&mut cursor, start, end, |object_ref, ray, clip| {
+                    scene.get_object(object_ref).hit(ray, clip, &scene)
+                })
+            });
+
+            assert_eq!(
+                expected.map(|manifold| manifold.object_ref),
+                result.map(|manifold| manifold.object_ref)
+            );
+        }
+        assert!(cursor.reused() > cursor.refilled());
+    }
+}
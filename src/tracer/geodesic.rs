@@ -0,0 +1,836 @@
+use glam::{Mat3A, Quat, Vec3, Vec3A};
+
+use crate::scene::Scene;
+
+use super::{Clip, MassProfile, Ray, Screen};
+
+/// How an emitter moves, which decides the doppler part of the frequency
+/// shift of the light it emits.
+#[derive(Debug, Default, Clone, Copy)]
+pub enum Emitter {
+    #[default]
+    Static,
+    /// A circular keplerian orbit around `axis` through `center`, counter
+    /// clockwise when looking down the axis.
+    Orbit { center: Vec3A, axis: Vec3A },
+}
+
+/// Rays traced backwards only creep up on a horizon without crossing it,
+/// so they are taken to have fallen in this close to it, relative to its
+/// radius.
+const HORIZON_MARGIN: f32 = 1e-3;
+
+/// A source of spacetime curvature, in geometrized units (`G = c = 1`).
+///
+/// Lenses are written in Kerr-Schild form, `g^μν = η^μν - f l^μ l^ν`, which
+/// lets several of them be superimposed on the same flat background. The
+/// superposition is only exact for a single lens, but it is a good
+/// approximation as long as the lenses are far apart. Extended masses are
+/// weak fields, and use the linearized metric of their Newtonian potential.
+#[derive(Debug, Clone, Copy)]
+pub enum Lens {
+    Schwarzschild {
+        center: Vec3A,
+        mass: f32,
+    },
+    /// `spin` is the angular momentum per unit mass `a`, the spin axis is
+    /// the local y axis of `rotation`.
+    Kerr {
+        center: Vec3A,
+        rotation: Quat,
+        mass: f32,
+        spin: f32,
+    },
+    /// A spherical mass distribution, `g^μν = η^μν + 2Φ diag(1, 1, 1, 1)`.
+    Halo {
+        center: Vec3A,
+        profile: MassProfile,
+    },
+}
+
+impl Lens {
+    pub fn schwarzschild(center: Vec3A, mass: f32) -> Self {
+        Self::Schwarzschild { center, mass }
+    }
+
+    pub fn kerr(center: Vec3A, rotation: Quat, mass: f32, spin: f32) -> Self {
+        Self::Kerr {
+            center,
+            rotation,
+            mass,
+            spin,
+        }
+    }
+
+    pub fn halo(center: Vec3A, profile: MassProfile) -> Self {
+        Self::Halo { center, profile }
+    }
+
+    pub fn center(&self) -> Vec3A {
+        match *self {
+            Self::Schwarzschild { center, .. }
+            | Self::Kerr { center, .. }
+            | Self::Halo { center, .. } => center,
+        }
+    }
+
+    pub fn mass(&self) -> f32 {
+        match *self {
+            Self::Schwarzschild { mass, .. } | Self::Kerr { mass, .. } => mass,
+            Self::Halo { profile, .. } => profile.mass(),
+        }
+    }
+
+    pub fn is_inside_horizon(&self, position: Vec3A) -> bool {
+        match *self {
+            Self::Schwarzschild { center, mass } => {
+                position.distance(center) <= 2.0 * mass * (1.0 + HORIZON_MARGIN)
+            }
+            Self::Kerr {
+                center,
+                rotation,
+                mass,
+                spin,
+            } => {
+                let x = to_kerr_schild(rotation.inverse() * (position - center));
+                let horizon = mass + (mass * mass - spin * spin).max(0.0).sqrt();
+                kerr_radius(x, spin) <= horizon * (1.0 + HORIZON_MARGIN)
+            }
+            Self::Halo { .. } => false,
+        }
+    }
+
+    /// Time component of the four-velocity and the angular velocity of a
+    /// circular orbit through `position` around `axis`, if there is one.
+    fn orbit(&self, position: Vec3A, axis: Vec3A) -> Option<(f32, f32)> {
+        match *self {
+            Self::Schwarzschild { center, mass } => {
+                let r = position.distance(center);
+                let denom = 1.0 - 3.0 * mass / r;
+                (denom > 0.0).then(|| (denom.sqrt().recip(), (mass / (r * r * r)).sqrt()))
+            }
+            Self::Kerr {
+                center,
+                rotation,
+                mass,
+                spin,
+            } => {
+                let x = to_kerr_schild(rotation.inverse() * (position - center));
+                let r = kerr_radius(x, spin);
+                let a = spin * (rotation * Vec3A::Y).dot(axis);
+
+                let sqrt_m = mass.sqrt();
+                let r_sqrt = r.sqrt();
+                let r_32 = r * r_sqrt;
+                let denom = r_32 - 3.0 * mass * r_sqrt + 2.0 * a * sqrt_m;
+                (denom > 0.0).then(|| {
+                    let ut = (r_32 + a * sqrt_m) / (r_sqrt.sqrt() * r_sqrt * denom.sqrt());
+                    let omega = sqrt_m / (r_32 + a * sqrt_m);
+                    (ut, omega)
+                })
+            }
+            Self::Halo { center, profile } => {
+                let r = position.distance(center);
+                let omega = (profile.enclosed_mass(r) / (r * r * r)).sqrt();
+                let denom = 1.0 + 2.0 * profile.potential(r) - (r * omega).powi(2);
+                (denom > 0.0).then(|| (denom.sqrt().recip(), omega))
+            }
+        }
+    }
+
+    /// Impact parameter below which a photon falls into the lens.
+    pub fn capture_radius(&self) -> f32 {
+        match *self {
+            Self::Schwarzschild { .. } | Self::Kerr { .. } => 3.0 * 3_f32.sqrt() * self.mass(),
+            Self::Halo { .. } => 0.0,
+        }
+    }
+
+    /// Mass inside a cylinder of radius `impact` along the line of sight.
+    pub fn projected_mass(&self, impact: f32) -> f32 {
+        match *self {
+            Self::Schwarzschild { mass, .. } | Self::Kerr { mass, .. } => mass,
+            Self::Halo { profile, .. } => profile.projected_mass(impact),
+        }
+    }
+
+    /// Distance along `ray` to its closest approach to the lens.
+    pub fn closest_approach(&self, ray: &Ray) -> f32 {
+        (self.center() - ray.origin).dot(ray.direction)
+    }
+
+    /// Bends `ray` once at its closest approach to the lens by the weak
+    /// field deflection angle `4 M(<b) / b`, or returns `None` if it is
+    /// captured.
+    pub fn deflect(&self, ray: &Ray) -> Option<Ray> {
+        let position = ray.at(self.closest_approach(ray));
+        let offset = position - self.center();
+        let impact = offset.length();
+        if impact <= self.capture_radius() {
+            return None;
+        }
+
+        let angle = 4.0 * self.projected_mass(impact) / impact;
+        let direction = ray.direction * angle.cos() - offset / impact * angle.sin();
+        Some(Ray::new(position, direction))
+    }
+
+    /// Kerr-Schild scalar `f` and the spatial part of the null vector `l` at
+    /// `position`. Halos aren't in Kerr-Schild form and have `f = 0`.
+    fn kerr_schild(&self, position: Vec3A) -> (f32, Vec3A) {
+        match *self {
+            Self::Schwarzschild { center, mass } => {
+                let x = position - center;
+                let r = x.length();
+                (2.0 * mass / r, x / r)
+            }
+            Self::Kerr {
+                center,
+                rotation,
+                mass,
+                spin,
+            } => {
+                let x = to_kerr_schild(rotation.inverse() * (position - center));
+                let (f, l) = kerr_schild_local(x, mass, spin);
+                (f, rotation * from_kerr_schild(l))
+            }
+            Self::Halo { .. } => (0.0, Vec3A::ZERO),
+        }
+    }
+
+    /// Perturbation of the inverse metric at `position`, as `h^tt`, `h^ti`
+    /// and `h^ij` in `g^μν = η^μν - h^μν` with `p_t = -E`.
+    fn perturbation(&self, position: Vec3A) -> (f32, Vec3A, Mat3A) {
+        match *self {
+            Self::Halo { center, profile } => {
+                let h = -2.0 * profile.potential(position.distance(center));
+                (h, Vec3A::ZERO, Mat3A::from_diagonal(Vec3::splat(h)))
+            }
+            _ => {
+                let (f, l) = self.kerr_schild(position);
+                (f, f * l, Mat3A::from_cols(l * l.x, l * l.y, l * l.z) * f)
+            }
+        }
+    }
+
+    /// Strength of the field at `position`; zero in flat space.
+    pub fn strength(&self, position: Vec3A) -> f32 {
+        match *self {
+            Self::Halo { center, profile } => -2.0 * profile.potential(position.distance(center)),
+            _ => self.kerr_schild(position).0,
+        }
+    }
+
+    // the hamiltonian is `H = ½ η^μν p_μ p_ν - ½ Σ f (l·p - E)²`, or
+    // `+ Φ (|p|² + E²)` for halos; these are this lens' contributions to
+    // `∂H/∂p` and `-∂H/∂x`
+
+    fn velocity(&self, position: Vec3A, momentum: Vec3A, energy: f32) -> Vec3A {
+        match *self {
+            Self::Halo { center, profile } => {
+                2.0 * profile.potential(position.distance(center)) * momentum
+            }
+            _ => {
+                let (f, l) = self.kerr_schild(position);
+                -f * (l.dot(momentum) - energy) * l
+            }
+        }
+    }
+
+    fn force(&self, position: Vec3A, momentum: Vec3A, energy: f32) -> Vec3A {
+        match *self {
+            Self::Schwarzschild { center, mass } => {
+                let x = position - center;
+                let r = x.length();
+                let n = x / r;
+                let f = 2.0 * mass / r;
+                let lp = n.dot(momentum) - energy;
+
+                let grad_f = -f * n / r;
+                let grad_lp = (momentum - n * n.dot(momentum)) / r;
+                0.5 * lp * lp * grad_f + f * lp * grad_lp
+            }
+            Self::Kerr {
+                center,
+                rotation,
+                mass,
+                spin,
+            } => {
+                let x = to_kerr_schild(rotation.inverse() * (position - center));
+                let p = to_kerr_schild(rotation.inverse() * momentum);
+                let potential = |x| {
+                    let (f, l) = kerr_schild_local(x, mass, spin);
+                    let lp = l.dot(p) - energy;
+                    0.5 * f * lp * lp
+                };
+
+                // the gradient is messy in closed form, so differentiate numerically
+                let eps = 1e-3 * (mass + x.length());
+                let grad = Vec3A::new(
+                    potential(x + Vec3A::X * eps) - potential(x - Vec3A::X * eps),
+                    potential(x + Vec3A::Y * eps) - potential(x - Vec3A::Y * eps),
+                    potential(x + Vec3A::Z * eps) - potential(x - Vec3A::Z * eps),
+                ) / (2.0 * eps);
+                rotation * from_kerr_schild(grad)
+            }
+            Self::Halo { center, profile } => {
+                let x = position - center;
+                let r = x.length();
+                let grad = profile.enclosed_mass(r) / (r * r * r) * x;
+                -grad * (momentum.length_squared() + energy * energy)
+            }
+        }
+    }
+}
+
+// kerr-schild coordinates have the spin axis along z, while objects spin
+// around their local y axis; these cyclically permute the axes to keep the
+// handedness of the rotation
+
+fn to_kerr_schild(v: Vec3A) -> Vec3A {
+    Vec3A::new(v.z, v.x, v.y)
+}
+
+fn from_kerr_schild(v: Vec3A) -> Vec3A {
+    Vec3A::new(v.y, v.z, v.x)
+}
+
+/// Boyer-Lindquist radius of a point in kerr-schild coordinates.
+fn kerr_radius(x: Vec3A, spin: f32) -> f32 {
+    let a2 = spin * spin;
+    let w = 0.5 * (x.length_squared() - a2);
+    (w + (w * w + a2 * x.z * x.z).sqrt()).sqrt()
+}
+
+fn kerr_schild_local(x: Vec3A, mass: f32, spin: f32) -> (f32, Vec3A) {
+    let a = spin;
+    let r = kerr_radius(x, a);
+    let r2 = r * r;
+    let f = 2.0 * mass * r2 * r / (r2 * r2 + a * a * x.z * x.z);
+    let l = Vec3A::new(
+        (r * x.x + a * x.y) / (r2 + a * a),
+        (r * x.y - a * x.x) / (r2 + a * a),
+        x.z / r,
+    );
+    (f, l)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Spacetime {
+    lenses: Vec<Lens>,
+    screens: Vec<Screen>,
+}
+
+impl Spacetime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collects the lenses and screens of the scene, leaving out those in
+    /// the prototypes of instances.
+    pub fn from_scene(scene: &Scene) -> Self {
+        let objects = || {
+            scene
+                .pairs()
+                .filter(|&(object_ref, _)| !scene.is_instanced(object_ref))
+                .map(|(_, object)| object)
+        };
+        let lenses = objects().filter_map(|object| object.lens()).collect();
+        let screens = objects()
+            .filter_map(|object| object.screen(scene))
+            .collect();
+        Self { lenses, screens }
+    }
+
+    pub fn add(&mut self, lens: Lens) {
+        self.lenses.push(lens);
+    }
+
+    pub fn add_screen(&mut self, screen: Screen) {
+        self.screens.push(screen);
+    }
+
+    pub fn lenses(&self) -> &[Lens] {
+        &self.lenses
+    }
+
+    pub fn screens(&self) -> &[Screen] {
+        &self.screens
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.lenses.is_empty() && self.screens.is_empty()
+    }
+
+    /// Index of the first screen `ray` crosses within `clip`, and the
+    /// distance to it.
+    pub fn next_screen(&self, ray: &Ray, clip: &Clip) -> Option<(usize, f32)> {
+        self.screens
+            .iter()
+            .enumerate()
+            .filter_map(|(index, screen)| Some((index, screen.crossing(ray)?)))
+            .filter(|&(_, t)| t >= clip.min && t <= clip.max)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    pub fn is_inside_horizon(&self, position: Vec3A) -> bool {
+        !position.is_finite()
+            || self
+                .lenses
+                .iter()
+                .any(|lens| lens.is_inside_horizon(position))
+    }
+
+    pub fn strength(&self, position: Vec3A) -> f32 {
+        self.lenses.iter().map(|lens| lens.strength(position)).sum()
+    }
+
+    /// Whether a ray at `position` heading along `direction` is moving away
+    /// from every lens through a field weaker than `threshold`, so that the
+    /// rest of its path can be treated as straight.
+    pub fn is_escaping(&self, position: Vec3A, direction: Vec3A, threshold: f32) -> bool {
+        self.strength(position) < threshold
+            && self
+                .lenses
+                .iter()
+                .all(|lens| (position - lens.center()).dot(direction) >= 0.0)
+    }
+
+    /// Frequency measured by a static observer at `position` for a photon
+    /// with conserved `energy`.
+    fn static_frequency(&self, position: Vec3A, energy: f32) -> f32 {
+        energy / (1.0 - self.strength(position)).max(1e-6).sqrt()
+    }
+
+    /// Ratio `g` of the frequency seen by a static observer at `observer` to
+    /// the frequency at which `emitter` sent out a photon from `position`,
+    /// where it had the (backwards) `momentum` and conserved `energy`.
+    pub fn shift(
+        &self,
+        observer: Vec3A,
+        position: Vec3A,
+        momentum: Vec3A,
+        energy: f32,
+        emitter: Emitter,
+    ) -> f32 {
+        let observed = self.static_frequency(observer, energy);
+
+        let emitted = match emitter {
+            Emitter::Static => None,
+            Emitter::Orbit { center, axis } => self
+                .lenses
+                .iter()
+                .min_by(|a, b| {
+                    let a = a.center().distance_squared(center);
+                    let b = b.center().distance_squared(center);
+                    a.total_cmp(&b)
+                })
+                .and_then(|lens| lens.orbit(position, axis))
+                .map(|(ut, omega)| {
+                    let angular_momentum = axis.dot((position - center).cross(momentum));
+                    ut * (energy + omega * angular_momentum)
+                }),
+        };
+        let emitted = emitted.unwrap_or_else(|| self.static_frequency(position, energy));
+
+        observed / emitted
+    }
+
+    /// Spatial momentum of a photon that a static observer at `position`
+    /// sees moving along the unit vector `direction` with unit frequency.
+    ///
+    /// That is `k_i = n_i + u_i`, where the observer's rest space has the
+    /// inverse metric `γ^ij = δ^ij - Σ h^ij`, so its local directions
+    /// map to `n_i` by the symmetric `γ^-1/2`, found with a coupled
+    /// Newton-Schulz iteration. Inside an ergosphere there are no static
+    /// observers and `direction` is used as is.
+    pub fn local_momentum(&self, position: Vec3A, direction: Vec3A) -> Vec3A {
+        let strength = self.strength(position);
+        if self.is_flat() || strength >= 1.0 {
+            return direction;
+        }
+
+        let (metric, shift) =
+            self.lenses
+                .iter()
+                .fold((Mat3A::IDENTITY, Vec3A::ZERO), |(metric, shift), lens| {
+                    let (_, h_ti, h_ij) = lens.perturbation(position);
+                    (metric - h_ij, shift + h_ti)
+                });
+
+        let mut y = metric;
+        let mut z = Mat3A::IDENTITY;
+        for _ in 0..16 {
+            let t = (Mat3A::from_diagonal(Vec3::splat(3.0)) - z * y) * 0.5;
+            y *= t;
+            z = t * z;
+        }
+
+        z * direction - shift / (1.0 - strength).sqrt()
+    }
+
+    /// Solves `H = 0` for the conserved energy of a photon leaving
+    /// `position` with the spatial `momentum`.
+    pub fn energy(&self, position: Vec3A, momentum: Vec3A) -> Option<f32> {
+        let initial = (1.0, 0.0, momentum.length_squared());
+        let (a, b, c) = self.lenses.iter().fold(initial, |(a, b, c), lens| {
+            let (h_tt, h_ti, h_ij) = lens.perturbation(position);
+            (
+                a + h_tt,
+                b + h_ti.dot(momentum),
+                c - momentum.dot(h_ij * momentum),
+            )
+        });
+
+        let discriminant = b * b + a * c;
+        if discriminant.is_sign_negative() {
+            return None;
+        }
+
+        let energy = (b + discriminant.sqrt()) / a;
+        (energy > 0.0).then_some(energy)
+    }
+
+    /// Derivatives of position and momentum with respect to coordinate distance.
+    fn derivative(&self, position: Vec3A, momentum: Vec3A, energy: f32) -> (Vec3A, Vec3A) {
+        let (velocity, force) =
+            self.lenses
+                .iter()
+                .fold((momentum, Vec3A::ZERO), |(velocity, force), lens| {
+                    (
+                        velocity + lens.velocity(position, momentum, energy),
+                        force + lens.force(position, momentum, energy),
+                    )
+                });
+
+        let speed_recip = velocity.length_recip();
+        (velocity * speed_recip, force * speed_recip)
+    }
+}
+
+// Dormand-Prince 5(4) tableau, the last row gives the fifth order solution
+const DP_A: [&[f32]; 6] = [
+    &[1.0 / 5.0],
+    &[3.0 / 40.0, 9.0 / 40.0],
+    &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+    &[
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+    ],
+    &[
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+    ],
+    &[
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ],
+];
+
+// difference between the fifth and fourth order weights
+const DP_E: [f32; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+/// Error control for `Geodesic::step_adaptive`.
+#[derive(Debug, Clone, Copy)]
+pub struct StepControl {
+    /// Largest error in position and momentum allowed per step.
+    pub tolerance: f32,
+    pub min_step: f32,
+    pub max_step: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveStep {
+    pub taken: f32,
+    /// Suggested size for the next step.
+    pub next: f32,
+    pub rejected: usize,
+}
+
+/// A null geodesic traced backwards from the camera.
+///
+/// The momentum is the covariant spatial momentum `p_i` of the photon with
+/// its direction flipped, so integrating forward in the affine parameter
+/// walks the photon's path back towards its source.
+#[derive(Debug, Clone, Copy)]
+pub struct Geodesic {
+    pub position: Vec3A,
+    pub momentum: Vec3A,
+    pub energy: f32,
+    pub length: f32,
+}
+
+impl Geodesic {
+    /// Starts a geodesic along `ray`, whose direction is the one seen by a
+    /// static observer at its origin.
+    pub fn new(spacetime: &Spacetime, ray: &Ray) -> Option<Self> {
+        if spacetime.is_inside_horizon(ray.origin) {
+            return None;
+        }
+
+        let momentum = spacetime.local_momentum(ray.origin, ray.direction);
+        let energy = spacetime.energy(ray.origin, momentum)?;
+        Some(Self {
+            position: ray.origin,
+            momentum,
+            energy,
+            length: 0.0,
+        })
+    }
+
+    pub fn direction(&self, spacetime: &Spacetime) -> Vec3A {
+        spacetime
+            .derivative(self.position, self.momentum, self.energy)
+            .0
+    }
+
+    pub fn ray(&self, spacetime: &Spacetime) -> Ray {
+        Ray::new(self.position, self.direction(spacetime))
+    }
+
+    /// Moves the geodesic `distance` along the straight line to where it
+    /// crosses `screen` at `position`, and bends it there.
+    pub fn cross(&mut self, screen: &Screen, position: Vec3A, distance: f32) {
+        let direction = self.momentum.normalize();
+        let bent = screen.bend(position, direction);
+        self.momentum = Quat::from_rotation_arc(direction.into(), bent.into()) * self.momentum;
+        self.position = position;
+        self.length += distance;
+    }
+
+    /// Advances the geodesic by `step` units of coordinate distance using RK4.
+    pub fn step(&mut self, spacetime: &Spacetime, step: f32) {
+        let (x, p, e) = (self.position, self.momentum, self.energy);
+
+        let (k1x, k1p) = spacetime.derivative(x, p, e);
+        let (k2x, k2p) = spacetime.derivative(x + 0.5 * step * k1x, p + 0.5 * step * k1p, e);
+        let (k3x, k3p) = spacetime.derivative(x + 0.5 * step * k2x, p + 0.5 * step * k2p, e);
+        let (k4x, k4p) = spacetime.derivative(x + step * k3x, p + step * k3p, e);
+
+        self.position += step / 6.0 * (k1x + 2.0 * k2x + 2.0 * k3x + k4x);
+        self.momentum += step / 6.0 * (k1p + 2.0 * k2p + 2.0 * k3p + k4p);
+        self.length += step;
+    }
+
+    /// Advances the geodesic using Dormand-Prince, shrinking `step` until
+    /// the estimated error is within tolerance.
+    pub fn step_adaptive(
+        &mut self,
+        spacetime: &Spacetime,
+        step: f32,
+        control: &StepControl,
+    ) -> AdaptiveStep {
+        let mut step = step.clamp(control.min_step, control.max_step);
+        let mut rejected = 0;
+
+        loop {
+            let (position, momentum, error) = self.dormand_prince(spacetime, step);
+
+            let ratio = error / control.tolerance;
+            // a non-finite error always shrinks the step
+            let factor = if ratio > 0.0 {
+                (0.9 * ratio.powf(-0.2)).clamp(0.2, 5.0)
+            } else if ratio == 0.0 {
+                5.0
+            } else {
+                0.2
+            };
+
+            if ratio <= 1.0 || step <= control.min_step {
+                self.position = position;
+                self.momentum = momentum;
+                self.length += step;
+
+                return AdaptiveStep {
+                    taken: step,
+                    next: (step * factor).clamp(control.min_step, control.max_step),
+                    rejected,
+                };
+            }
+
+            rejected += 1;
+            step = (step * factor).max(control.min_step);
+        }
+    }
+
+    fn dormand_prince(&self, spacetime: &Spacetime, step: f32) -> (Vec3A, Vec3A, f32) {
+        let (x, p, e) = (self.position, self.momentum, self.energy);
+
+        let combine = |weights: &[f32], k: &[(Vec3A, Vec3A)]| {
+            weights
+                .iter()
+                .zip(k)
+                .fold((Vec3A::ZERO, Vec3A::ZERO), |(dx, dp), (&w, &(kx, kp))| {
+                    (dx + w * kx, dp + w * kp)
+                })
+        };
+
+        let mut k = [(Vec3A::ZERO, Vec3A::ZERO); 7];
+        k[0] = spacetime.derivative(x, p, e);
+        for (i, weights) in DP_A.iter().enumerate() {
+            let (dx, dp) = combine(weights, &k);
+            k[i + 1] = spacetime.derivative(x + step * dx, p + step * dp, e);
+        }
+
+        let (dx, dp) = combine(DP_A[5], &k);
+        let (ex, ep) = combine(&DP_E, &k);
+        let error = (step * ex).length().max((step * ep).length());
+
+        (x + step * dx, p + step * dp, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    fn trace(spacetime: &Spacetime, ray: &Ray, distance: f32) -> Vec3A {
+        let mut geodesic = Geodesic::new(spacetime, ray).unwrap();
+        while geodesic.length < distance {
+            let step = 0.01 * geodesic.position.length();
+            geodesic.step(spacetime, step);
+        }
+        geodesic.direction(spacetime)
+    }
+
+    /// Whether `ray` falls through a horizon before it gets `distance` away
+    /// from the origin again.
+    fn is_captured(spacetime: &Spacetime, ray: &Ray, distance: f32) -> bool {
+        let control = StepControl {
+            tolerance: 1e-4,
+            min_step: 1e-4,
+            max_step: 10.0,
+        };
+        let mut geodesic = Geodesic::new(spacetime, ray).unwrap();
+        let mut step = 1.0;
+        while geodesic.length < 2.0 * distance {
+            step = geodesic.step_adaptive(spacetime, step, &control).next;
+            if spacetime.is_inside_horizon(geodesic.position) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Smallest impact parameter along `side` at which rays along x pass
+    /// the lens.
+    fn critical_impact(spacetime: &Spacetime, side: Vec3A) -> f32 {
+        let (mut captured, mut passed) = (0.0, 10.0);
+        while passed - captured > 5e-3 {
+            let impact = 0.5 * (captured + passed);
+            let ray = Ray::new(Vec3A::new(-1000.0, 0.0, 0.0) + side * impact, Vec3A::X);
+            if is_captured(spacetime, &ray, 1000.0) {
+                captured = impact;
+            } else {
+                passed = impact;
+            }
+        }
+        passed
+    }
+
+    #[test]
+    fn kerr_shadow() {
+        let mut spacetime = Spacetime::new();
+        spacetime.add(Lens::kerr(Vec3A::ZERO, Quat::IDENTITY, 1.0, 0.9));
+
+        // traced backwards, rays passing +z go around the spin axis y like
+        // the hole does, so the photons they follow counter-rotate and are
+        // captured further out; the critical impact parameters of equatorial
+        // photons are from Bardeen's formula
+        let prograde = critical_impact(&spacetime, Vec3A::NEG_Z);
+        let retrograde = critical_impact(&spacetime, Vec3A::Z);
+        assert_relative_eq!(prograde, 2.844, max_relative = 5e-3);
+        assert_relative_eq!(retrograde, 6.832, max_relative = 5e-3);
+
+        // without spin it is a Schwarzschild hole, captured at 3√3 M
+        let mut spacetime = Spacetime::new();
+        spacetime.add(Lens::kerr(Vec3A::ZERO, Quat::IDENTITY, 1.0, 0.0));
+        for side in [Vec3A::Y, Vec3A::Z] {
+            let impact = critical_impact(&spacetime, side);
+            assert_relative_eq!(impact, 3.0 * 3.0f32.sqrt(), max_relative = 5e-3);
+        }
+    }
+
+    #[test]
+    fn adaptive_step() {
+        let mut spacetime = Spacetime::new();
+        spacetime.add(Lens::schwarzschild(Vec3A::ZERO, 1.0));
+        let start =
+            Geodesic::new(&spacetime, &Ray::new(Vec3A::new(-8.0, 5.0, 0.0), Vec3A::X)).unwrap();
+
+        let mut taken = f32::INFINITY;
+        for tolerance in [1e-2, 1e-3, 1e-4] {
+            let control = StepControl {
+                tolerance,
+                min_step: 1e-4,
+                max_step: 100.0,
+            };
+            let mut adaptive = start;
+            let step = adaptive.step_adaptive(&spacetime, 100.0, &control);
+
+            // RK4 with tiny steps over the same distance
+            let mut reference = start;
+            let substeps = 200;
+            for _ in 0..substeps {
+                reference.step(&spacetime, step.taken / substeps as f32);
+            }
+
+            let error = adaptive
+                .position
+                .distance(reference.position)
+                .max(adaptive.momentum.distance(reference.momentum));
+            assert!(
+                error <= tolerance,
+                "error {error} over tolerance {tolerance}"
+            );
+            assert!(step.rejected > 0);
+            assert!(step.taken < taken, "tighter tolerances take smaller steps");
+            taken = step.taken;
+        }
+    }
+
+    #[test]
+    fn thin_lens_weak_field() {
+        let lens = Lens::schwarzschild(Vec3A::ZERO, 1.0);
+        let mut spacetime = Spacetime::new();
+        spacetime.add(lens);
+
+        for impact in [200.0, 500.0, 1000.0] {
+            let ray = Ray::new(Vec3A::new(-1e5, impact, 0.0), Vec3A::X);
+
+            let thin = lens.deflect(&ray).unwrap().direction;
+            let full = trace(&spacetime, &ray, 2e5);
+
+            let thin_angle = thin.y.atan2(thin.x);
+            let full_angle = full.y.atan2(full.x);
+            assert_relative_eq!(thin_angle, -4.0 / impact, max_relative = 1e-3);
+            assert_relative_eq!(thin_angle, full_angle, max_relative = 0.02);
+        }
+    }
+
+    #[test]
+    fn thin_lens_capture() {
+        let lens = Lens::schwarzschild(Vec3A::ZERO, 1.0);
+        let ray = Ray::new(Vec3A::new(-100.0, 5.0, 0.0), Vec3A::X);
+        assert!(lens.deflect(&ray).is_none());
+    }
+}
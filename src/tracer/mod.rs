@@ -1,17 +1,30 @@
+use std::ops::{Add, AddAssign};
+use std::sync::Mutex;
+
 use glam::{Vec3, Vec3A};
 use rand::prelude::*;
 use rand_distr::Uniform;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 
+use crate::color::{self, LinearRgb, WAVELENGTH_MAX, WAVELENGTH_MIN};
 use crate::math::distr::UnitDisk;
-use crate::scene::{DataRef, ObjectRef, Scene};
+use crate::math::spherical_uv;
+use crate::scene::{DataRef, Material, ObjectRef, Scene};
 
 mod buffer;
+mod bvh;
+mod geodesic;
+mod profile;
 mod ray;
+mod screen;
 
 pub use self::buffer::*;
+pub use self::bvh::*;
+pub use self::geodesic::*;
+pub use self::profile::*;
 pub use self::ray::*;
+pub use self::screen::*;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Config {
@@ -20,6 +33,25 @@ pub struct Config {
     pub clip_min: f32,
     pub clip_max: f32,
     pub volume_step: f32,
+    pub integrator: Integrator,
+    /// Size of the first step of every geodesic.
+    pub geodesic_step: f32,
+    pub geodesic_tolerance: f32,
+    pub geodesic_min_step: f32,
+    pub geodesic_max_step: f32,
+    pub geodesic_escape: f32,
+    pub max_geodesic_steps: usize,
+    pub redshift: bool,
+    pub beaming: Beaming,
+    /// Whether dispersive materials split light into its wavelengths, or
+    /// only use their index of refraction at the reference wavelength.
+    pub dispersion: bool,
+    /// Number of wavelengths a path carries from the first dispersive
+    /// surface it meets: a hero wavelength, which picks the directions the
+    /// path takes, and companions spread evenly around it. More of them
+    /// take out more color noise at little cost, except behind smooth
+    /// refractions, which only the hero's light gets through.
+    pub wavelengths: usize,
     pub chunks_x: usize,
     pub chunks_y: usize,
     pub output: Output,
@@ -32,6 +64,17 @@ impl Config {
         clip_min: 0.01,
         clip_max: 1000.0,
         volume_step: 0.1,
+        integrator: Integrator::Geodesic,
+        geodesic_step: 0.05,
+        geodesic_tolerance: 1e-4,
+        geodesic_min_step: 1e-4,
+        geodesic_max_step: 1.0,
+        geodesic_escape: 0.005,
+        max_geodesic_steps: 4096,
+        redshift: true,
+        beaming: Beaming::Bolometric,
+        dispersion: true,
+        wavelengths: 4,
         chunks_x: 4,
         chunks_y: 2,
         output: Output::Full,
@@ -105,6 +148,41 @@ impl Iterator for SubsampleIntoIter {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// Rays travel in straight lines, lenses are ignored.
+    Straight,
+    /// Rays follow null geodesics around lenses, stepped with an adaptive
+    /// Dormand-Prince integrator.
+    #[default]
+    Geodesic,
+    /// Rays are bent once at their closest approach to each lens, which is
+    /// much cheaper than `Geodesic` for weak fields.
+    ThinLens,
+}
+
+/// How the intensity of emitted light scales with its frequency shift `g`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Beaming {
+    /// Intensity is left alone, only the color of emitters shifts.
+    None,
+    /// Specific intensity, `g³`.
+    Specific,
+    /// Bolometric intensity, `g⁴`; this is exact for blackbody emitters.
+    #[default]
+    Bolometric,
+}
+
+impl Beaming {
+    pub fn exponent(self) -> i32 {
+        match self {
+            Self::None => 0,
+            Self::Specific => 3,
+            Self::Bolometric => 4,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub enum Output {
     #[default]
@@ -162,9 +240,51 @@ pub enum Status {
     InProgress,
 }
 
+/// Counters collected over a render, to tune quality against speed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub geodesics: usize,
+    pub steps: usize,
+    pub rejected_steps: usize,
+    pub absorbed: usize,
+    /// Geodesics that ran out of steps before escaping or hitting anything.
+    pub exhausted: usize,
+}
+
+impl Stats {
+    pub fn steps_per_geodesic(&self) -> f32 {
+        if self.geodesics == 0 {
+            0.0
+        } else {
+            self.steps as f32 / self.geodesics as f32
+        }
+    }
+}
+
+impl Add for Stats {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            geodesics: self.geodesics + rhs.geodesics,
+            steps: self.steps + rhs.steps,
+            rejected_steps: self.rejected_steps + rhs.rejected_steps,
+            absorbed: self.absorbed + rhs.absorbed,
+            exhausted: self.exhausted + rhs.exhausted,
+        }
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Tracer {
     pub config: Config,
+    stats: Mutex<Stats>,
 }
 
 impl Tracer {
@@ -173,7 +293,15 @@ impl Tracer {
     }
 
     pub fn with_config(config: Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            stats: Default::default(),
+        }
+    }
+
+    /// Statistics of the last call to `render`.
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock().unwrap()
     }
 
     pub fn render(
@@ -191,10 +319,23 @@ impl Tracer {
             .chunks(self.config.chunks_x, self.config.chunks_y)
             .collect::<Vec<_>>();
 
-        chunks.into_par_iter().for_each(|chunk| {
-            let mut chunk_state = ChunkState::new(ChunkConfig::with_configs(&self.config, config));
-            chunk_state.render_samples(scene, camera, chunk);
-        });
+        let spacetime = Spacetime::from_scene(scene);
+        let bvh = Bvh::from_scene(scene);
+
+        let stats = chunks
+            .into_par_iter()
+            .map(|chunk| {
+                let mut chunk_state = ChunkState::new(
+                    ChunkConfig::with_configs(&self.config, config),
+                    &spacetime,
+                    &bvh,
+                );
+                chunk_state.render_samples(scene, camera, chunk);
+                chunk_state.stats
+            })
+            .reduce(Stats::default, Stats::add);
+
+        *self.stats.lock().unwrap() = stats;
 
         buffer.inc_samples(config.samples * config.subsample.subpixel_count());
 
@@ -212,6 +353,15 @@ struct ChunkConfig {
     pub clip_min: f32,
     pub clip_max: f32,
     pub volume_step: f32,
+    pub integrator: Integrator,
+    pub geodesic_step: f32,
+    pub geodesic_control: StepControl,
+    pub geodesic_escape: f32,
+    pub max_geodesic_steps: usize,
+    pub redshift: bool,
+    pub beaming: Beaming,
+    pub dispersion: bool,
+    pub wavelengths: usize,
 }
 
 impl ChunkConfig {
@@ -225,23 +375,134 @@ impl ChunkConfig {
             clip_min: main.clip_min,
             clip_max: main.clip_max,
             volume_step: render.volume_step.unwrap_or(main.volume_step),
+            integrator: main.integrator,
+            geodesic_step: main.geodesic_step,
+            geodesic_control: StepControl {
+                tolerance: main.geodesic_tolerance,
+                min_step: main.geodesic_min_step,
+                max_step: main.geodesic_max_step,
+            },
+            geodesic_escape: main.geodesic_escape,
+            max_geodesic_steps: main.max_geodesic_steps,
+            redshift: main.redshift,
+            beaming: main.beaming,
+            dispersion: main.dispersion,
+            wavelengths: main.wavelengths.max(1),
         }
     }
 }
 
+enum Trace<'a> {
+    /// The manifold's shift is the frequency shift along the last segment only.
+    Hit(Manifold<'a>),
+    Escaped(Ray, f32),
+    Absorbed,
+}
+
+/// Wavelengths in nanometres that a path carries through dispersive
+/// surfaces, as seen by the camera.
+///
+/// The visible range is cut into equal parts and each wavelength sits at
+/// the same random offset into its part. One of them, picked at random, is
+/// the hero, whose index of refraction decides where the path goes. The
+/// others come along with the path weighted by how likely their own light
+/// is to take it, and each wavelength's light is weighted by how likely
+/// the path is to be sampled by any of them, which is the balance
+/// heuristic over the wavelengths.
+#[derive(Debug, Clone)]
+struct Wavelengths {
+    wavelengths: Vec<f32>,
+    hero: usize,
+    /// Scattering of each wavelength along the path, relative to the hero's.
+    scattering: Vec<f32>,
+    /// Density each wavelength would have sampled the path with, relative
+    /// to the hero's.
+    pdf: Vec<f32>,
+}
+
+impl Wavelengths {
+    fn new<R: Rng + ?Sized>(rng: &mut R, count: usize) -> Self {
+        let stratum = (WAVELENGTH_MAX - WAVELENGTH_MIN) / count as f32;
+        let offset = rng.sample(Uniform::new(0.0, stratum));
+        Self {
+            wavelengths: (0..count)
+                .map(|i| WAVELENGTH_MIN + i as f32 * stratum + offset)
+                .collect(),
+            hero: rng.sample(Uniform::new(0, count)),
+            scattering: vec![1.0; count],
+            pdf: vec![1.0; count],
+        }
+    }
+
+    fn hero(&self) -> f32 {
+        self.wavelengths[self.hero]
+    }
+
+    /// Takes the path on along `ray`, which `material` scattered the hero's
+    /// light into at `manifold`.
+    fn scatter(&mut self, material: &Material, manifold: &Manifold, ray: &Ray) {
+        let shift = manifold.wavelength.map_or(1.0, |hero| hero / self.hero());
+        let (hero_scattering, hero_pdf) = material.spectral_pdf(manifold, ray);
+        if hero_scattering <= 0.0 || hero_pdf <= 0.0 {
+            return;
+        }
+
+        for (i, &wavelength) in self.wavelengths.iter().enumerate() {
+            let manifold = Manifold {
+                wavelength: Some(wavelength * shift),
+                ..*manifold
+            };
+            let (scattering, pdf) = material.spectral_pdf(&manifold, ray);
+            self.scattering[i] *= scattering / hero_scattering;
+            self.pdf[i] *= pdf / hero_pdf;
+        }
+    }
+
+    /// Color of light that has come along the path so far.
+    fn weight(&self) -> LinearRgb {
+        let pdf: f32 = self.pdf.iter().sum();
+        self.wavelengths
+            .iter()
+            .zip(&self.scattering)
+            .map(|(&wavelength, &scattering)| {
+                color::wavelength_response(wavelength) * (scattering / pdf)
+            })
+            .fold(LinearRgb::BLACK, |total, color| total + color)
+    }
+}
+
 #[derive(Debug)]
-pub struct ChunkState {
+pub struct ChunkState<'a> {
     config: ChunkConfig,
+    spacetime: &'a Spacetime,
+    bvh: &'a Bvh,
+    /// Objects near the current geodesic.
+    cursor: SegmentCursor,
+    stats: Stats,
+    /// Root material of the region behind the last wormhole the path went
+    /// through, if it differs from the scene's.
+    environment: Option<DataRef>,
+    /// Wavelengths the path carries, once it has met a dispersive surface.
+    wavelengths: Option<Wavelengths>,
     pub rng: SmallRng,
 }
 
-impl ChunkState {
-    fn new(config: ChunkConfig) -> Self {
+impl<'a> ChunkState<'a> {
+    fn new(config: ChunkConfig, spacetime: &'a Spacetime, bvh: &'a Bvh) -> Self {
         let rng = SmallRng::from_entropy();
-        Self { config, rng }
+        Self {
+            config,
+            spacetime,
+            bvh,
+            cursor: SegmentCursor::new(),
+            stats: Stats::default(),
+            environment: None,
+            wavelengths: None,
+            rng,
+        }
     }
 
-    fn render_samples<'a>(&mut self, scene: &Scene, camera: ObjectRef, chunk: Chunk<'a>) {
+    fn render_samples(&mut self, scene: &Scene, camera: ObjectRef, chunk: Chunk) {
         let camera_obj = scene.get_object(camera);
         let camera = camera_obj.as_camera().expect("expected a camera object");
 
@@ -276,8 +537,8 @@ impl ChunkState {
 
                 for _ in 0..self.config.samples {
                     for (u_sub, v_sub) in self.config.subsample {
-                        let u_offset = u_sub * pixel_width + self.rng.sample(&scatter_u);
-                        let v_offset = v_sub * pixel_height + self.rng.sample(&scatter_v);
+                        let u_offset = u_sub * pixel_width + self.rng.sample(scatter_u);
+                        let v_offset = v_sub * pixel_height + self.rng.sample(scatter_v);
 
                         let u = u + u_offset;
                         let v = v + v_offset;
@@ -301,7 +562,7 @@ impl ChunkState {
                             ray = camera_obj.transform() * ray;
                         }
 
-                        let sample = self.sample(&ray, scene, 0);
+                        let sample = self.sample(&ray, scene, 0, 1.0);
 
                         let depth = (sample.depth - self.config.clip_min)
                             / (self.config.clip_max - self.config.clip_min);
@@ -319,28 +580,118 @@ impl ChunkState {
         }
     }
 
-    fn sample(&mut self, ray: &Ray, scene: &Scene, bounce: usize) -> ColorData {
+    fn sample(&mut self, ray: &Ray, scene: &Scene, bounce: usize, shift: f32) -> ColorData {
         if bounce > self.config.max_bounces {
             return Default::default();
         }
 
-        if let Some(manifold) = self.try_hit(ray, scene) {
-            if manifold.face.is_surface() {
-                match manifold.mat_ref {
-                    Some(mat_ref) => self.sample_surface(scene, &manifold, mat_ref, bounce),
-                    None => Default::default(),
-                }
-            } else {
-                match manifold.vol_ref {
-                    Some(vol_ref) => self.sample_volume(scene, &manifold, vol_ref, bounce, 0),
-                    None => Default::default(),
-                }
+        let integrator = if self.spacetime.is_flat() {
+            Integrator::Straight
+        } else {
+            self.config.integrator
+        };
+
+        let trace = match integrator {
+            Integrator::Straight => self.try_hit_straight(ray, scene),
+            Integrator::Geodesic => self.try_hit_geodesic(ray, scene),
+            Integrator::ThinLens => self.try_hit_thin_lens(ray, scene),
+        };
+
+        match trace {
+            Trace::Hit(mut manifold) => {
+                manifold.shift *= shift;
+                manifold.wavelength = self.hero_wavelength(manifold.shift);
+                self.sample_hit(scene, &manifold, bounce)
+            }
+            Trace::Escaped(ray, escaped_shift) => {
+                self.sample_root(&ray, scene, shift * escaped_shift)
+            }
+            Trace::Absorbed => Default::default(),
+        }
+    }
+
+    fn sample_hit(&mut self, scene: &Scene, manifold: &Manifold, bounce: usize) -> ColorData {
+        let wormhole = manifold
+            .object_ref
+            .and_then(|object_ref| scene.get_object(object_ref).as_wormhole());
+        if wormhole.is_some() {
+            return self.sample_wormhole(scene, manifold, bounce);
+        }
+
+        if manifold.face.is_surface() {
+            match manifold.mat_ref {
+                Some(mat_ref) => self.sample_surface(scene, manifold, mat_ref, bounce),
+                None => Default::default(),
             }
         } else {
-            self.sample_root(ray, scene)
+            match manifold.vol_ref {
+                Some(vol_ref) => self.sample_volume(scene, manifold, vol_ref, bounce, 0),
+                None => Default::default(),
+            }
         }
     }
 
+    /// Follows a ray that entered a wormhole mouth through the throat. Each
+    /// passage counts as a bounce, so facing mouths can't recurse forever.
+    fn sample_wormhole(&mut self, scene: &Scene, manifold: &Manifold, bounce: usize) -> ColorData {
+        let object = scene.get_object(manifold.object_ref.unwrap());
+        let wormhole = object.as_wormhole().unwrap();
+        let mut passage = wormhole.enter(
+            object.transform(),
+            scene,
+            manifold.position,
+            manifold.ray.direction,
+        );
+
+        // the region around the exit mouth may have its own environment,
+        // the one around this object is assumed to see the scene's
+        let environment = self.environment;
+        let environments = match passage.side() {
+            0 => [environment, wormhole.environment],
+            _ => [None, environment],
+        };
+        let mut travelled = manifold.t;
+
+        let color_data = 'passage: {
+            for _ in 0..self.config.max_geodesic_steps {
+                self.environment = environments[passage.side()];
+
+                if let Some(ray) = passage.exit() {
+                    break 'passage self.sample(&ray, scene, bounce + 1, manifold.shift);
+                }
+
+                let start = passage.position();
+                let side = passage.side();
+                let length = passage.step();
+
+                // the mouths are far apart in the embedding, so the step
+                // through the throat itself can't hit anything
+                if passage.side() == side {
+                    let end = passage.position();
+                    let segment = Ray::new(start, end - start);
+                    let clip = Clip {
+                        min: 0.0,
+                        max: end.distance(start),
+                    };
+                    if let Some(mut hit) = self.try_hit(&segment, &clip, scene) {
+                        hit.t += travelled;
+                        hit.shift = manifold.shift;
+                        hit.wavelength = manifold.wavelength;
+                        break 'passage self.sample_hit(scene, &hit, bounce + 1);
+                    }
+                }
+
+                travelled += length;
+            }
+
+            // trapped on the photon sphere at the throat
+            Default::default()
+        };
+
+        self.environment = environment;
+        color_data
+    }
+
     fn sample_volumetric(
         &mut self,
         ray: &Ray,
@@ -348,12 +699,16 @@ impl ChunkState {
         last_object: ObjectRef,
         bounce: usize,
         volume_bounce: usize,
+        shift: f32,
     ) -> ColorData {
         if volume_bounce > self.config.max_volume_bounces {
             return Default::default();
         }
 
-        if let Some(manifold) = self.try_hit_volume(ray, scene, last_object) {
+        if let Some(mut manifold) = self.try_hit_volume(ray, scene, last_object) {
+            // volumes are marched in short straight steps, so the shift is carried over unchanged
+            manifold.shift = shift;
+            manifold.wavelength = self.hero_wavelength(shift);
             if manifold.face.is_surface() {
                 match manifold.mat_ref {
                     Some(mat_ref) => self.sample_surface(scene, &manifold, mat_ref, bounce),
@@ -368,10 +723,30 @@ impl ChunkState {
                 }
             }
         } else {
-            self.sample_root(ray, scene)
+            self.sample_root(ray, scene, shift)
         }
     }
 
+    /// Wavelength of the hero at a point whose light is shifted by `shift`
+    /// on its way to the camera.
+    fn hero_wavelength(&self, shift: f32) -> Option<f32> {
+        self.wavelengths
+            .as_ref()
+            .map(|wavelengths| wavelengths.hero() * shift)
+    }
+
+    /// Color that light emitted at the current vertex of the path is seen
+    /// in, white until the path has met a dispersive surface.
+    fn spectral_weight(&self) -> LinearRgb {
+        self.wavelengths
+            .as_ref()
+            .map_or(LinearRgb::WHITE, Wavelengths::weight)
+    }
+
+    fn beaming(&self, shift: f32) -> f32 {
+        shift.powi(self.config.beaming.exponent())
+    }
+
     fn clip(&self) -> Clip {
         Clip {
             min: self.config.clip_min,
@@ -386,52 +761,302 @@ impl ChunkState {
         }
     }
 
-    fn try_hit<'a>(&mut self, ray: &Ray, scene: &'a Scene) -> Option<Manifold<'a>> {
-        let mut result = None;
+    fn try_hit<'b>(&mut self, ray: &Ray, clip: &Clip, scene: &'b Scene) -> Option<Manifold<'b>> {
+        self.bvh.hit(ray, clip, |object_ref, clip| {
+            scene.get_object(object_ref).hit(ray, clip, scene)
+        })
+    }
 
-        let mut clip = self.clip();
+    /// Hit-tests the segment of a curved path from `start` to `end`,
+    /// reusing the objects found near the previous segments.
+    fn try_hit_segment<'b>(
+        &mut self,
+        start: Vec3A,
+        end: Vec3A,
+        clip_min: f32,
+        scene: &'b Scene,
+    ) -> Option<Manifold<'b>> {
+        self.bvh
+            .hit_segment(&mut self.cursor, start, end, |object_ref, ray, clip| {
+                let clip = Clip {
+                    min: clip.min.max(clip_min),
+                    max: clip.max,
+                };
+                scene.get_object(object_ref).hit(ray, &clip, scene)
+            })
+    }
 
-        for object in scene.iter() {
-            if let Some(manifold) = object.hit(ray, &clip, scene) {
-                clip.max = manifold.t;
-                result = Some(manifold);
-            }
+    fn try_hit_straight<'b>(&mut self, ray: &Ray, scene: &'b Scene) -> Trace<'b> {
+        match self.try_hit(ray, &self.clip(), scene) {
+            Some(manifold) => Trace::Hit(manifold),
+            None => Trace::Escaped(*ray, 1.0),
+        }
+    }
+
+    /// Frequency shift between the start of a path segment at `observer`
+    /// and its end at `position`, where it hit `object_ref` or escaped.
+    fn segment_shift(
+        &self,
+        scene: &Scene,
+        observer: Vec3A,
+        position: Vec3A,
+        momentum: Vec3A,
+        energy: f32,
+        object_ref: Option<ObjectRef>,
+    ) -> f32 {
+        if !self.config.redshift {
+            return 1.0;
         }
 
-        result
+        let emitter = object_ref.map_or(Emitter::Static, |object_ref| {
+            scene.get_object(object_ref).emitter()
+        });
+        self.spacetime
+            .shift(observer, position, momentum, energy, emitter)
     }
 
-    fn try_hit_volume<'a>(
-        &mut self,
-        ray: &Ray,
-        scene: &'a Scene,
-        last_object: ObjectRef,
-    ) -> Option<Manifold<'a>> {
-        let mut result = None;
+    fn try_hit_thin_lens<'b>(&mut self, ray: &Ray, scene: &'b Scene) -> Trace<'b> {
+        let spacetime = self.spacetime;
+        let mut deflected = vec![false; spacetime.lenses().len()];
 
-        let mut clip = self.clip_volumetric();
+        let observer = ray.origin;
+        let energy = match spacetime.energy(ray.origin, ray.direction) {
+            Some(energy) => energy,
+            None => return Trace::Absorbed,
+        };
 
-        for (object_ref, object) in scene.pairs() {
-            let manifold = if object_ref == last_object {
-                object.hit_volumetric(ray, &clip, scene)
-            } else {
-                object.hit(ray, &clip, scene)
+        let mut ray = *ray;
+        let mut travelled = 0.0;
+        let mut clip_min = self.config.clip_min;
+
+        loop {
+            // every lens bends the ray once, in the order they are passed
+            let next = spacetime
+                .lenses()
+                .iter()
+                .enumerate()
+                .filter(|&(index, _)| !deflected[index])
+                .map(|(index, lens)| (index, lens, lens.closest_approach(&ray)))
+                .filter(|&(_, _, t)| t > clip_min)
+                .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+
+            let remaining = self.config.clip_max - travelled;
+            let next_screen = spacetime.next_screen(
+                &ray,
+                &Clip {
+                    min: clip_min,
+                    max: remaining,
+                },
+            );
+            let max = [next.map(|(_, _, t)| t), next_screen.map(|(_, t)| t)]
+                .into_iter()
+                .flatten()
+                .fold(remaining, f32::min);
+            let clip = Clip { min: clip_min, max };
+
+            if let Some(mut manifold) = self.try_hit(&ray, &clip, scene) {
+                manifold.t += travelled;
+                manifold.shift = self.segment_shift(
+                    scene,
+                    observer,
+                    manifold.position,
+                    ray.direction * energy,
+                    energy,
+                    manifold.object_ref,
+                );
+                return Trace::Hit(manifold);
+            }
+
+            match (next, next_screen) {
+                (_, Some((index, t))) if t <= max => {
+                    let position = ray.at(t);
+                    let screen = &spacetime.screens()[index];
+                    ray = Ray::new(position, screen.bend(position, ray.direction));
+                    travelled += t;
+                    // the ray starts on the screen, don't cross it again
+                    clip_min = self.config.clip_min;
+                }
+                (Some((index, lens, t)), _) if t < remaining => match lens.deflect(&ray) {
+                    Some(bent) => {
+                        deflected[index] = true;
+                        ray = bent;
+                        travelled += t;
+                        clip_min = 0.0;
+                    }
+                    None => return Trace::Absorbed,
+                },
+                _ => {
+                    let shift = self.segment_shift(
+                        scene,
+                        observer,
+                        ray.at(remaining),
+                        ray.direction * energy,
+                        energy,
+                        None,
+                    );
+                    return Trace::Escaped(ray, shift);
+                }
+            }
+        }
+    }
+
+    fn try_hit_geodesic<'b>(&mut self, ray: &Ray, scene: &'b Scene) -> Trace<'b> {
+        let spacetime = self.spacetime;
+        self.stats.geodesics += 1;
+
+        let mut geodesic = match Geodesic::new(spacetime, ray) {
+            Some(geodesic) => geodesic,
+            None => {
+                self.stats.absorbed += 1;
+                return Trace::Absorbed;
+            }
+        };
+
+        let observer = ray.origin;
+        let mut clip_min = self.config.clip_min;
+        let mut step = self.config.geodesic_step;
+        self.cursor.reset();
+
+        for _ in 0..self.config.max_geodesic_steps {
+            let direction = geodesic.direction(spacetime);
+            let remaining = self.config.clip_max - geodesic.length;
+
+            if remaining <= 0.0
+                || spacetime.is_escaping(geodesic.position, direction, self.config.geodesic_escape)
+            {
+                let ray = Ray::new(geodesic.position, direction);
+                let mut clip = Clip {
+                    min: clip_min,
+                    max: remaining.max(clip_min),
+                };
+                let screen = spacetime.next_screen(&ray, &clip);
+                if let Some((_, t)) = screen {
+                    clip.max = t;
+                }
+
+                if let Some(mut manifold) = self.try_hit(&ray, &clip, scene) {
+                    // depth is measured along the whole bent path
+                    manifold.t += geodesic.length;
+                    manifold.shift = self.segment_shift(
+                        scene,
+                        observer,
+                        manifold.position,
+                        geodesic.momentum,
+                        geodesic.energy,
+                        manifold.object_ref,
+                    );
+                    return Trace::Hit(manifold);
+                }
+
+                // screens can bend the ray back towards the lenses
+                if let Some((index, t)) = screen {
+                    geodesic.cross(&spacetime.screens()[index], ray.at(t), t);
+                    clip_min = self.config.clip_min;
+                    continue;
+                }
+
+                let shift = self.segment_shift(
+                    scene,
+                    observer,
+                    ray.at(clip.max),
+                    geodesic.momentum,
+                    geodesic.energy,
+                    None,
+                );
+                return Trace::Escaped(ray, shift);
+            }
+
+            let start = geodesic.position;
+            let length = geodesic.length;
+            let result = geodesic.step_adaptive(spacetime, step, &self.config.geodesic_control);
+            step = result.next;
+            self.stats.steps += 1;
+            self.stats.rejected_steps += result.rejected;
+
+            // nothing behind the horizon can be seen, and steps into the
+            // singularity don't have a finite end
+            if spacetime.is_inside_horizon(geodesic.position) {
+                self.stats.absorbed += 1;
+                return Trace::Absorbed;
+            }
+
+            let segment = Ray::new(start, geodesic.position - start);
+            let clip = Clip {
+                min: clip_min,
+                max: geodesic.position.distance(start),
             };
-            if let Some(manifold) = manifold {
-                clip.max = manifold.t;
-                result = Some(manifold);
+            let screen = spacetime.next_screen(&segment, &clip);
+            let end = screen.map_or(geodesic.position, |(_, t)| segment.at(t));
+
+            if let Some(mut manifold) = self.try_hit_segment(start, end, clip_min, scene) {
+                manifold.t += length;
+                manifold.shift = self.segment_shift(
+                    scene,
+                    observer,
+                    manifold.position,
+                    geodesic.momentum,
+                    geodesic.energy,
+                    manifold.object_ref,
+                );
+                return Trace::Hit(manifold);
+            }
+
+            if let Some((index, t)) = screen {
+                // screens are thin, so the rest of the step is thrown away
+                geodesic.length = length;
+                geodesic.cross(&spacetime.screens()[index], segment.at(t), t);
+                clip_min = self.config.clip_min;
+            } else {
+                clip_min = 0.0;
             }
         }
 
-        result
+        self.stats.exhausted += 1;
+
+        let ray = geodesic.ray(spacetime);
+        let shift = self.segment_shift(
+            scene,
+            observer,
+            ray.origin,
+            geodesic.momentum,
+            geodesic.energy,
+            None,
+        );
+        Trace::Escaped(ray, shift)
     }
 
-    fn sample_root(&mut self, ray: &Ray, scene: &Scene) -> ColorData {
-        let material = scene.root_material();
+    fn try_hit_volume<'b>(
+        &mut self,
+        ray: &Ray,
+        scene: &'b Scene,
+        last_object: ObjectRef,
+    ) -> Option<Manifold<'b>> {
+        self.bvh
+            .hit(ray, &self.clip_volumetric(), |object_ref, clip| {
+                let object = scene.get_object(object_ref);
+                if object_ref == last_object {
+                    object.hit_volumetric(ray, clip, scene)
+                } else {
+                    object.hit(ray, clip, scene)
+                }
+            })
+    }
+
+    fn sample_root(&mut self, ray: &Ray, scene: &Scene, shift: f32) -> ColorData {
+        let material = match self.environment {
+            Some(environment) => scene
+                .get_data(environment)
+                .as_material()
+                .expect("expected material data"),
+            None => scene.root_material(),
+        };
 
         let manifold = Manifold {
             position: ray.at(self.config.clip_max),
             normal: -ray.direction,
+            uv: spherical_uv(ray.direction),
+            tangent: Vec3A::ZERO,
+            bitangent: Vec3A::ZERO,
             bbox: (Vec3A::splat(f32::NEG_INFINITY), Vec3A::splat(f32::INFINITY)),
             face: Face::Volume,
             t: self.config.clip_max,
@@ -439,15 +1064,18 @@ impl ChunkState {
             object_ref: None,
             mat_ref: None,
             vol_ref: None,
+            shift,
+            wavelength: self.hero_wavelength(shift),
             scene,
         };
 
         let clip = self.clip();
-        let emitted = material.emitted(&mut self.rng, &manifold);
+        let emitted = material.emitted(&mut self.rng, &manifold) * self.beaming(shift);
         let data = material.shade(&mut self.rng, &manifold, &clip);
 
         let mut color_data = data.albedo.unwrap_or_default();
         color_data.color += emitted;
+        color_data.color *= self.spectral_weight();
         color_data
     }
 
@@ -462,14 +1090,33 @@ impl ChunkState {
             .get_data(mat_ref)
             .as_material()
             .expect("expected material data");
+        if self.config.dispersion && self.wavelengths.is_none() && material.is_dispersive() {
+            // wavelengths are seen by the camera, and shifted on the way
+            self.wavelengths = Some(Wavelengths::new(&mut self.rng, self.config.wavelengths));
+            let manifold = Manifold {
+                wavelength: self.hero_wavelength(manifold.shift),
+                ..*manifold
+            };
+            let color_data = self.sample_surface(scene, &manifold, mat_ref, bounce);
+            self.wavelengths = None;
+            return color_data;
+        }
 
         let clip = self.clip();
-        let emitted = material.emitted(&mut self.rng, manifold);
+        let emitted = material.emitted(&mut self.rng, manifold)
+            * self.beaming(manifold.shift)
+            * self.spectral_weight();
         let data = material.shade(&mut self.rng, manifold, &clip);
         let mut attenuation = data.albedo;
 
         if let Some(ray) = data.scatter {
-            let reflected = self.sample(&ray, scene, bounce + 1);
+            if material.is_dispersive() {
+                if let Some(wavelengths) = &mut self.wavelengths {
+                    wavelengths.scatter(material, manifold, &ray);
+                }
+            }
+
+            let reflected = self.sample(&ray, scene, bounce + 1, manifold.shift);
             if let Some(attenuation) = &mut attenuation {
                 attenuation.color *= material.pdf(manifold, &ray);
                 attenuation.color *= reflected.color / data.pdf;
@@ -502,7 +1149,7 @@ impl ChunkState {
 
         if let Some(ray) = ray {
             let reflected = if manifold.face == Face::VolumeBack {
-                self.sample(&ray, scene, bounce + 1)
+                self.sample(&ray, scene, bounce + 1, manifold.shift)
             } else {
                 self.sample_volumetric(
                     &ray,
@@ -510,6 +1157,7 @@ impl ChunkState {
                     manifold.object_ref.unwrap(),
                     bounce,
                     volume_bounce + 1,
+                    manifold.shift,
                 )
             };
             if let Some(attenuation) = &mut attenuation {
@@ -522,3 +1170,379 @@ impl ChunkState {
         attenuation.unwrap_or_default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use glam::{Vec2, Vec3A};
+    use rand::rngs::SmallRng;
+
+    use super::*;
+    use crate::color::LinearRgb;
+    use crate::scene::{
+        Camera, Data, Dispersion, Instance, MassivePoint, Material, Mesh, Object, ObjectFlags,
+        Plane, Sphere, TriangleMesh,
+    };
+
+    const SIZE: usize = 96;
+
+    fn config() -> Config {
+        Config {
+            clip_max: 2000.0,
+            geodesic_max_step: 20.0,
+            geodesic_escape: 1e-4,
+            ..Default::default()
+        }
+    }
+
+    /// Renders `scene` through a square camera at the origin looking down
+    /// -z, returning the buffer and the angular field of view.
+    fn render(scene: &mut Scene, fov: f32, config: Config) -> (Buffer, f32) {
+        let camera = Camera {
+            focal_length: 0.5 * Camera::default().sensor_size / (0.5 * fov).tan(),
+            aspect_ratio: 1.0,
+            ..Default::default()
+        };
+        let camera = scene.add_object(Object::new(camera));
+
+        let tracer = Tracer::with_config(config);
+        let mut buffer = Buffer::new(SIZE, SIZE, ColorSpace::Linear);
+        tracer.render(scene, camera, &RenderConfig::with_samples(1), &mut buffer);
+
+        (buffer, fov)
+    }
+
+    /// Pixels with their brightness, the angle of their center to the
+    /// optical axis, and their solid angle.
+    fn pixels(buffer: &Buffer, fov: f32) -> impl Iterator<Item = (f32, f32, f32)> + '_ {
+        let pixel_width = buffer.pixel_width();
+        let pixel_height = buffer.pixel_height();
+        let pixel_angle = 0.5 * fov * pixel_width;
+
+        buffer.enumerate_pixels().map(move |(x, y, pixel)| {
+            let u = (x as f32 + 0.5) * pixel_width - 1.0;
+            let v = (y as f32 + 0.5) * pixel_height - 1.0;
+            let ray = Ray::with_frustum(fov, fov, u, v);
+            let angle = ray.direction.dot(Vec3A::NEG_Z).clamp(-1.0, 1.0).acos();
+            let pitch = 0.5 * fov * v;
+            let brightness = pixel.0[..3].iter().sum::<f32>() / buffer.samples() as f32;
+            (brightness, angle, pixel_angle * pixel_angle * pitch.cos())
+        })
+    }
+
+    #[test]
+    fn einstein_ring() {
+        let mass = 0.05;
+        let lens_distance = 400.0;
+        let source_distance = 800.0;
+
+        let mut scene = Scene::default();
+        let light = scene.add_data(Data::new(Material::emissive(LinearRgb::WHITE, 1.0)));
+        scene.add_object(
+            Object::new(MassivePoint::new(mass)).with_translation(Vec3A::new(
+                0.0,
+                0.0,
+                -lens_distance,
+            )),
+        );
+        scene.add_object(
+            Object::new(Sphere::new(light, 2.0)).with_translation(Vec3A::new(
+                0.0,
+                0.0,
+                -source_distance,
+            )),
+        );
+
+        let distance_ratio = (source_distance - lens_distance) / source_distance;
+        let einstein_angle = (4.0 * mass * distance_ratio / lens_distance).sqrt();
+
+        // the ring is still this close to the lens that the second order
+        // term of the deflection, `15π/4 (M/b)²`, shifts it by about a percent
+        let ring_angle = (0..8).fold(einstein_angle, |angle, _| {
+            let b = lens_distance * angle;
+            let correction = 15.0 * f32::consts::PI / 4.0 * (mass / b).powi(2);
+            (einstein_angle.powi(2) + distance_ratio * correction * angle).sqrt()
+        });
+
+        let (buffer, fov) = render(&mut scene, 4.0 * einstein_angle, config());
+
+        // the edges of the ring are at `θ₁ θ₂ = θ_E²`, so the mean of `ln θ`
+        // weighted by `dθ / θ` doesn't depend on the size of the source
+        let (weight, weighted) =
+            pixels(&buffer, fov).fold((0.0, 0.0), |(weight, weighted), (brightness, angle, _)| {
+                let brightness = brightness / (angle * angle);
+                (weight + brightness, weighted + brightness * angle.ln())
+            });
+        assert!(weight > 0.0, "the source is not visible");
+
+        let measured = (weighted / weight).exp();
+        assert!(
+            (measured / ring_angle - 1.0).abs() < 0.01,
+            "ring at {measured}, expected {ring_angle}"
+        );
+    }
+
+    #[test]
+    fn schwarzschild_shadow() {
+        let mass = 1.0;
+        let distance = 30.0;
+
+        let mut scene = Scene::default();
+        let sky = scene.add_data(Data::new(Material::flat(LinearRgb::WHITE)));
+        scene.set_root_material(sky);
+        scene.add_object(
+            Object::new(MassivePoint::new(mass)).with_translation(Vec3A::new(0.0, 0.0, -distance)),
+        );
+
+        // photons at the critical impact parameter 3√3 M, as seen by a
+        // static observer at `distance`
+        let critical = 3.0 * 3.0f32.sqrt() * mass;
+        let shadow_angle = (critical / distance * (1.0 - 2.0 * mass / distance).sqrt()).asin();
+        let (buffer, fov) = render(&mut scene, 3.0 * shadow_angle, config());
+
+        let sky_brightness = pixels(&buffer, fov)
+            .map(|(brightness, _, _)| brightness)
+            .fold(0.0, f32::max);
+        let solid_angle: f32 = pixels(&buffer, fov)
+            .map(|(brightness, _, area)| (1.0 - brightness / sky_brightness).max(0.0) * area)
+            .sum();
+
+        let measured = (solid_angle / f32::consts::PI).sqrt();
+        assert!(
+            (measured / shadow_angle - 1.0).abs() < 0.015,
+            "shadow of {measured}, expected {shadow_angle}"
+        );
+    }
+
+    #[test]
+    fn gravitational_redshift() {
+        let mass = 1.0;
+        let distance = 30.0;
+        let radius = 6.0;
+
+        // a green ball around the hole, which all shines from the same depth
+        // in its field
+        let mut scene = Scene::default();
+        let green = LinearRgb::new(0.0, 1.0, 0.0);
+        let light = scene.add_data(Data::new(Material::emissive(green, 1.0)));
+        let center = Vec3A::new(0.0, 0.0, -distance);
+        scene.add_object(Object::new(MassivePoint::new(mass)).with_translation(center));
+        scene.add_object(Object::new(Sphere::new(light, radius)).with_translation(center));
+
+        let mean = |scene: &mut Scene, config: Config| {
+            let (buffer, _) = render(scene, 0.05, config);
+            let sum = buffer.pixels().fold(LinearRgb::BLACK, |sum, pixel| {
+                sum + LinearRgb::new(pixel.0[0], pixel.0[1], pixel.0[2])
+            });
+            sum / (buffer.samples() * SIZE * SIZE) as f32
+        };
+        let assert_color = |color: LinearRgb, expected: LinearRgb| {
+            let error = <[f32; 3]>::from(color - expected)
+                .into_iter()
+                .fold(0.0, |error: f32, channel| error.max(channel.abs()));
+            assert!(error < 2e-3, "{color:?} != {expected:?}");
+        };
+
+        // the frequency climbing out to a static observer
+        let shift = ((1.0 - 2.0 * mass / radius) / (1.0 - 2.0 * mass / distance)).sqrt();
+        let shifted = color::shifted(green, shift);
+        assert!(shifted.r > 0.0, "green light turns yellow");
+
+        for (beaming, exponent) in [
+            (Beaming::None, 0),
+            (Beaming::Specific, 3),
+            (Beaming::Bolometric, 4),
+        ] {
+            let color = mean(
+                &mut scene,
+                Config {
+                    beaming,
+                    ..config()
+                },
+            );
+            assert_color(color, shifted * shift.powi(exponent));
+        }
+
+        let config = Config {
+            redshift: false,
+            ..config()
+        };
+        assert_color(mean(&mut scene, config), green);
+    }
+
+    #[test]
+    fn instanced_light() {
+        // the only light is seen through an instance, so there is none to
+        // sample directly
+        let mut scene = Scene::default();
+        let floor = scene.add_data(Data::new(Material::diffuse(LinearRgb::WHITE, 1.0)));
+        let light = scene.add_data(Data::new(Material::emissive(LinearRgb::WHITE, 1.0)));
+        scene.add_object(Object::new(Plane::new(floor)).with_translation(Vec3A::NEG_Y));
+        let prototype =
+            scene.add_object(Object::new(Sphere::new(light, 1.0)).with_flags(ObjectFlags::LIGHT));
+        scene.add_object(
+            Object::new(Instance::new(prototype)).with_translation(Vec3A::new(0.0, 1.0, -5.0)),
+        );
+
+        // the floor in the bottom rows is lit by it
+        let (buffer, _) = render(&mut scene, 1.0, config());
+        let lit = buffer
+            .enumerate_pixels()
+            .filter(|(_, y, pixel)| *y as usize >= 3 * SIZE / 4 && pixel.0[0] > 0.0)
+            .count();
+        assert!(lit > 0);
+    }
+
+    #[test]
+    fn hero_wavelengths() {
+        // light refracted by flint glass past a plane that red light gets
+        // over more easily than blue, seen through the hero and its
+        // companions or one wavelength at a time
+        let scene = Scene::default();
+        let manifold = Manifold {
+            position: Vec3A::ZERO,
+            normal: Vec3A::Y,
+            uv: Vec2::ZERO,
+            tangent: Vec3A::ZERO,
+            bitangent: Vec3A::ZERO,
+            bbox: (Vec3A::splat(-1.0), Vec3A::splat(1.0)),
+            face: Face::Front,
+            t: 1.0,
+            ray: Ray::new(Vec3A::new(-1.0, 1.0, 0.0), Vec3A::new(1.0, -1.0, 0.0)),
+            object_ref: None,
+            mat_ref: None,
+            vol_ref: None,
+            shift: 1.0,
+            wavelength: None,
+            scene: &scene,
+        };
+        let clip = Clip { min: 0.0, max: 1.0 };
+        let mut rng = SmallRng::seed_from_u64(1);
+
+        for roughness in [0.0, 0.2] {
+            let glass =
+                Material::dispersive_glass(LinearRgb::WHITE, roughness, Dispersion::DENSE_FLINT);
+            let sample = |rng: &mut SmallRng, wavelength| {
+                let manifold = Manifold {
+                    wavelength: Some(wavelength),
+                    ..manifold
+                };
+                let data = glass.shade(rng, &manifold, &clip);
+                let ray = data.scatter?;
+                let passed = f32::from(ray.direction.x > 0.394 && ray.direction.y < 0.0);
+                let weight = glass.pdf(&manifold, &ray) / data.pdf * passed;
+                Some((manifold, ray, weight))
+            };
+
+            let count = 100_000;
+            let mut hero = LinearRgb::BLACK;
+            let mut single = LinearRgb::BLACK;
+            for _ in 0..count {
+                let mut wavelengths = Wavelengths::new(&mut rng, 4);
+                if let Some((manifold, ray, weight)) = sample(&mut rng, wavelengths.hero()) {
+                    wavelengths.scatter(&glass, &manifold, &ray);
+                    hero += wavelengths.weight() * weight;
+                }
+
+                let wavelength = rng.gen_range(WAVELENGTH_MIN..WAVELENGTH_MAX);
+                if let Some((_, _, weight)) = sample(&mut rng, wavelength) {
+                    single += color::wavelength_response(wavelength) * weight;
+                }
+            }
+
+            // blue light hardly gets past at all
+            let hero = <[f32; 3]>::from(hero / count as f32);
+            let single = <[f32; 3]>::from(single / count as f32);
+            for (hero, single) in hero.into_iter().zip(single) {
+                assert!(
+                    (hero - single).abs() < 0.02 * single + 0.005,
+                    "{roughness}: {hero} != {single}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn prism() {
+        // a thin prism of dense flint with its base towards +x, seen through
+        // which a small white light is bent away from its apex
+        let angle: f32 = 0.2;
+        let half_width = 3.0 * (0.5 * angle).tan();
+        let section = [
+            Vec3A::new(-3.0, 0.0, -3.0),
+            Vec3A::new(3.0, 0.0, -3.0 + 2.0 * half_width),
+            Vec3A::new(3.0, 0.0, -3.0 - 2.0 * half_width),
+        ];
+        let positions: Vec<Vec3A> = [-5.0, 5.0]
+            .into_iter()
+            .flat_map(|y| section.map(|corner| corner + Vec3A::Y * y))
+            .collect();
+        let center = positions.iter().sum::<Vec3A>() / 6.0;
+        let indices = [
+            [0, 1, 2],
+            [3, 4, 5],
+            [0, 1, 4],
+            [0, 4, 3],
+            [1, 2, 5],
+            [1, 5, 4],
+            [2, 0, 3],
+            [2, 3, 5],
+        ]
+        .map(|[a, b, c]: [u32; 3]| {
+            let [pa, pb, pc] = [a, b, c].map(|i| positions[i as usize]);
+            let outward = (pb - pa).cross(pc - pa).dot(pa + pb + pc - 3.0 * center) > 0.0;
+            if outward {
+                [a, b, c]
+            } else {
+                [a, c, b]
+            }
+        });
+        let triangles = TriangleMesh::new(positions, Vec::new(), Vec::new(), indices.to_vec());
+
+        let mut scene = Scene::default();
+        let glass = scene.add_data(Data::new(Material::dispersive_glass(
+            LinearRgb::WHITE,
+            0.0,
+            Dispersion::DENSE_FLINT,
+        )));
+        let light = scene.add_data(Data::new(Material::emissive(LinearRgb::WHITE, 1.0)));
+        let mesh = scene.add_data(Data::new(triangles));
+        let triangles = scene.get_data(mesh).as_mesh().unwrap();
+        scene.add_object(Object::new(Mesh::new(mesh, triangles, glass)));
+
+        // where the reference wavelength takes the light, at about the
+        // smallest deviation
+        let deviation = 2.0 * ((0.5 * angle).sin() * 1.7847).asin() - angle;
+        let direction = Vec3A::new(deviation.sin(), 0.0, -deviation.cos());
+        scene.add_object(Object::new(Sphere::new(light, 0.2)).with_translation(direction * 100.0));
+
+        // horizontal centers of the red and blue light
+        let centers = |scene: &mut Scene, config: Config| {
+            let (buffer, _) = render(scene, 0.1, config);
+            let (red, blue) = buffer.enumerate_pixels().fold(
+                (Vec2::ZERO, Vec2::ZERO),
+                |(red, blue), (x, _, pixel)| {
+                    let x = x as f32;
+                    (
+                        red + Vec2::new(pixel.0[0] * x, pixel.0[0]),
+                        blue + Vec2::new(pixel.0[2] * x, pixel.0[2]),
+                    )
+                },
+            );
+            assert!(red.y > 0.0 && blue.y > 0.0, "the light is out of view");
+            (red.x / red.y, blue.x / blue.y)
+        };
+
+        // blue is bent further, so it is seen coming from closer to the apex
+        let (red, blue) = centers(&mut scene, config());
+        assert!(red - blue > 4.0, "red at {red}, blue at {blue}");
+
+        let config = Config {
+            dispersion: false,
+            ..config()
+        };
+        let (red, blue) = centers(&mut scene, config);
+        assert!((red - blue).abs() < 0.1, "red at {red}, blue at {blue}");
+    }
+}
@@ -0,0 +1,217 @@
+use std::f32;
+
+/// Number of intervals used to integrate projected masses.
+const PROJECTION_STEPS: usize = 64;
+
+/// Density profile of a spherical mass distribution in geometrized units
+/// (`G = c = 1`).
+///
+/// Both profiles have a diverging total mass, so they are cut off at
+/// `truncation_radius`, outside of which they act like a point mass.
+#[derive(Debug, Clone, Copy)]
+pub enum MassProfile {
+    /// Singular isothermal sphere, `ρ = σ² / 2πr²`.
+    Isothermal {
+        velocity_dispersion: f32,
+        truncation_radius: f32,
+    },
+    /// Navarro-Frenk-White halo, `ρ = ρ_s / (x (1 + x)²)` with `x = r / r_s`.
+    Nfw {
+        density: f32,
+        scale_radius: f32,
+        truncation_radius: f32,
+    },
+}
+
+impl MassProfile {
+    pub fn truncation_radius(&self) -> f32 {
+        match *self {
+            Self::Isothermal {
+                truncation_radius, ..
+            }
+            | Self::Nfw {
+                truncation_radius, ..
+            } => truncation_radius,
+        }
+    }
+
+    pub fn density(&self, radius: f32) -> f32 {
+        if radius > self.truncation_radius() {
+            return 0.0;
+        }
+
+        match *self {
+            Self::Isothermal {
+                velocity_dispersion,
+                ..
+            } => velocity_dispersion.powi(2) / (f32::consts::TAU * radius * radius),
+            Self::Nfw {
+                density,
+                scale_radius,
+                ..
+            } => {
+                let x = radius / scale_radius;
+                density / (x * (1.0 + x) * (1.0 + x))
+            }
+        }
+    }
+
+    /// Mass inside a sphere of `radius`.
+    pub fn enclosed_mass(&self, radius: f32) -> f32 {
+        let radius = radius.min(self.truncation_radius());
+        match *self {
+            Self::Isothermal {
+                velocity_dispersion,
+                ..
+            } => 2.0 * velocity_dispersion.powi(2) * radius,
+            Self::Nfw {
+                density,
+                scale_radius,
+                ..
+            } => {
+                let x = radius / scale_radius;
+                let scale = 2.0 * f32::consts::TAU * density * scale_radius.powi(3);
+                scale * ((1.0 + x).ln() - x / (1.0 + x))
+            }
+        }
+    }
+
+    pub fn mass(&self) -> f32 {
+        self.enclosed_mass(self.truncation_radius())
+    }
+
+    /// Newtonian potential, zero at infinity.
+    pub fn potential(&self, radius: f32) -> f32 {
+        let truncation = self.truncation_radius();
+        if radius >= truncation {
+            return -self.mass() / radius;
+        }
+
+        // `Φ(r) = -m(r) / r - ∫ 4π r' ρ(r') dr'` from `r` to the truncation
+        let outer = match *self {
+            Self::Isothermal {
+                velocity_dispersion,
+                ..
+            } => 2.0 * velocity_dispersion.powi(2) * (truncation / radius).ln(),
+            Self::Nfw {
+                density,
+                scale_radius,
+                ..
+            } => {
+                let x = radius / scale_radius;
+                let xt = truncation / scale_radius;
+                let scale = 2.0 * f32::consts::TAU * density * scale_radius.powi(2);
+                scale * ((1.0 + x).recip() - (1.0 + xt).recip())
+            }
+        };
+        let radius = radius.max(f32::EPSILON);
+        -self.enclosed_mass(radius) / radius - outer
+    }
+
+    /// Mass inside a cylinder of radius `impact` through the center, which
+    /// decides the thin lens deflection `4 M(<b) / b`.
+    pub fn projected_mass(&self, impact: f32) -> f32 {
+        let truncation = self.truncation_radius();
+        if impact <= 0.0 {
+            return 0.0;
+        } else if impact >= truncation {
+            return self.mass();
+        }
+
+        // add the parts of the shells outside of `b` that lie within the
+        // cylinder, `∫ 4π r² ρ (1 - √(1 - b²/r²)) dr`, written in
+        // `r = b cosh u` to get rid of the singularity at `r = b` and to
+        // spread the steps out logarithmically
+        let length = (truncation / impact).acosh();
+        let h = length / PROJECTION_STEPS as f32;
+        let integrand = |u: f32| {
+            let radius = (impact * u.cosh()).min(truncation);
+            let shell = 2.0 * f32::consts::TAU * self.density(radius) * impact.powi(3);
+            shell * u.sinh() / (1.0 + u.tanh())
+        };
+        let outside = (0..=PROJECTION_STEPS)
+            .map(|i| {
+                let weight = match i {
+                    0 => 1.0,
+                    i if i == PROJECTION_STEPS => 1.0,
+                    i if i % 2 == 1 => 4.0,
+                    _ => 2.0,
+                };
+                weight * integrand(i as f32 * h)
+            })
+            .sum::<f32>()
+            * h
+            / 3.0;
+
+        self.enclosed_mass(impact) + outside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isothermal_projected_mass() {
+        let (sigma, truncation) = (0.05, 100.0);
+        let profile = MassProfile::Isothermal {
+            velocity_dispersion: sigma,
+            truncation_radius: truncation,
+        };
+
+        for impact in [0.5, 5.0, 50.0, 99.0] {
+            let cut = (truncation * truncation - impact * impact).sqrt();
+            let expected =
+                2.0 * sigma * sigma * (truncation - cut + impact * (impact / truncation).acos());
+            let mass = profile.projected_mass(impact);
+            assert!(
+                (mass - expected).abs() < 1e-3 * expected,
+                "{impact}: {mass} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn nfw() {
+        let (density, scale, truncation) = (1e-3, 2.0, 2000.0);
+        let profile = MassProfile::Nfw {
+            density,
+            scale_radius: scale,
+            truncation_radius: truncation,
+        };
+        let mass = 2.0 * f32::consts::TAU * density * scale.powi(3);
+
+        // inside the truncation the potential is the untruncated one, raised
+        // by the missing outer shells
+        let offset = mass / (scale + truncation);
+        for radius in [0.5, 4.0, 30.0] {
+            let x: f32 = radius / scale;
+            let expected = -mass * (1.0 + x).ln() / radius + offset;
+            let potential = profile.potential(radius);
+            assert!(
+                (potential - expected).abs() < 2e-3 * expected.abs(),
+                "{radius}: {potential} != {expected}"
+            );
+        }
+
+        for impact in [0.5, 4.0, 30.0] {
+            let x: f32 = impact / scale;
+            let g = if x < 1.0 {
+                (1.0 / x).acosh() / (1.0 - x * x).sqrt()
+            } else {
+                (1.0 / x).acos() / (x * x - 1.0).sqrt()
+            };
+            let expected = mass * ((0.5 * x).ln() + g);
+            // the cylinder misses little mass beyond the truncation
+            let projected = profile.projected_mass(impact);
+            assert!(
+                (projected - expected).abs() < 2e-3 * expected,
+                "{impact}: {projected} != {expected}"
+            );
+        }
+
+        // outside it acts like a point mass
+        let outside = profile.potential(2.0 * truncation);
+        assert!((outside + profile.mass() / (2.0 * truncation)).abs() < 1e-6);
+    }
+}
@@ -1,6 +1,6 @@
 use std::ops::Mul;
 
-use glam::{Affine3A, Quat, Vec3A};
+use glam::{Affine3A, Quat, Vec2, Vec3A};
 
 use crate::color::LinearRgb;
 use crate::scene::{DataRef, ObjectRef, Scene};
@@ -30,12 +30,30 @@ impl Face {
     pub fn is_volume(&self) -> bool {
         matches!(self, Self::Volume | Self::VolumeFront | Self::VolumeBack)
     }
+
+    /// The same kind of face, seen from the other side.
+    pub fn flip(self) -> Self {
+        match self {
+            Self::Front => Self::Back,
+            Self::Back => Self::Front,
+            Self::Volume => Self::Volume,
+            Self::VolumeFront => Self::VolumeBack,
+            Self::VolumeBack => Self::VolumeFront,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Manifold<'a> {
     pub position: Vec3A,
     pub normal: Vec3A,
+    /// Texture coordinates of the surface at `position`.
+    pub uv: Vec2,
+    /// Directions along the surface in which u and v grow, which normal
+    /// maps are laid out along. Both are zero where the surface has no
+    /// such directions.
+    pub tangent: Vec3A,
+    pub bitangent: Vec3A,
     pub bbox: (Vec3A, Vec3A),
     pub face: Face,
     pub t: f32,
@@ -43,6 +61,11 @@ pub struct Manifold<'a> {
     pub object_ref: Option<ObjectRef>,
     pub mat_ref: Option<DataRef>,
     pub vol_ref: Option<DataRef>,
+    /// Ratio of the observed to the emitted frequency of light leaving this point.
+    pub shift: f32,
+    /// Wavelength in nanometres of the light leaving this point, for paths
+    /// that carry their hero wavelength from a dispersive surface on.
+    pub wavelength: Option<f32>,
     pub scene: &'a Scene,
 }
 
@@ -149,13 +172,13 @@ impl<'a> Mul<&'a Ray> for Affine3A {
     impl_mul_fn!(&'a Ray);
 }
 
-impl<'a> Mul<Ray> for &'a Affine3A {
+impl Mul<Ray> for &Affine3A {
     type Output = Ray;
 
     impl_mul_fn!(Ray);
 }
 
-impl<'a, 'b> Mul<&'a Ray> for &'b Affine3A {
+impl<'a> Mul<&'a Ray> for &Affine3A {
     type Output = Ray;
 
     impl_mul_fn!(&'a Ray);
@@ -0,0 +1,110 @@
+use glam::{Quat, Vec2, Vec3A};
+
+use super::Ray;
+use crate::scene::DeflectionMap;
+
+/// What bends the rays crossing a `Screen`.
+#[derive(Debug, Clone)]
+pub enum ScreenKind {
+    /// A disk of uniform surface density, `α = 4 M(<ρ) / ρ`.
+    UniformDisk { mass: f32, radius: f32 },
+    /// Tabulated deflection angles covering `extent` around the center,
+    /// rays crossing outside of it are not bent.
+    Map { map: DeflectionMap, extent: Vec2 },
+}
+
+/// A thin lens plane, the local xz plane of its `rotation` through `center`.
+///
+/// Rays are bent once where they cross the plane, which is a good
+/// approximation as long as the mass is thin compared to the distances
+/// to the observer and the source.
+#[derive(Debug, Clone)]
+pub struct Screen {
+    center: Vec3A,
+    rotation: Quat,
+    kind: ScreenKind,
+}
+
+impl Screen {
+    pub fn new(center: Vec3A, rotation: Quat, kind: ScreenKind) -> Self {
+        Self {
+            center,
+            rotation,
+            kind,
+        }
+    }
+
+    pub fn uniform_disk(center: Vec3A, rotation: Quat, mass: f32, radius: f32) -> Self {
+        Self::new(center, rotation, ScreenKind::UniformDisk { mass, radius })
+    }
+
+    pub fn map(center: Vec3A, rotation: Quat, map: DeflectionMap, extent: Vec2) -> Self {
+        Self::new(center, rotation, ScreenKind::Map { map, extent })
+    }
+
+    pub fn center(&self) -> Vec3A {
+        self.center
+    }
+
+    pub fn normal(&self) -> Vec3A {
+        self.rotation * Vec3A::Y
+    }
+
+    /// Distance along `ray` to where it crosses the screen, if it does.
+    pub fn crossing(&self, ray: &Ray) -> Option<f32> {
+        let normal = self.normal();
+        let denom = ray.direction.dot(normal);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.center - ray.origin).dot(normal) / denom;
+        (t > 0.0).then_some(t)
+    }
+
+    /// Deflection angle at `position` on the screen, pointing the way rays
+    /// are bent.
+    pub fn deflection(&self, position: Vec3A) -> Vec3A {
+        let local = self.rotation.inverse() * (position - self.center);
+        let local = Vec3A::new(local.x, 0.0, local.z);
+
+        let deflection = match self.kind {
+            ScreenKind::UniformDisk { mass, radius } => {
+                let rho = local.length();
+                if rho <= 0.0 {
+                    return Vec3A::ZERO;
+                }
+                let enclosed = mass * (rho / radius).min(1.0).powi(2);
+                -local / rho * (4.0 * enclosed / rho)
+            }
+            ScreenKind::Map { ref map, extent } => {
+                let coord = Vec2::new(local.x, local.z) / extent + 0.5;
+                if coord.cmplt(Vec2::ZERO).any() || coord.cmpgt(Vec2::ONE).any() {
+                    return Vec3A::ZERO;
+                }
+                let angle = map.sample(coord);
+                Vec3A::new(angle.x, 0.0, angle.y)
+            }
+        };
+
+        self.rotation * deflection
+    }
+
+    /// Bends `direction` as it crosses the screen at `position`.
+    pub fn bend(&self, position: Vec3A, direction: Vec3A) -> Vec3A {
+        let deflection = self.deflection(position);
+        let across = deflection - direction * direction.dot(deflection);
+        let angle = across.length();
+        if angle <= 0.0 {
+            return direction;
+        }
+
+        direction * angle.cos() + across / angle * angle.sin()
+    }
+
+    /// Bends `ray` where it crosses the screen, if it does.
+    pub fn deflect(&self, ray: &Ray) -> Option<Ray> {
+        let position = ray.at(self.crossing(ray)?);
+        Some(Ray::new(position, self.bend(position, ray.direction)))
+    }
+}